@@ -0,0 +1,530 @@
+//! Command line interface around `ReplicationManagerBuilder`, replicating the
+//! collections of a masking policy file with their rules. Typed `Mask` models are
+//! only available through the library.
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::Level;
+use tuxedo::{
+    LeakReport, MemorySink, Policy, ReplicationManagerBuilder, RunPlan, RunReport, Sample,
+    ScanConfig, ScanConfigBuilder, ScanReport, TuxedoError, TuxedoResult, VerificationReport,
+    ViewOutcome,
+};
+
+/// The command succeeded
+const SUCCESS: u8 = 0;
+
+/// The report holds failures: errors, discrepancies, leaks or personal information
+/// left unmasked
+const FAILURES: u8 = 1;
+
+/// The command could not run, e.g. an invalid policy or an unreachable database.
+/// Invalid arguments exit with the same code.
+const ERROR: u8 = 2;
+
+/// Name of the database mapping built from the source and target flags
+const DEFAULT_DATABASE: &str = "default";
+
+/// Masks and copies MongoDB collections
+#[derive(Parser)]
+#[command(name = "tuxedo", version)]
+struct Cli {
+    #[command(flatten)]
+    connection: Connection,
+
+    /// Logs at info level, or debug level when repeated
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Connection {
+    #[arg(long, env = "TUXEDO_SOURCE_URI", global = true)]
+    source_uri: Option<String>,
+
+    #[arg(long, env = "TUXEDO_TARGET_URI", global = true)]
+    target_uri: Option<String>,
+
+    /// Source database, when the URI does not name one
+    #[arg(long, env = "TUXEDO_SOURCE_DB", global = true)]
+    source_db: Option<String>,
+
+    /// Target database, when the URI does not name one
+    #[arg(long, env = "TUXEDO_TARGET_DB", global = true)]
+    target_db: Option<String>,
+
+    /// Number of concurrent tasks, one per CPU by default
+    #[arg(long, env = "TUXEDO_THREADS", global = true)]
+    threads: Option<usize>,
+
+    /// Seconds a secondary may lag behind the primary to serve source reads, at
+    /// least 90. Source reads prefer secondaries unless the URI sets `readPreference`,
+    /// which can't be `primary` then.
+    #[arg(long, env = "TUXEDO_MAX_STALENESS", global = true)]
+    max_staleness: Option<u64>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lists what `run` would copy, without writing anything
+    Plan(PlanArgs),
+    /// Drops the target collections of the policy and copies them from the source
+    Run(RunArgs),
+    /// Compares the target written by an earlier run with the source
+    Verify(VerifyArgs),
+    /// Samples every source collection and reports personal information the policy
+    /// leaves unmasked
+    Scan(ScanArgs),
+    /// Samples every source collection and prints a policy skeleton or Rust models
+    /// of its fields
+    Inspect(InspectArgs),
+}
+
+#[derive(Args)]
+struct PolicyArgs {
+    /// Masking policy listing the collections to copy and their rules
+    #[arg(long, env = "TUXEDO_POLICY")]
+    policy: PathBuf,
+}
+
+#[derive(Args)]
+struct PlanArgs {
+    #[command(flatten)]
+    policy: PolicyArgs,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    policy: PolicyArgs,
+
+    /// Documents read per task
+    #[arg(long)]
+    batch_size: Option<u64>,
+
+    /// Documents written per insert
+    #[arg(long)]
+    write_batch_size: Option<u64>,
+
+    /// Copies the source views
+    #[arg(long)]
+    copy_views: bool,
+
+    /// Compares the source and target collections once copied
+    #[arg(long)]
+    verify: bool,
+
+    /// Scans the target for source values at the paths of the rules once copied
+    #[arg(long)]
+    detect_leaks: bool,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[command(flatten)]
+    policy: PolicyArgs,
+
+    /// Also scans the target for source values at the paths of the rules
+    #[arg(long)]
+    detect_leaks: bool,
+}
+
+#[derive(Args)]
+struct ScanArgs {
+    /// Masking policy whose rules should cover the personal information found
+    #[arg(long, env = "TUXEDO_POLICY")]
+    policy: Option<PathBuf>,
+
+    #[command(flatten)]
+    sample: SampleArgs,
+
+    /// Redacted example values printed per finding
+    #[arg(long, default_value_t = 3)]
+    examples: usize,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    #[command(flatten)]
+    sample: SampleArgs,
+
+    #[arg(long, value_enum, default_value_t = Format::Policy)]
+    format: Format,
+
+    /// Writes into a file instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct SampleArgs {
+    /// Documents sampled per collection
+    #[arg(long, default_value_t = 1000)]
+    sample_size: u64,
+
+    /// Names added to the dictionary of the name detector
+    #[arg(long = "name", value_delimiter = ',')]
+    names: Vec<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Masking policy with suggested rules, see `--policy`
+    Policy,
+    /// Rust structs with serde attributes and a `Mask` implementation
+    Rust,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_max_level(match cli.verbose {
+            0 => Level::WARN,
+            1 => Level::INFO,
+            _ => Level::DEBUG,
+        })
+        .with_writer(std::io::stderr)
+        .init();
+
+    match execute(cli).await {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(ERROR)
+        }
+    }
+}
+
+async fn execute(cli: Cli) -> TuxedoResult<u8> {
+    let connection = cli.connection;
+    match cli.command {
+        Command::Plan(args) => {
+            let manager = connection
+                .builder()
+                .clear_target(false)
+                .policy(load_policy(&args.policy.policy)?)
+                .build()
+                .await?;
+            Ok(print_plan(&manager.plan().await?))
+        }
+        Command::Run(args) => {
+            let mut builder = connection
+                .builder()
+                .copy_views(args.copy_views)
+                .verify(args.verify)
+                .detect_leaks(args.detect_leaks)
+                .policy(load_policy(&args.policy.policy)?);
+            if let Some(size) = args.batch_size {
+                builder = builder.batch_size(size);
+            }
+            if let Some(size) = args.write_batch_size {
+                builder = builder.write_batch_size(size);
+            }
+            let report = builder.build().await?.run().await?;
+            Ok(print_run_report(&report))
+        }
+        Command::Verify(args) => {
+            let manager = connection
+                .builder()
+                .clear_target(false)
+                .detect_leaks(args.detect_leaks)
+                .policy(load_policy(&args.policy.policy)?)
+                .build()
+                .await?;
+            Ok(print_run_report(&manager.verify_target().await?))
+        }
+        Command::Scan(args) => {
+            let mut builder = connection.source_builder();
+            if let Some(policy) = args.policy.as_deref() {
+                builder = builder.policy(load_policy(policy)?);
+            }
+            let config = args.sample.config().examples(args.examples).build();
+            let report = builder.build().await?.scan(config).await?;
+            Ok(print_scan_report(&report))
+        }
+        Command::Inspect(args) => {
+            let config = args.sample.config().build();
+            let report = connection
+                .source_builder()
+                .build()
+                .await?
+                .scan(config)
+                .await?;
+            let output = match args.format {
+                Format::Policy => report.policy_skeleton(),
+                Format::Rust => report.rust_structs(),
+            };
+            match args.output {
+                Some(path) => std::fs::write(path, output)?,
+                None => print!("{output}"),
+            }
+
+            let failed = report.collections.iter().any(|c| c.error.is_some());
+            Ok(if failed { FAILURES } else { SUCCESS })
+        }
+    }
+}
+
+impl Connection {
+    fn builder(&self) -> ReplicationManagerBuilder {
+        let mut builder = ReplicationManagerBuilder::new();
+        if let Some(uri) = self.source_uri.as_ref() {
+            builder = builder.source_uri(uri);
+        }
+        if let Some(uri) = self.target_uri.as_ref() {
+            builder = builder.target_uri(uri);
+        }
+        if let Some(db) = self.source_db.as_ref() {
+            builder = builder.source_db(db);
+        }
+        if let Some(db) = self.target_db.as_ref() {
+            builder = builder.target_db(db);
+        }
+        if let Some(threads) = self.threads {
+            builder = builder.thread_count(threads);
+        }
+        if let Some(seconds) = self.max_staleness {
+            builder = builder.source_max_staleness(Duration::from_secs(seconds));
+        }
+        builder
+    }
+
+    /// Only reads the source, the target is not needed
+    fn source_builder(&self) -> ReplicationManagerBuilder {
+        self.builder().clear_target(false).target(MemorySink::new())
+    }
+}
+
+impl SampleArgs {
+    fn config(&self) -> ScanConfigBuilder {
+        ScanConfig::builder()
+            .sample_size(self.sample_size)
+            .names(self.names.iter().cloned())
+    }
+}
+
+fn load_policy(path: &Path) -> TuxedoResult<Policy> {
+    let policy = Policy::load(path)?;
+    if policy.collections.is_empty() {
+        return Err(TuxedoError::ConfigError(format!(
+            "Masking policy `{}` has no collections",
+            path.display()
+        )));
+    }
+    Ok(policy)
+}
+
+/// Collection prefixed by its database mapping unless it is the default one
+fn label(database: &str, collection: &str) -> String {
+    if database == DEFAULT_DATABASE {
+        collection.to_string()
+    } else {
+        format!("{database}.{collection}")
+    }
+}
+
+fn print_plan(plan: &RunPlan) -> u8 {
+    if plan.subset {
+        println!("Documents are restricted to a subset selected during the run");
+    }
+
+    for collection in plan.collections.iter() {
+        let mut details = Vec::new();
+        if let Some(count) = collection.source_documents {
+            details.push(format!("{count} documents"));
+        }
+        match collection.sample {
+            Some(Sample::Count(count)) => details.push(format!("sample of {count}")),
+            Some(Sample::Percent(percent)) => details.push(format!("sample of {percent}%")),
+            None => {}
+        }
+        if collection.pipeline {
+            details.push("reshaped by a pipeline".to_string());
+        }
+        details.push(if collection.masked {
+            format!("masked at {} paths", collection.sensitive_paths.len())
+        } else {
+            "copied unmasked".to_string()
+        });
+        match collection.target_documents {
+            Some(count) if count > 0 => details.push(format!("drops {count} target documents")),
+            _ => {}
+        }
+        if let Some(error) = collection.error.as_ref() {
+            details.push(error.clone());
+        }
+
+        println!(
+            "{} -> {}: {}",
+            label(&collection.database, &collection.source_collection),
+            collection.target_collection,
+            details.join(", ")
+        );
+    }
+
+    let failed = plan.collections.iter().any(|c| c.error.is_some());
+    if failed {
+        FAILURES
+    } else {
+        SUCCESS
+    }
+}
+
+fn print_run_report(report: &RunReport) -> u8 {
+    for collection in report.collections.iter() {
+        println!(
+            "{} -> {}: {}/{} documents written, {} failed, {} indexes copied",
+            label(&collection.database, &collection.source_collection),
+            collection.target_collection,
+            collection.documents_written,
+            collection.documents_total,
+            collection.documents_failed,
+            collection.indexes_copied
+        );
+        for error in collection.errors.iter() {
+            println!("  error: {error}");
+        }
+        for failure in collection.index_failures.iter() {
+            println!("  index {}: {}", failure.name, failure.error);
+        }
+    }
+
+    for view in report.views.iter() {
+        match &view.outcome {
+            ViewOutcome::Created => {}
+            ViewOutcome::Skipped(reason) => {
+                println!(
+                    "view {}: skipped, {reason}",
+                    label(&view.database, &view.name)
+                )
+            }
+            ViewOutcome::Failed(error) => {
+                println!(
+                    "view {}: failed, {error}",
+                    label(&view.database, &view.name)
+                )
+            }
+        }
+    }
+
+    for verification in report.verifications.iter() {
+        print_verification(verification);
+    }
+    for leaks in report.leaks.iter() {
+        print_leaks(leaks);
+    }
+
+    let success = report.is_success();
+    println!(
+        "{} in {:.1}s",
+        if success { "Succeeded" } else { "Failed" },
+        report.elapsed.as_secs_f64()
+    );
+    if success {
+        SUCCESS
+    } else {
+        FAILURES
+    }
+}
+
+fn print_verification(report: &VerificationReport) {
+    let outcome = if let Some(error) = report.error.as_ref() {
+        error.clone()
+    } else if let Some(reason) = report.skipped.as_ref() {
+        format!("not verified, {reason}")
+    } else if report.is_success() {
+        format!("{} documents verified", report.source_count)
+    } else {
+        let mut outcome = format!(
+            "{} source documents, {} missing, {} mismatched",
+            report.source_count, report.missing, report.mismatched
+        );
+        if let Some(count) = report.target_count {
+            outcome.push_str(&format!(", {count} target documents"));
+        }
+        outcome
+    };
+    println!(
+        "verify {} -> {}: {outcome}",
+        label(&report.database, &report.source_collection),
+        report.target_collection
+    );
+    if !report.missing_ids.is_empty() {
+        println!("  missing: {}", join(report.missing_ids.iter()));
+    }
+    if !report.mismatched_ids.is_empty() {
+        println!("  mismatched: {}", join(report.mismatched_ids.iter()));
+    }
+}
+
+fn print_leaks(report: &LeakReport) {
+    let outcome = if let Some(error) = report.error.as_ref() {
+        error.clone()
+    } else if let Some(reason) = report.skipped.as_ref() {
+        format!("not scanned, {reason}")
+    } else {
+        format!(
+            "{} leaks in {} documents",
+            report.leaks, report.documents_scanned
+        )
+    };
+    println!(
+        "leaks {} -> {}: {outcome}",
+        label(&report.database, &report.source_collection),
+        report.target_collection
+    );
+    for leak in report.leaked.iter() {
+        println!("  {} of {}", leak.path, leak.id);
+    }
+}
+
+fn print_scan_report(report: &ScanReport) -> u8 {
+    let mut failed = false;
+    for collection in report.collections.iter() {
+        let name = label(&collection.database, &collection.collection);
+        if let Some(error) = collection.error.as_ref() {
+            println!("{name}: {error}");
+            failed = true;
+        }
+        for finding in collection.findings.iter() {
+            println!(
+                "{name}.{}: {} in {}/{} values, {}, e.g. {}",
+                finding.path,
+                finding.kind,
+                finding.hits,
+                finding.values,
+                if finding.covered {
+                    "masked"
+                } else {
+                    "NOT masked"
+                },
+                finding.examples.join(", ")
+            );
+        }
+    }
+
+    let uncovered = report.uncovered().count();
+    println!(
+        "{} collections scanned, {uncovered} findings not masked",
+        report.collections.len()
+    );
+    if failed || uncovered > 0 {
+        FAILURES
+    } else {
+        SUCCESS
+    }
+}
+
+fn join(values: impl Iterator<Item = impl ToString>) -> String {
+    values
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
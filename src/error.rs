@@ -1,6 +1,11 @@
+use mongodb::error::ErrorKind;
 use thiserror::Error;
 use tokio::sync::AcquireError;
 
+/// Server error codes of refused credentials or operations: Unauthorized,
+/// AuthenticationFailed and Atlas' authentication error
+const PERMISSION_DENIED_CODES: [i32; 3] = [13, 18, 8000];
+
 #[derive(Debug, Error)]
 pub enum TuxedoError {
     #[error("std::io::Error: {0}")]
@@ -15,12 +20,36 @@ pub enum TuxedoError {
     #[error("Database driver error: {0}")]
     Database(#[from] mongodb::error::Error),
 
+    #[error("Could not connect to source database `{database}`: {reason}")]
+    SourceConnectionFailed { database: String, reason: String },
+
+    #[error("Could not connect to target database `{database}`: {reason}")]
+    TargetConnectionFailed { database: String, reason: String },
+
+    #[error("Permission denied on database `{database}`: {reason}")]
+    PermissionDenied { database: String, reason: String },
+
+    /// The source database has no collections, MongoDB doesn't tell it apart from a
+    /// missing one
+    #[error("Source database `{0}` does not exist")]
+    DatabaseMissing(String),
+
     #[error("Error when acquiring semaphore: {0}")]
     SemaphoreError(#[from] AcquireError),
 
     #[error("Error serializing data: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    #[error("Error serializing BSON: {0}")]
+    BsonSerialization(#[from] bson::ser::Error),
+
+    #[error("Error deserializing BSON: {0}")]
+    BsonDeserialization(#[from] bson::de::Error),
+
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
     #[error("Error joining future: {0}")]
     FutureJoin(#[from] tokio::task::JoinError),
 
@@ -32,4 +61,18 @@ pub enum TuxedoError {
     Other(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
+impl TuxedoError {
+    /// Whether the server refused the credentials or the operation
+    pub(crate) fn is_permission_denied(&self) -> bool {
+        let TuxedoError::Database(error) = self else {
+            return false;
+        };
+        match error.kind.as_ref() {
+            ErrorKind::Authentication { .. } => true,
+            ErrorKind::Command(error) => PERMISSION_DENIED_CODES.contains(&error.code),
+            _ => false,
+        }
+    }
+}
+
 pub type TuxedoResult<T> = std::result::Result<T, TuxedoError>;
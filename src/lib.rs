@@ -1,12 +1,41 @@
 mod error;
 mod mask;
+mod paths;
+mod pii;
+mod policy;
 mod replication;
+mod rules;
 
 pub use error::{TuxedoError, TuxedoResult};
 pub use mask::Mask;
+pub use pii::PiiKind;
+pub use policy::{CollectionPolicy, Policy};
 pub use replication::{
+    gridfs::{GridFsConfig, GridFsConfigBuilder},
+    indexes::ServerVersion,
+    infer::FieldSchema,
+    jsonl::ExtendedJsonMode,
     manager::ReplicationManager,
     manager_builder::ReplicationManagerBuilder,
+    memory::{MemorySink, MemorySource, MemoryView},
+    plan::{CollectionPlan, RunPlan},
     processor::{ProcessorConfigBuilder, ReplicationConfigBuilder},
+    progress::{PlainProgress, ProgressEvent, ProgressListener},
+    report::{
+        CollectionReport, ErrorStage, IndexFailure, Leak, LeakReport, RunReport,
+        VerificationReport, ViewOutcome, ViewReport, MAX_REPORTED_IDS,
+    },
+    sample::Sample,
+    scan::{CollectionScan, PiiFinding, ScanConfig, ScanConfigBuilder, ScanReport},
+    schema::CollectionValidator,
+    sink::{MongoSink, Sink},
+    source::{DocumentCursor, MongoSource, Source},
+    subset::{Relationship, SubsetConfig, SubsetConfigBuilder},
     types::ReplicationStrategy,
 };
+pub use rules::{MaskAction, MaskRules};
+
+#[cfg(feature = "metrics")]
+pub use replication::metrics::Metrics;
+#[cfg(feature = "progress-bars")]
+pub use replication::progress::ProgressBars;
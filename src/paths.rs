@@ -0,0 +1,52 @@
+use bson::{Bson, Document};
+
+/// Collects every value found at a dotted `path`, descending into arrays the same
+/// way MongoDB queries do. Arrays found at the end of the path are flattened.
+pub(crate) fn values_at_path<'a>(document: &'a Document, path: &str) -> Vec<&'a Bson> {
+    let segments: Vec<&str> = path.split('.').collect();
+    let mut values = Vec::new();
+    collect_from_document(document, &segments, &mut values);
+    values
+}
+
+fn collect_from_document<'a>(
+    document: &'a Document,
+    segments: &[&str],
+    values: &mut Vec<&'a Bson>,
+) {
+    let Some((field, rest)) = segments.split_first() else {
+        return;
+    };
+
+    if let Some(value) = document.get(*field) {
+        collect_from_value(value, rest, values);
+    }
+}
+
+fn collect_from_value<'a>(value: &'a Bson, segments: &[&str], values: &mut Vec<&'a Bson>) {
+    match value {
+        Bson::Array(elements) if segments.is_empty() => values.extend(elements.iter()),
+        _ if segments.is_empty() => values.push(value),
+        Bson::Document(document) => collect_from_document(document, segments, values),
+        Bson::Array(elements) => {
+            if let Ok(index) = segments[0].parse::<usize>() {
+                if let Some(element) = elements.get(index) {
+                    collect_from_value(element, &segments[1..], values);
+                }
+                return;
+            }
+            for element in elements {
+                collect_from_value(element, segments, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Byte representation of a value usable as a hash key, `Bson` itself does not
+/// implement `Hash`/`Eq`
+pub(crate) fn bson_key(value: &Bson) -> Vec<u8> {
+    let mut document = Document::new();
+    document.insert("v", value.clone());
+    bson::to_vec(&document).unwrap_or_default()
+}
@@ -0,0 +1,298 @@
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::LazyLock;
+
+/// Kind of personal information a value was detected as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PiiKind {
+    Email,
+    PhoneNumber,
+    /// US ZIP, Canadian and UK postal codes
+    PostalCode,
+    /// Card numbers passing the Luhn check
+    CreditCard,
+    /// US social security and Canadian social insurance numbers
+    NationalId,
+    IpAddress,
+    /// Words of the names dictionary
+    Name,
+}
+
+impl PiiKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PiiKind::Email => "email",
+            PiiKind::PhoneNumber => "phone_number",
+            PiiKind::PostalCode => "postal_code",
+            PiiKind::CreditCard => "credit_card",
+            PiiKind::NationalId => "national_id",
+            PiiKind::IpAddress => "ip_address",
+            PiiKind::Name => "name",
+        }
+    }
+}
+
+impl fmt::Display for PiiKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Found anywhere in a value, e.g. in free text
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").expect("Valid email regex")
+});
+static IPV4: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b")
+        .expect("Valid IPv4 regex")
+});
+static CARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(?:\d[ -]?){12,18}\d\b").expect("Valid card regex"));
+
+/// Matching the whole value, short numbers are too common to be looked for in text
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:(?:\+?1[\s.-]?)?\(?\d{3}\)?[\s.-]?\d{3}[\s.-]?\d{4}|\+[1-9]\d{7,14})$")
+        .expect("Valid phone regex")
+});
+static POSTAL_CODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^(?:\d{5}(?:-\d{4})?|[ABCEGHJ-NPRSTVXY]\d[ABCEGHJ-NPRSTV-Z] ?\d[ABCEGHJ-NPRSTV-Z]\d|[A-Z]{1,2}\d[A-Z\d]? ?\d[A-Z]{2})$",
+    )
+    .expect("Valid postal code regex")
+});
+static SSN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d{3})-(\d{2})-(\d{4})$").expect("Valid SSN regex"));
+static SIN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d{3}[ -]?\d{3}[ -]?\d{3}$").expect("Valid SIN regex"));
+
+/// Common first and last names which are not also common words, separated by spaces
+const NAMES: &str = "\
+    aaron abigail adam alan albert alexander alexis alice amanda amy andrea andrew \
+    angela anna anthony arthur ashley barbara benjamin betty beverly brandon brenda \
+    brian brittany bruce bryan carl carol carolyn catherine charles charlotte cheryl \
+    christina christine christopher cynthia daniel danielle david deborah debra denise \
+    dennis diana diane donald donna dorothy douglas dylan edward elijah elizabeth emily \
+    emma eric ethan evelyn gabriel george gerald gloria gregory hannah harold heather \
+    helen henry isabella jacob jacqueline james janet janice jason jeffrey jennifer \
+    jeremy jessica john jonathan joseph joshua joyce judith julia julie justin karen \
+    katherine kathleen kathryn kayla keith kelly kenneth kevin kimberly kyle larry laura \
+    lauren lawrence linda lisa logan lori madison margaret maria marilyn martha mary \
+    matthew megan melissa michael michelle nancy natalie nathan nicholas nicole olivia \
+    pamela patricia patrick peter rachel randy rebecca richard robert roger ronald ruth \
+    ryan samantha samuel sandra sarah scott sean sharon shirley sophia stephanie stephen \
+    steven susan teresa theresa thomas tiffany timothy tyler victoria vincent virginia \
+    walter william zachary adams allen anderson beaulieu belanger bergeron boucher \
+    bouchard campbell caron clark cloutier davis fortin fournier gagne gagnon garcia \
+    gauthier girard gonzalez harris hernandez jackson johnson jones lapointe lavoie \
+    leblanc leclerc lefebvre levesque lewis lopez martinez miller mitchell morin nelson \
+    nguyen ouellet paquette pelletier perez poirier poulin ramirez rivera roberts \
+    robinson rodriguez sanchez simard smith taylor thibault thompson torres tremblay \
+    walker williams wilson";
+
+/// Detects the kinds of personal information held by string values
+#[derive(Debug, Clone)]
+pub(crate) struct PiiDetector {
+    names: HashSet<String>,
+}
+
+impl PiiDetector {
+    /// Uses the built in names dictionary extended with `names`
+    pub(crate) fn new(names: &[String]) -> Self {
+        Self {
+            names: NAMES
+                .split_whitespace()
+                .map(|name| name.to_string())
+                .chain(names.iter().map(|name| name.to_lowercase()))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn detect(&self, value: &str) -> Vec<PiiKind> {
+        let value = value.trim();
+        let mut kinds = Vec::new();
+
+        if EMAIL.is_match(value) {
+            kinds.push(PiiKind::Email);
+        }
+        if PHONE.is_match(value) {
+            kinds.push(PiiKind::PhoneNumber);
+        }
+        if POSTAL_CODE.is_match(value) {
+            kinds.push(PiiKind::PostalCode);
+        }
+        if CARD
+            .find_iter(value)
+            .any(|card| is_card_number(card.as_str()))
+        {
+            kinds.push(PiiKind::CreditCard);
+        }
+        if is_ssn(value) || is_sin(value) {
+            kinds.push(PiiKind::NationalId);
+        }
+        if IPV4.is_match(value) || value.parse::<IpAddr>().is_ok() {
+            kinds.push(PiiKind::IpAddress);
+        }
+        if value
+            .split(|c: char| !c.is_alphabetic())
+            .any(|word| word.len() > 2 && self.names.contains(&word.to_lowercase()))
+        {
+            kinds.push(PiiKind::Name);
+        }
+        kinds
+    }
+}
+
+fn digits(value: &str) -> Vec<u32> {
+    value.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+/// Luhn checksum used by card and social insurance numbers
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| match (i % 2, digit * 2) {
+            (0, _) => *digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum % 10 == 0
+}
+
+fn is_card_number(value: &str) -> bool {
+    let digits = digits(value);
+    (13..=19).contains(&digits.len()) && digits.iter().any(|digit| *digit != 0) && luhn(&digits)
+}
+
+/// SSNs never use area 000, 666 or 900-999, group 00 or serial 0000
+fn is_ssn(value: &str) -> bool {
+    let Some(captures) = SSN.captures(value) else {
+        return false;
+    };
+    let area = &captures[1];
+    area != "000"
+        && area != "666"
+        && !area.starts_with('9')
+        && &captures[2] != "00"
+        && &captures[3] != "0000"
+}
+
+/// SINs are 9 digits passing the Luhn check
+fn is_sin(value: &str) -> bool {
+    let digits = digits(value);
+    SIN.is_match(value) && digits.iter().any(|digit| *digit != 0) && luhn(&digits)
+}
+
+/// Longest example kept, in characters
+const MAX_EXAMPLE_LENGTH: usize = 64;
+
+/// Hides a value while keeping its shape: the first letter of every word is kept,
+/// other letters become `*` and digits `#`
+pub(crate) fn redact(value: &str) -> String {
+    let mut redacted = String::new();
+    let mut previous_alphabetic = false;
+    for (i, c) in value.chars().enumerate() {
+        if i == MAX_EXAMPLE_LENGTH {
+            redacted.push('…');
+            break;
+        }
+        redacted.push(match c {
+            c if c.is_alphabetic() && !previous_alphabetic => c,
+            c if c.is_alphabetic() => '*',
+            c if c.is_numeric() => '#',
+            c => c,
+        });
+        previous_alphabetic = c.is_alphabetic();
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn card_numbers_pass_the_luhn_check() {
+        for card in [
+            "4111111111111111",
+            "4111 1111 1111 1111",
+            "5500-0000-0000-0004",
+            "378282246310005",
+            "6011111111111117",
+        ] {
+            assert!(is_card_number(card), "{card}");
+        }
+        for card in [
+            "4111111111111112",
+            "0000000000000000",
+            // Too short and too long
+            "411111111116",
+            "41111111111111111113",
+        ] {
+            assert!(!is_card_number(card), "{card}");
+        }
+    }
+
+    #[test]
+    fn ssns_skip_unassigned_numbers() {
+        assert!(is_ssn("123-45-6789"));
+        assert!(is_ssn("899-99-9999"));
+        for ssn in [
+            "000-12-3456",
+            "666-12-3456",
+            "900-12-3456",
+            "999-12-3456",
+            "123-00-4567",
+            "123-45-0000",
+            "123456789",
+            "123-456-789",
+        ] {
+            assert!(!is_ssn(ssn), "{ssn}");
+        }
+    }
+
+    #[test]
+    fn sins_pass_the_luhn_check() {
+        for sin in ["046 454 286", "046-454-286", "046454286"] {
+            assert!(is_sin(sin), "{sin}");
+        }
+        for sin in ["046 454 287", "000 000 000", "046 454 2860", "046/454/286"] {
+            assert!(!is_sin(sin), "{sin}");
+        }
+    }
+
+    #[test]
+    fn detects_values_by_kind() {
+        let detector = PiiDetector::new(&["Zorglub".to_string()]);
+        let detected = |value: &str| detector.detect(value);
+
+        assert!(detected("Contact jane.doe@example.com").contains(&PiiKind::Email));
+        assert!(detected("(555) 123-4567").contains(&PiiKind::PhoneNumber));
+        assert!(detected("H2X 1Y4").contains(&PiiKind::PostalCode));
+        assert!(detected("Paid with 4111 1111 1111 1111").contains(&PiiKind::CreditCard));
+        assert!(detected("123-45-6789").contains(&PiiKind::NationalId));
+        assert!(detected("10.0.0.1").contains(&PiiKind::IpAddress));
+        assert!(detected("::1").contains(&PiiKind::IpAddress));
+        assert!(detected("Mary Tremblay").contains(&PiiKind::Name));
+        assert!(detected("zorglub").contains(&PiiKind::Name));
+        assert!(detected("Order shipped").is_empty());
+    }
+
+    #[test]
+    fn redacts_letters_and_digits() {
+        assert_eq!(redact("John Smith"), "J*** S****");
+        assert_eq!(redact("555-1234"), "###-####");
+        assert_eq!(redact("a1b2 éte"), "a#b# é**");
+        assert_eq!(redact("jane@example.com"), "j***@e******.c**");
+
+        let long = "x".repeat(MAX_EXAMPLE_LENGTH + 10);
+        let redacted = redact(&long);
+        assert_eq!(redacted.chars().count(), MAX_EXAMPLE_LENGTH + 1);
+        assert!(redacted.starts_with("x*"));
+        assert!(redacted.ends_with('…'));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{MaskAction, MaskRules, TuxedoError, TuxedoResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Masking rules of a set of collections, kept in a TOML file. A skeleton can be
+/// generated from a sample of the source with `ScanReport::policy_skeleton`.
+///
+/// ```toml
+/// [[collections]]
+/// name = "users"
+///
+/// [collections.rules]
+/// email = "email"
+/// "address.street" = "address"
+/// ssn = { numeric_string = 9 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub collections: Vec<CollectionPolicy>,
+}
+
+/// Rules of a single source collection
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectionPolicy {
+    pub name: String,
+    /// Database mapping of the collection, the default one when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_collection: Option<String>,
+    /// Actions keyed by dotted path, applied in path order
+    #[serde(default)]
+    pub rules: BTreeMap<String, MaskAction>,
+}
+
+impl Policy {
+    pub fn from_toml(policy: &str) -> TuxedoResult<Self> {
+        toml::from_str(policy)
+            .map_err(|e| TuxedoError::ConfigError(format!("Invalid masking policy: {e}")))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> TuxedoResult<Self> {
+        let path = path.as_ref();
+        let policy = std::fs::read_to_string(path).map_err(|e| {
+            TuxedoError::ConfigError(format!(
+                "Could not read masking policy `{}`: {e}",
+                path.display()
+            ))
+        })?;
+        Self::from_toml(&policy)
+    }
+}
+
+impl CollectionPolicy {
+    pub fn mask_rules(&self) -> MaskRules {
+        self.rules
+            .iter()
+            .fold(MaskRules::new(), |rules, (path, action)| {
+                rules.rule(path, action.clone())
+            })
+    }
+}
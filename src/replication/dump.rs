@@ -0,0 +1,1027 @@
+use super::filter::{sort_documents, Filter};
+use super::indexes::ServerVersion;
+use super::sample::{random_seed, seeded_hash};
+use super::schema::CollectionValidator;
+use super::sink::Sink;
+use super::source::{DocumentCursor, Source};
+use crate::{TuxedoError, TuxedoResult};
+use async_trait::async_trait;
+use bson::{doc, Bson, Document, RawDocumentBuf};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::stream::{self, StreamExt};
+use mongodb::options::{AggregateOptions, CreateCollectionOptions, FindOptions, InsertManyOptions};
+use mongodb::results::CollectionSpecification;
+use mongodb::IndexModel;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+const BSON_EXTENSION: &str = ".bson";
+const METADATA_EXTENSION: &str = ".metadata.json";
+const GZIP_EXTENSION: &str = ".gz";
+
+/// Writes a database in the directory layout `mongorestore` understands: a
+/// `<collection>.bson` file holding the documents and a `<collection>.metadata.json`
+/// file holding the options, indexes and view definition of every collection.
+///
+/// Documents are streamed to disk as they are written, the metadata files are
+/// written once the run finishes.
+#[derive(Debug)]
+pub(crate) struct DumpSink {
+    files: DumpFiles,
+    collections: Mutex<BTreeMap<String, Arc<Mutex<DumpCollection>>>>,
+}
+
+#[derive(Debug, Default)]
+struct DumpCollection {
+    writer: Option<DumpWriter>,
+    options: Document,
+    indexes: Vec<Document>,
+    is_view: bool,
+}
+
+#[derive(Debug)]
+enum DumpWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl DumpWriter {
+    fn create(path: &Path, gzip: bool) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        if gzip {
+            Ok(Self::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Self::Plain(file))
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.write_all(bytes),
+            Self::Gzip(writer) => writer.write_all(bytes),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Plain(mut writer) => writer.flush(),
+            Self::Gzip(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+impl DumpSink {
+    /// `directory` is the database directory, e.g. `dump/<database>`
+    pub(crate) fn new(directory: impl Into<PathBuf>, gzip: bool) -> Self {
+        Self {
+            files: DumpFiles {
+                directory: directory.into(),
+                gzip,
+            },
+            collections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn collection(&self, collection_name: &str) -> Arc<Mutex<DumpCollection>> {
+        let mut collections = self.collections.lock().expect("Dump collections poisoned");
+        Arc::clone(collections.entry(collection_name.to_string()).or_default())
+    }
+}
+
+/// Files of a dump database directory, written from blocking tasks
+#[derive(Debug, Clone)]
+struct DumpFiles {
+    directory: PathBuf,
+    gzip: bool,
+}
+
+impl DumpFiles {
+    fn file_path(&self, collection_name: &str, extension: &str) -> PathBuf {
+        let mut file_name = format!("{collection_name}{extension}");
+        if self.gzip {
+            file_name.push_str(GZIP_EXTENSION);
+        }
+        self.directory.join(file_name)
+    }
+
+    fn write_metadata(
+        &self,
+        collection_name: &str,
+        collection: &DumpCollection,
+    ) -> TuxedoResult<()> {
+        let metadata = doc! {
+            "indexes": collection.indexes.clone(),
+            "collectionName": collection_name,
+            "type": if collection.is_view { "view" } else { "collection" },
+            "options": collection.options.clone(),
+        };
+        let json = serde_json::to_vec(&Bson::Document(metadata).into_canonical_extjson())?;
+
+        let mut writer = DumpWriter::create(
+            &self.file_path(collection_name, METADATA_EXTENSION),
+            self.gzip,
+        )?;
+        writer.write_all(&json)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[async_trait]
+impl Sink for DumpSink {
+    async fn write(
+        &self,
+        collection_name: &str,
+        documents: &[RawDocumentBuf],
+        _options: Option<InsertManyOptions>,
+    ) -> TuxedoResult<()> {
+        let collection = self.collection(collection_name);
+        let path = self.files.file_path(collection_name, BSON_EXTENSION);
+        let gzip = self.files.gzip;
+        // A BSON dump file is simply every document one after the other
+        let bytes: Vec<u8> = documents
+            .iter()
+            .flat_map(|document| document.as_bytes())
+            .copied()
+            .collect();
+
+        tokio::task::spawn_blocking(move || {
+            let mut collection = collection.lock().expect("Dump collection poisoned");
+            if collection.writer.is_none() {
+                collection.writer = Some(DumpWriter::create(&path, gzip)?);
+            }
+            if let Some(writer) = collection.writer.as_mut() {
+                writer.write_all(&bytes)?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        options: CreateCollectionOptions,
+    ) -> TuxedoResult<()> {
+        let options = bson::to_document(&options)?;
+        let collection = self.collection(collection_name);
+        collection.lock().expect("Dump collection poisoned").options = options;
+        Ok(())
+    }
+
+    async fn apply_validator(
+        &self,
+        collection_name: &str,
+        validator: &CollectionValidator,
+    ) -> TuxedoResult<()> {
+        let collection = self.collection(collection_name);
+        let mut collection = collection.lock().expect("Dump collection poisoned");
+
+        collection
+            .options
+            .insert("validator", validator.validator.clone());
+        if let Some(validation_level) = validator.validation_level.as_ref() {
+            collection
+                .options
+                .insert("validationLevel", bson::to_bson(validation_level)?);
+        }
+        if let Some(validation_action) = validator.validation_action.as_ref() {
+            collection
+                .options
+                .insert("validationAction", bson::to_bson(validation_action)?);
+        }
+        Ok(())
+    }
+
+    async fn create_indexes(
+        &self,
+        collection_name: &str,
+        indexes: Vec<IndexModel>,
+    ) -> TuxedoResult<()> {
+        let indexes = indexes
+            .iter()
+            .map(bson::to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let collection = self.collection(collection_name);
+        collection
+            .lock()
+            .expect("Dump collection poisoned")
+            .indexes
+            .extend(indexes);
+        Ok(())
+    }
+
+    async fn create_view(
+        &self,
+        view_name: &str,
+        view_on: &str,
+        pipeline: Vec<Document>,
+    ) -> TuxedoResult<()> {
+        let collection = self.collection(view_name);
+        let mut collection = collection.lock().expect("Dump collection poisoned");
+        collection.is_view = true;
+        collection.options = doc! { "viewOn": view_on, "pipeline": pipeline };
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> TuxedoResult<()> {
+        self.collections
+            .lock()
+            .expect("Dump collections poisoned")
+            .remove(collection_name);
+
+        let directory = self.files.directory.clone();
+        let collection_name = collection_name.to_string();
+        tokio::task::spawn_blocking(move || {
+            for extension in [BSON_EXTENSION, METADATA_EXTENSION] {
+                let file_name = format!("{collection_name}{extension}");
+                remove_if_exists(&directory.join(&file_name))?;
+                remove_if_exists(&directory.join(format!("{file_name}{GZIP_EXTENSION}")))?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Collections written during this run as well as the ones already on disk
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        let mut names: BTreeSet<String> = self
+            .collections
+            .lock()
+            .expect("Dump collections poisoned")
+            .keys()
+            .cloned()
+            .collect();
+
+        let directory = self.files.directory.clone();
+        tokio::task::spawn_blocking(move || {
+            for entry in fs::read_dir(&directory)? {
+                let file_name = entry?.file_name().to_string_lossy().into_owned();
+                let file_name = file_name.trim_end_matches(GZIP_EXTENSION);
+                if let Some(name) = file_name
+                    .strip_suffix(METADATA_EXTENSION)
+                    .or_else(|| file_name.strip_suffix(BSON_EXTENSION))
+                {
+                    names.insert(name.to_string());
+                }
+            }
+            Ok(names.into_iter().collect())
+        })
+        .await?
+    }
+
+    async fn server_version(&self) -> Option<ServerVersion> {
+        None
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        let directory = self.files.directory.clone();
+        tokio::task::spawn_blocking(move || {
+            fs::create_dir_all(&directory).map_err(|e| {
+                TuxedoError::ConfigError(format!(
+                    "Could not create dump directory `{}`: {e}",
+                    directory.display()
+                ))
+            })
+        })
+        .await?
+    }
+
+    /// Closes the `.bson` files and writes the metadata of every collection. Collections
+    /// without documents still get an empty `.bson` file so `mongorestore` creates them.
+    async fn finish(&self) -> TuxedoResult<()> {
+        let collections: Vec<(String, Arc<Mutex<DumpCollection>>)> = self
+            .collections
+            .lock()
+            .expect("Dump collections poisoned")
+            .iter()
+            .map(|(name, collection)| (name.clone(), Arc::clone(collection)))
+            .collect();
+
+        let files = self.files.clone();
+        tokio::task::spawn_blocking(move || {
+            for (collection_name, collection) in collections {
+                let mut collection = collection.lock().expect("Dump collection poisoned");
+
+                match collection.writer.take() {
+                    Some(writer) => writer.finish()?,
+                    None if !collection.is_view => DumpWriter::create(
+                        &files.file_path(&collection_name, BSON_EXTENSION),
+                        files.gzip,
+                    )?
+                    .finish()?,
+                    None => {}
+                }
+
+                files.write_metadata(&collection_name, &collection)?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// Magic number at the start of a `mongodump --archive` file
+const ARCHIVE_MAGIC: u32 = 0x8199_e26d;
+/// Ends the metadata section and every namespace block of an archive
+const ARCHIVE_TERMINATOR: u32 = 0xffff_ffff;
+/// Documents buffered between the reading thread and the consuming task
+const READ_BUFFER_SIZE: usize = 1_024;
+/// Documents looked at to estimate the average document size
+const SIZE_SAMPLE_COUNT: usize = 1_000;
+
+/// Reads a database from `mongodump` output without restoring it anywhere.
+///
+/// Both the directory layout (`<database>/<collection>.bson` with its
+/// `.metadata.json`) and single `--archive` files are supported, either of them
+/// optionally gzip compressed. Documents are streamed from disk, queries and
+/// projections are evaluated in memory.
+#[derive(Debug)]
+pub(crate) struct DumpSource {
+    layout: DumpLayout,
+    database: String,
+    metadata: BTreeMap<String, Document>,
+}
+
+#[derive(Debug, Clone)]
+enum DumpLayout {
+    /// Directory holding the `.bson` files of the database
+    Directory(PathBuf),
+    /// Archive holding every database of the dump, interleaved
+    Archive(PathBuf),
+}
+
+impl DumpSource {
+    /// Opens the dump at `path` and reads the metadata of `database`. `path` is either
+    /// an archive file, the dump directory or the directory of the database itself.
+    pub(crate) fn open(path: impl Into<PathBuf>, database: &str) -> TuxedoResult<Self> {
+        let path = path.into();
+        let layout = if path.is_file() {
+            DumpLayout::Archive(path)
+        } else if path.join(database).is_dir() {
+            DumpLayout::Directory(path.join(database))
+        } else if path.is_dir() {
+            DumpLayout::Directory(path)
+        } else {
+            return Err(TuxedoError::ConfigError(format!(
+                "Dump `{}` does not exist",
+                path.display()
+            )));
+        };
+
+        let metadata = match &layout {
+            DumpLayout::Directory(directory) => read_directory_metadata(directory)?,
+            DumpLayout::Archive(path) => read_archive_metadata(path, database)?,
+        };
+
+        Ok(Self {
+            layout,
+            database: database.to_string(),
+            metadata,
+        })
+    }
+
+    /// Streams the documents of a collection matching `filter` from a reading thread
+    fn stream(
+        &self,
+        collection_name: &str,
+        filter: Filter,
+        skip: u64,
+        limit: Option<u64>,
+        projection: Option<Document>,
+    ) -> DocumentCursor {
+        let (sender, receiver) = mpsc::channel(READ_BUFFER_SIZE);
+        let layout = self.layout.clone();
+        let database = self.database.clone();
+        let collection_name = collection_name.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut skipped = 0;
+            let mut sent = 0;
+            let result = scan_collection(&layout, &database, &collection_name, |document| {
+                if !matches_filter(&filter, &document)? {
+                    return Ok(true);
+                }
+                if skipped < skip {
+                    skipped += 1;
+                    return Ok(true);
+                }
+                let document = match projection.as_ref() {
+                    Some(projection) => {
+                        let projected =
+                            project(&document.to_document().map_err(raw_error)?, projection)?;
+                        RawDocumentBuf::from_document(&projected).map_err(raw_error)?
+                    }
+                    None => document,
+                };
+                // The cursor was dropped, stop reading
+                if sender.blocking_send(Ok(document)).is_err() {
+                    return Ok(false);
+                }
+                sent += 1;
+                Ok(limit.is_none_or(|limit| sent < limit))
+            });
+
+            if let Err(e) = result {
+                let _ = sender.blocking_send(Err(e));
+            }
+        });
+
+        cursor_from_receiver(receiver)
+    }
+
+    /// Reads every document of a collection matching `filter` into memory
+    async fn collect(&self, collection_name: &str, filter: Filter) -> TuxedoResult<Vec<Document>> {
+        let layout = self.layout.clone();
+        let database = self.database.clone();
+        let collection_name = collection_name.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut documents = Vec::new();
+            scan_collection(&layout, &database, &collection_name, |document| {
+                let document: Document = bson::from_slice(document.as_bytes())?;
+                if filter.matches(&document) {
+                    documents.push(document);
+                }
+                Ok(true)
+            })?;
+            Ok(documents)
+        })
+        .await?
+    }
+
+    fn specification(
+        &self,
+        collection_name: &str,
+        metadata: &Document,
+    ) -> TuxedoResult<CollectionSpecification> {
+        let specification = doc! {
+            "name": collection_name,
+            "type": metadata.get_str("type").unwrap_or("collection"),
+            "options": metadata.get_document("options").cloned().unwrap_or_default(),
+            "info": { "readOnly": false },
+        };
+        Ok(bson::from_document(specification)?)
+    }
+}
+
+#[async_trait]
+impl Source for DumpSource {
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let filter = Filter::parse(&query)?;
+        let options = options.unwrap_or_default();
+        if options.sort.is_some() {
+            let documents = self.collect(collection_name, filter).await?;
+            return find_cursor(documents, Some(options));
+        }
+
+        let skip = options.skip.unwrap_or_default();
+        let limit = options
+            .limit
+            .map(i64::unsigned_abs)
+            .filter(|limit| *limit > 0);
+        Ok(self.stream(collection_name, filter, skip, limit, options.projection))
+    }
+
+    /// Supports `$match`, `$project`, `$sort`, `$skip`, `$limit`, `$sample` and
+    /// `$count`. Leading `$match` stages are applied while reading.
+    async fn aggregate(
+        &self,
+        collection_name: &str,
+        pipeline: Vec<Document>,
+        _options: Option<AggregateOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let leading_matches = pipeline
+            .iter()
+            .take_while(|stage| stage.contains_key("$match"))
+            .count();
+        let mut filters = Vec::new();
+        for stage in pipeline.iter().take(leading_matches) {
+            filters.push(
+                stage
+                    .get_document("$match")
+                    .cloned()
+                    .map_err(|_| TuxedoError::ConfigError("`$match` expects a document".into()))?,
+            );
+        }
+        let filter = Filter::parse(&match filters.as_slice() {
+            [] => Document::new(),
+            [filter] => filter.clone(),
+            _ => doc! { "$and": filters },
+        })?;
+        let stages = &pipeline[leading_matches..];
+
+        if stages.is_empty() {
+            return Ok(self.stream(collection_name, filter, 0, None, None));
+        }
+
+        // Reject unsupported stages before reading the whole collection
+        for stage in stages.iter() {
+            let name = stage.keys().next().map(String::as_str).unwrap_or_default();
+            if ![
+                "$match", "$project", "$sort", "$skip", "$limit", "$sample", "$count",
+            ]
+            .contains(&name)
+            {
+                return Err(TuxedoError::ConfigError(format!(
+                    "Aggregation stage `{name}` is not supported when reading from files"
+                )));
+            }
+        }
+
+        let mut documents = self.collect(collection_name, filter).await?;
+        for stage in stages.iter() {
+            documents = apply_stage(documents, stage)?;
+        }
+        Ok(cursor_from_documents(documents.into_iter()))
+    }
+
+    async fn count_documents(&self, collection_name: &str, query: Document) -> TuxedoResult<u64> {
+        let filter = Filter::parse(&query)?;
+        let layout = self.layout.clone();
+        let database = self.database.clone();
+        let collection_name = collection_name.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut count = 0;
+            scan_collection(&layout, &database, &collection_name, |document| {
+                if matches_filter(&filter, &document)? {
+                    count += 1;
+                }
+                Ok(true)
+            })?;
+            Ok(count)
+        })
+        .await?
+    }
+
+    async fn average_document_size(&self, collection_name: &str) -> TuxedoResult<u64> {
+        let layout = self.layout.clone();
+        let database = self.database.clone();
+        let collection_name = collection_name.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut count = 0;
+            let mut total_size = 0;
+            scan_collection(&layout, &database, &collection_name, |document| {
+                count += 1;
+                total_size += document.as_bytes().len() as u64;
+                Ok(count < SIZE_SAMPLE_COUNT as u64)
+            })?;
+            Ok(total_size.checked_div(count).unwrap_or(1024))
+        })
+        .await?
+    }
+
+    async fn collection_specification(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<Option<CollectionSpecification>> {
+        self.metadata
+            .get(collection_name)
+            .map(|metadata| self.specification(collection_name, metadata))
+            .transpose()
+    }
+
+    async fn list_indexes(&self, collection_name: &str) -> TuxedoResult<Vec<IndexModel>> {
+        let Some(Ok(indexes)) = self
+            .metadata
+            .get(collection_name)
+            .map(|metadata| metadata.get_array("indexes"))
+        else {
+            return Ok(Vec::new());
+        };
+
+        indexes
+            .iter()
+            .filter_map(Bson::as_document)
+            .map(|index| Ok(bson::from_document(index.clone())?))
+            .collect()
+    }
+
+    async fn list_views(&self) -> TuxedoResult<Vec<CollectionSpecification>> {
+        self.metadata
+            .iter()
+            .filter(|(_, metadata)| metadata.get_str("type") == Ok("view"))
+            .map(|(name, metadata)| self.specification(name, metadata))
+            .collect()
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        Ok(self
+            .metadata
+            .iter()
+            .filter(|(_, metadata)| metadata.get_str("type") != Ok("view"))
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+
+    /// The dump was already read when it was opened
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    fn supports_partitioned_reads(&self) -> bool {
+        false
+    }
+}
+
+fn matches_filter(filter: &Filter, document: &RawDocumentBuf) -> TuxedoResult<bool> {
+    if filter.is_empty() {
+        return Ok(true);
+    }
+    let document: Document = bson::from_slice(document.as_bytes())?;
+    Ok(filter.matches(&document))
+}
+
+fn cursor_from_receiver(receiver: mpsc::Receiver<TuxedoResult<RawDocumentBuf>>) -> DocumentCursor {
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    });
+    DocumentCursor::new(stream.boxed())
+}
+
+/// Applies the sort, skip, limit and projection of a find to the matching documents
+pub(crate) fn find_cursor(
+    mut documents: Vec<Document>,
+    options: Option<FindOptions>,
+) -> TuxedoResult<DocumentCursor> {
+    let options = options.unwrap_or_default();
+    let skip = options.skip.unwrap_or_default() as usize;
+    let limit = options
+        .limit
+        .map(i64::unsigned_abs)
+        .filter(|limit| *limit > 0)
+        .map_or(usize::MAX, |limit| limit as usize);
+
+    if let Some(sort) = options.sort.as_ref() {
+        sort_documents(&mut documents, sort);
+    }
+    let mut documents: Vec<Document> = documents.into_iter().skip(skip).take(limit).collect();
+    if let Some(projection) = options.projection.as_ref() {
+        for document in documents.iter_mut() {
+            *document = project(document, projection)?;
+        }
+    }
+    Ok(cursor_from_documents(documents.into_iter()))
+}
+
+pub(crate) fn cursor_from_documents(documents: impl Iterator<Item = Document>) -> DocumentCursor {
+    let documents = documents
+        .map(|document| RawDocumentBuf::from_document(&document).map_err(raw_error))
+        .collect::<Vec<TuxedoResult<RawDocumentBuf>>>();
+    DocumentCursor::new(stream::iter(documents).boxed())
+}
+
+fn raw_error(error: bson::raw::Error) -> TuxedoError {
+    TuxedoError::Generic(format!("Invalid BSON document in dump: {error}"))
+}
+
+/// Applies a single in-memory aggregation stage
+pub(crate) fn apply_stage(
+    mut documents: Vec<Document>,
+    stage: &Document,
+) -> TuxedoResult<Vec<Document>> {
+    let Some((name, value)) = stage.iter().next() else {
+        return Ok(documents);
+    };
+
+    match (name.as_str(), value) {
+        ("$match", Bson::Document(query)) => {
+            let filter = Filter::parse(query)?;
+            documents.retain(|document| filter.matches(document));
+        }
+        ("$project", Bson::Document(projection)) => {
+            for document in documents.iter_mut() {
+                *document = project(document, projection)?;
+            }
+        }
+        ("$sort", Bson::Document(sort)) => sort_documents(&mut documents, sort),
+        ("$skip", value) => {
+            documents.drain(..(stage_number(name, value)? as usize).min(documents.len()));
+        }
+        ("$limit", value) => documents.truncate(stage_number(name, value)? as usize),
+        ("$sample", Bson::Document(sample)) => {
+            let size = stage_number("$sample", sample.get("size").unwrap_or(&Bson::Null))?;
+            let seed = random_seed();
+            let mut ranked: Vec<(u64, Document)> = documents
+                .into_iter()
+                .map(|document| {
+                    let bytes = bson::to_vec(&document).unwrap_or_default();
+                    (seeded_hash(seed, &bytes), document)
+                })
+                .collect();
+            ranked.sort_by_key(|(rank, _)| *rank);
+            documents = ranked
+                .into_iter()
+                .take(size as usize)
+                .map(|(_, document)| document)
+                .collect();
+        }
+        ("$count", Bson::String(field)) => {
+            // Like the server, nothing is returned when there is nothing to count
+            if !documents.is_empty() {
+                documents = vec![doc! { field: documents.len() as i64 }];
+            }
+        }
+        _ => {
+            return Err(TuxedoError::ConfigError(format!(
+                "Aggregation stage `{name}` is not supported when reading from files"
+            )))
+        }
+    }
+
+    Ok(documents)
+}
+
+fn stage_number(stage: &str, value: &Bson) -> TuxedoResult<u64> {
+    match value {
+        Bson::Int32(value) if *value >= 0 => Ok(*value as u64),
+        Bson::Int64(value) if *value >= 0 => Ok(*value as u64),
+        Bson::Double(value) if *value >= 0.0 => Ok(*value as u64),
+        _ => Err(TuxedoError::ConfigError(format!(
+            "`{stage}` expects a positive number"
+        ))),
+    }
+}
+
+/// Inclusion or exclusion projection, dotted fields reach into embedded documents
+/// and the documents of arrays
+pub(crate) fn project(document: &Document, projection: &Document) -> TuxedoResult<Document> {
+    let included = |value: &Bson| {
+        !matches!(
+            value,
+            Bson::Int32(0) | Bson::Int64(0) | Bson::Boolean(false)
+        )
+    };
+
+    let keep_id = projection.get("_id").is_none_or(included);
+    // `{ _id: 1 }` on its own is an inclusion projection as well
+    let inclusion = projection
+        .iter()
+        .any(|(field, value)| field != "_id" && included(value))
+        || (projection.len() == 1 && projection.get("_id").is_some_and(included));
+
+    let mut fields = projection.clone();
+    fields.remove("_id");
+    let mut projected = Document::new();
+    for (field, value) in document.iter() {
+        if field == "_id" {
+            if keep_id {
+                projected.insert(field.clone(), value.clone());
+            }
+        } else if let Some(value) = project_field(field, value, &fields, inclusion) {
+            projected.insert(field.clone(), value);
+        }
+    }
+    Ok(projected)
+}
+
+/// Value of `field` once projected, `None` when it is left out
+fn project_field(
+    field: &str,
+    value: &Bson,
+    projection: &Document,
+    inclusion: bool,
+) -> Option<Bson> {
+    if projection.contains_key(field) {
+        return inclusion.then(|| value.clone());
+    }
+
+    let prefix = format!("{field}.");
+    let nested: Document = projection
+        .iter()
+        .filter_map(|(path, flag)| {
+            path.strip_prefix(&prefix)
+                .map(|path| (path.to_string(), flag.clone()))
+        })
+        .collect();
+    if nested.is_empty() {
+        return (!inclusion).then(|| value.clone());
+    }
+
+    let project_document = |document: &Document| {
+        document
+            .iter()
+            .filter_map(|(field, value)| {
+                project_field(field, value, &nested, inclusion).map(|value| (field.clone(), value))
+            })
+            .collect::<Document>()
+    };
+    match value {
+        Bson::Document(document) => Some(Bson::Document(project_document(document))),
+        Bson::Array(elements) => Some(Bson::Array(
+            elements
+                .iter()
+                .filter_map(|element| match element {
+                    Bson::Document(document) => Some(Bson::Document(project_document(document))),
+                    element => (!inclusion).then(|| element.clone()),
+                })
+                .collect(),
+        )),
+        value => (!inclusion).then(|| value.clone()),
+    }
+}
+
+/// Opens a dump file, transparently decompressing it when it is gzip compressed
+fn open_reader(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if is_gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Reads the little-endian length prefix of the next document, `None` at the end
+fn read_length(reader: &mut impl Read) -> io::Result<Option<u32>> {
+    let mut bytes = [0u8; 4];
+    let mut read = 0;
+    while read < bytes.len() {
+        match reader.read(&mut bytes[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            count => read += count,
+        }
+    }
+    Ok(Some(u32::from_le_bytes(bytes)))
+}
+
+fn read_document(reader: &mut impl Read, length: u32) -> TuxedoResult<RawDocumentBuf> {
+    if length < 5 {
+        return Err(TuxedoError::Generic(format!(
+            "Invalid BSON document length {length} in dump"
+        )));
+    }
+
+    let mut bytes = vec![0u8; length as usize];
+    bytes[..4].copy_from_slice(&length.to_le_bytes());
+    reader.read_exact(&mut bytes[4..])?;
+    RawDocumentBuf::from_bytes(bytes).map_err(raw_error)
+}
+
+fn read_next_document(reader: &mut impl Read) -> TuxedoResult<Option<RawDocumentBuf>> {
+    match read_length(reader)? {
+        Some(length) => Ok(Some(read_document(reader, length)?)),
+        None => Ok(None),
+    }
+}
+
+/// Calls `visit` with every document of a collection until it returns `false`.
+/// A collection without a `.bson` file has no documents.
+fn scan_collection(
+    layout: &DumpLayout,
+    database: &str,
+    collection_name: &str,
+    mut visit: impl FnMut(RawDocumentBuf) -> TuxedoResult<bool>,
+) -> TuxedoResult<()> {
+    match layout {
+        DumpLayout::Directory(directory) => {
+            let plain = directory.join(format!("{collection_name}{BSON_EXTENSION}"));
+            let compressed =
+                directory.join(format!("{collection_name}{BSON_EXTENSION}{GZIP_EXTENSION}"));
+            let path = match (plain.is_file(), compressed.is_file()) {
+                (true, _) => plain,
+                (false, true) => compressed,
+                (false, false) => return Ok(()),
+            };
+
+            let mut reader = open_reader(&path)?;
+            while let Some(document) = read_next_document(&mut reader)? {
+                if !visit(document)? {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        DumpLayout::Archive(path) => {
+            let mut reader = open_archive(path)?;
+            skip_archive_metadata(&mut reader)?;
+
+            // The body is a sequence of namespace headers, each followed by documents
+            // of that namespace up to a terminator
+            while let Some(header) = read_next_document(&mut reader)? {
+                let header: Document = bson::from_slice(header.as_bytes())?;
+                let in_collection = header.get_str("db") == Ok(database)
+                    && header.get_str("collection") == Ok(collection_name);
+
+                loop {
+                    match read_length(&mut reader)? {
+                        None => return Ok(()),
+                        Some(ARCHIVE_TERMINATOR) => break,
+                        Some(length) => {
+                            let document = read_document(&mut reader, length)?;
+                            if in_collection && !visit(document)? {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Opens an archive and reads past its magic number and header
+fn open_archive(path: &Path) -> TuxedoResult<Box<dyn Read + Send>> {
+    let mut reader = open_reader(path)?;
+    if read_length(&mut reader)? != Some(ARCHIVE_MAGIC) {
+        return Err(TuxedoError::ConfigError(format!(
+            "`{}` is not a mongodump archive",
+            path.display()
+        )));
+    }
+    read_next_document(&mut reader)?;
+    Ok(reader)
+}
+
+/// Reads the metadata section of an archive, calling `visit` for every entry
+fn read_archive_metadata_entries(
+    reader: &mut impl Read,
+    mut visit: impl FnMut(Document) -> TuxedoResult<()>,
+) -> TuxedoResult<()> {
+    loop {
+        match read_length(reader)? {
+            None | Some(ARCHIVE_TERMINATOR) => return Ok(()),
+            Some(length) => {
+                let entry = read_document(reader, length)?;
+                visit(bson::from_slice(entry.as_bytes())?)?;
+            }
+        }
+    }
+}
+
+fn skip_archive_metadata(reader: &mut impl Read) -> TuxedoResult<()> {
+    read_archive_metadata_entries(reader, |_| Ok(()))
+}
+
+fn read_archive_metadata(path: &Path, database: &str) -> TuxedoResult<BTreeMap<String, Document>> {
+    let mut reader = open_archive(path)?;
+    let mut metadata = BTreeMap::new();
+    read_archive_metadata_entries(&mut reader, |entry| {
+        if entry.get_str("db") != Ok(database) {
+            return Ok(());
+        }
+        // The metadata of each collection is embedded as an Extended JSON string
+        if let (Ok(collection), Ok(json)) = (entry.get_str("collection"), entry.get_str("metadata"))
+        {
+            metadata.insert(collection.to_string(), parse_metadata(json.as_bytes())?);
+        }
+        Ok(())
+    })?;
+    Ok(metadata)
+}
+
+fn read_directory_metadata(directory: &Path) -> TuxedoResult<BTreeMap<String, Document>> {
+    let mut metadata = BTreeMap::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let Some(collection_name) = file_name
+            .trim_end_matches(GZIP_EXTENSION)
+            .strip_suffix(METADATA_EXTENSION)
+        else {
+            continue;
+        };
+
+        let mut json = Vec::new();
+        open_reader(&path)?.read_to_end(&mut json)?;
+        metadata.insert(collection_name.to_string(), parse_metadata(&json)?);
+    }
+    Ok(metadata)
+}
+
+fn parse_metadata(json: &[u8]) -> TuxedoResult<Document> {
+    let value: serde_json::Value = serde_json::from_slice(json)?;
+    match Bson::try_from(value) {
+        Ok(Bson::Document(metadata)) => Ok(metadata),
+        Ok(_) => Err(TuxedoError::Generic(
+            "Dump metadata is not a document".into(),
+        )),
+        Err(e) => Err(TuxedoError::Generic(format!("Invalid dump metadata: {e}"))),
+    }
+}
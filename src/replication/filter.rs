@@ -0,0 +1,506 @@
+use crate::paths::{bson_key, values_at_path};
+use crate::{TuxedoError, TuxedoResult};
+use bson::{doc, Bson, Document};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// A query filter evaluated in memory, for sources which are not a server.
+///
+/// Supports the subset of the query language tuxedo itself generates and that is
+/// commonly used to select documents: field equality, `$eq`, `$ne`, `$gt`, `$gte`,
+/// `$lt`, `$lte`, `$in`, `$nin`, `$exists`, `$not`, `$and`, `$or` and `$nor`, with
+/// dotted paths descending into arrays. Anything else is rejected when parsing.
+#[derive(Debug)]
+pub(crate) struct Filter {
+    clauses: Vec<Clause>,
+}
+
+#[derive(Debug)]
+enum Clause {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Nor(Vec<Filter>),
+    Field(String, Vec<Condition>),
+}
+
+#[derive(Debug)]
+enum Condition {
+    Eq(Bson),
+    Ne(Bson),
+    Compare(Ordering, bool, Bson),
+    In(ValueSet),
+    Nin(ValueSet),
+    Exists(bool),
+    Not(Vec<Condition>),
+}
+
+/// Values of an `$in` / `$nin`, looked up by their BSON representation. Numbers are
+/// kept aside so `1` matches `1.0` like it does on the server.
+#[derive(Debug, Default)]
+struct ValueSet {
+    keys: HashSet<Vec<u8>>,
+    numbers: Vec<Bson>,
+    has_null: bool,
+}
+
+impl ValueSet {
+    fn new(values: &[Bson]) -> Self {
+        let mut set = Self::default();
+        for value in values {
+            match value {
+                Bson::Null => set.has_null = true,
+                _ if as_f64(value).is_some() => set.numbers.push(value.clone()),
+                _ => {
+                    set.keys.insert(bson_key(value));
+                }
+            }
+        }
+        set
+    }
+
+    fn contains(&self, value: &Bson) -> bool {
+        if as_f64(value).is_some() {
+            return self
+                .numbers
+                .iter()
+                .any(|number| values_equal(number, value));
+        }
+        self.keys.contains(&bson_key(value))
+    }
+}
+
+impl Filter {
+    pub(crate) fn parse(filter: &Document) -> TuxedoResult<Self> {
+        let mut clauses = Vec::new();
+        for (key, value) in filter.iter() {
+            let clause = match key.as_str() {
+                "$and" => Clause::And(parse_filters(key, value)?),
+                "$or" => Clause::Or(parse_filters(key, value)?),
+                "$nor" => Clause::Nor(parse_filters(key, value)?),
+                _ if key.starts_with('$') => return Err(unsupported(key)),
+                _ => Clause::Field(key.clone(), parse_conditions(value)?),
+            };
+            clauses.push(clause);
+        }
+        Ok(Self { clauses })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    pub(crate) fn matches(&self, document: &Document) -> bool {
+        self.clauses.iter().all(|clause| match clause {
+            Clause::And(filters) => filters.iter().all(|f| f.matches(document)),
+            Clause::Or(filters) => filters.iter().any(|f| f.matches(document)),
+            Clause::Nor(filters) => !filters.iter().any(|f| f.matches(document)),
+            Clause::Field(path, conditions) => {
+                let values = field_values(document, path);
+                conditions.iter().all(|c| c.matches(&values))
+            }
+        })
+    }
+}
+
+/// Values at `path`, including an array found at the end of the path itself so
+/// whole arrays can be compared as well as their elements
+fn field_values<'a>(document: &'a Document, path: &str) -> Vec<&'a Bson> {
+    let mut values = values_at_path(document, path);
+    if let Some(array @ Bson::Array(_)) = document.get(path) {
+        values.push(array);
+    }
+    values
+}
+
+impl Condition {
+    fn matches(&self, values: &[&Bson]) -> bool {
+        match self {
+            Condition::Eq(expected) => equals_any(values, expected),
+            Condition::Ne(expected) => !equals_any(values, expected),
+            Condition::Compare(ordering, or_equal, expected) => values.iter().any(|v| {
+                compare_values(v, expected)
+                    .is_some_and(|o| o == *ordering || (*or_equal && o == Ordering::Equal))
+            }),
+            Condition::In(set) if values.is_empty() => set.has_null,
+            Condition::In(set) => values.iter().any(|v| set.contains(v)),
+            Condition::Nin(set) if values.is_empty() => !set.has_null,
+            Condition::Nin(set) => !values.iter().any(|v| set.contains(v)),
+            Condition::Exists(exists) => values.is_empty() != *exists,
+            Condition::Not(conditions) => !conditions.iter().all(|c| c.matches(values)),
+        }
+    }
+}
+
+/// A missing field is equal to `null`
+fn equals_any(values: &[&Bson], expected: &Bson) -> bool {
+    match expected {
+        Bson::Null if values.is_empty() => true,
+        _ => values.iter().any(|v| values_equal(v, expected)),
+    }
+}
+
+fn unsupported(operator: &str) -> TuxedoError {
+    TuxedoError::ConfigError(format!(
+        "Query operator `{operator}` is not supported when reading from files"
+    ))
+}
+
+fn parse_filters(operator: &str, value: &Bson) -> TuxedoResult<Vec<Filter>> {
+    let Bson::Array(filters) = value else {
+        return Err(TuxedoError::ConfigError(format!(
+            "`{operator}` expects an array of filters"
+        )));
+    };
+
+    filters
+        .iter()
+        .map(|filter| match filter {
+            Bson::Document(filter) => Filter::parse(filter),
+            _ => Err(TuxedoError::ConfigError(format!(
+                "`{operator}` expects an array of filters"
+            ))),
+        })
+        .collect()
+}
+
+fn parse_conditions(value: &Bson) -> TuxedoResult<Vec<Condition>> {
+    let operators = match value {
+        Bson::Document(document) if document.keys().all(|key| key.starts_with('$')) => document,
+        _ => return Ok(vec![Condition::Eq(value.clone())]),
+    };
+
+    let mut conditions = Vec::new();
+    for (operator, operand) in operators.iter() {
+        let condition = match operator.as_str() {
+            "$eq" => Condition::Eq(operand.clone()),
+            "$ne" => Condition::Ne(operand.clone()),
+            "$gt" => Condition::Compare(Ordering::Greater, false, operand.clone()),
+            "$gte" => Condition::Compare(Ordering::Greater, true, operand.clone()),
+            "$lt" => Condition::Compare(Ordering::Less, false, operand.clone()),
+            "$lte" => Condition::Compare(Ordering::Less, true, operand.clone()),
+            "$in" | "$nin" => {
+                let Bson::Array(values) = operand else {
+                    return Err(TuxedoError::ConfigError(format!(
+                        "`{operator}` expects an array"
+                    )));
+                };
+                let set = ValueSet::new(values);
+                if operator == "$in" {
+                    Condition::In(set)
+                } else {
+                    Condition::Nin(set)
+                }
+            }
+            "$exists" => Condition::Exists(!matches!(
+                operand,
+                Bson::Boolean(false) | Bson::Int32(0) | Bson::Int64(0) | Bson::Null
+            )),
+            "$not" => Condition::Not(parse_conditions(operand)?),
+            _ => return Err(unsupported(operator)),
+        };
+        conditions.push(condition);
+    }
+    Ok(conditions)
+}
+
+fn as_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        Bson::Double(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn values_equal(a: &Bson, b: &Bson) -> bool {
+    match (a, b) {
+        (Bson::Int64(a), Bson::Int64(b)) => a == b,
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+/// Orders two values of the same kind, `None` when they can't be compared
+pub(crate) fn compare_values(a: &Bson, b: &Bson) -> Option<Ordering> {
+    match (a, b) {
+        (Bson::Int64(a), Bson::Int64(b)) => Some(a.cmp(b)),
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.bytes().cmp(&b.bytes())),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::Timestamp(a), Bson::Timestamp(b)) => {
+            Some((a.time, a.increment).cmp(&(b.time, b.increment)))
+        }
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => None,
+        },
+    }
+}
+
+/// Sorts documents by a `{ field: 1 | -1 }` specification
+pub(crate) fn sort_documents(documents: &mut [Document], sort: &Document) {
+    let fields: Vec<(&str, bool)> = sort
+        .iter()
+        .map(|(field, direction)| {
+            let descending = matches!(direction, Bson::Int32(-1) | Bson::Int64(-1))
+                || matches!(direction, Bson::Double(d) if *d < 0.0);
+            (field.as_str(), descending)
+        })
+        .collect();
+
+    documents.sort_by(|a, b| {
+        for (field, descending) in fields.iter() {
+            let a = values_at_path(a, field).into_iter().next();
+            let b = values_at_path(b, field).into_iter().next();
+            // Missing values sort first, like `null` on the server
+            let ordering = match (a, b) {
+                (Some(a), Some(b)) => compare_bson(a, b),
+                (None, Some(_)) => Ordering::Less,
+                (Some(_), None) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            let ordering = if *descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+}
+
+/// Orders `_id`s like the server does, a missing `_id` first
+pub(crate) fn compare_ids(a: Option<&Bson>, b: Option<&Bson>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => compare_bson(a, b),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+/// Total order of BSON values following the server: by type first, then by value.
+/// Documents and arrays compare element by element, on the type of the values, then
+/// their field names, then the values themselves. Decimals can't be converted and
+/// only compare with each other, by their bytes.
+pub(crate) fn compare_bson(a: &Bson, b: &Bson) -> Ordering {
+    type_order(a)
+        .cmp(&type_order(b))
+        .then_with(|| match (a, b) {
+            (Bson::Decimal128(a), Bson::Decimal128(b)) => a.bytes().cmp(&b.bytes()),
+            (Bson::Decimal128(_), _) => Ordering::Greater,
+            (_, Bson::Decimal128(_)) => Ordering::Less,
+            // NaN sorts before every other number
+            (Bson::Double(a), Bson::Double(b)) if a.is_nan() || b.is_nan() => {
+                b.is_nan().cmp(&a.is_nan())
+            }
+            (Bson::Double(a), _) if a.is_nan() => Ordering::Less,
+            (_, Bson::Double(b)) if b.is_nan() => Ordering::Greater,
+            (Bson::String(a) | Bson::Symbol(a), Bson::String(b) | Bson::Symbol(b)) => a.cmp(b),
+            (Bson::Document(a), Bson::Document(b)) => compare_documents(a, b),
+            (Bson::Array(a), Bson::Array(b)) => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| compare_bson(a, b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Bson::Binary(a), Bson::Binary(b)) => a
+                .bytes
+                .len()
+                .cmp(&b.bytes.len())
+                .then_with(|| u8::from(a.subtype).cmp(&u8::from(b.subtype)))
+                .then_with(|| a.bytes.cmp(&b.bytes)),
+            (Bson::RegularExpression(a), Bson::RegularExpression(b)) => a
+                .pattern
+                .cmp(&b.pattern)
+                .then_with(|| a.options.cmp(&b.options)),
+            (Bson::JavaScriptCode(a), Bson::JavaScriptCode(b)) => a.cmp(b),
+            (Bson::JavaScriptCodeWithScope(a), Bson::JavaScriptCodeWithScope(b)) => a
+                .code
+                .cmp(&b.code)
+                .then_with(|| compare_documents(&a.scope, &b.scope)),
+            (Bson::DbPointer(_), Bson::DbPointer(_)) => bson::to_vec(&doc! { "v": a })
+                .unwrap_or_default()
+                .cmp(&bson::to_vec(&doc! { "v": b }).unwrap_or_default()),
+            _ => compare_values(a, b).unwrap_or(Ordering::Equal),
+        })
+}
+
+/// Compares the fields in order, on the type of their values, then their names,
+/// then the values, a document being a prefix of the other first
+fn compare_documents(a: &Document, b: &Document) -> Ordering {
+    a.iter()
+        .zip(b.iter())
+        .map(|((a_key, a), (b_key, b))| {
+            type_order(a)
+                .cmp(&type_order(b))
+                .then_with(|| a_key.cmp(b_key))
+                .then_with(|| compare_bson(a, b))
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+/// BSON comparison order of a value's type
+fn type_order(value: &Bson) -> u8 {
+    match value {
+        Bson::MinKey => 0,
+        Bson::Null | Bson::Undefined => 1,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => 2,
+        Bson::Symbol(_) | Bson::String(_) => 3,
+        Bson::Document(_) => 4,
+        Bson::Array(_) => 5,
+        Bson::Binary(_) => 6,
+        Bson::ObjectId(_) => 7,
+        Bson::Boolean(_) => 8,
+        Bson::DateTime(_) => 9,
+        Bson::Timestamp(_) => 10,
+        Bson::RegularExpression(_) => 11,
+        Bson::DbPointer(_) => 12,
+        Bson::JavaScriptCode(_) => 13,
+        Bson::JavaScriptCodeWithScope(_) => 14,
+        Bson::MaxKey => 15,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{oid::ObjectId, spec::BinarySubtype, Binary};
+
+    fn sorted(mut ids: Vec<Bson>) -> Vec<Bson> {
+        ids.sort_by(|a, b| compare_ids(Some(a), Some(b)));
+        ids
+    }
+
+    fn binary(subtype: BinarySubtype, bytes: &[u8]) -> Bson {
+        Bson::Binary(Binary {
+            subtype,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    #[test]
+    fn compound_ids_compare_by_value_not_size() {
+        let short = Bson::from(doc! { "a": "b" });
+        let long = Bson::from(doc! { "a": "aa" });
+        assert_eq!(compare_ids(Some(&long), Some(&short)), Ordering::Less);
+    }
+
+    #[test]
+    fn compound_ids_compare_field_by_field() {
+        let ids = vec![
+            Bson::from(doc! { "b": 0 }),
+            Bson::from(doc! { "a": 1, "b": 1 }),
+            Bson::from(doc! { "a": "x" }),
+            Bson::from(doc! { "a": 1 }),
+            Bson::from(doc! { "a": 1, "b": 0 }),
+            Bson::from(doc! { "a": 2 }),
+        ];
+        assert_eq!(
+            sorted(ids),
+            vec![
+                // Numbers sort before strings, whatever the field name
+                Bson::from(doc! { "a": 1 }),
+                Bson::from(doc! { "a": 1, "b": 0 }),
+                Bson::from(doc! { "a": 1, "b": 1 }),
+                Bson::from(doc! { "a": 2 }),
+                Bson::from(doc! { "b": 0 }),
+                Bson::from(doc! { "a": "x" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_ids_compare_nested_values() {
+        let ids = vec![
+            Bson::from(doc! { "k": { "x": [1, 3] } }),
+            Bson::from(doc! { "k": { "x": [1, 2, 5] } }),
+            Bson::from(doc! { "k": { "x": [1, 2] } }),
+        ];
+        assert_eq!(
+            sorted(ids),
+            vec![
+                Bson::from(doc! { "k": { "x": [1, 2] } }),
+                Bson::from(doc! { "k": { "x": [1, 2, 5] } }),
+                Bson::from(doc! { "k": { "x": [1, 3] } }),
+            ]
+        );
+    }
+
+    #[test]
+    fn ids_compare_by_type_first() {
+        let object_id = ObjectId::new();
+        let ids = vec![
+            Bson::ObjectId(object_id),
+            Bson::from(doc! { "a": 1 }),
+            Bson::String("1".into()),
+            Bson::Int64(2),
+            Bson::Double(1.5),
+            Bson::Null,
+        ];
+        assert_eq!(
+            sorted(ids),
+            vec![
+                Bson::Null,
+                Bson::Double(1.5),
+                Bson::Int64(2),
+                Bson::String("1".into()),
+                Bson::from(doc! { "a": 1 }),
+                Bson::ObjectId(object_id),
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_ids_compare_by_length_then_subtype_then_bytes() {
+        let ids = vec![
+            binary(BinarySubtype::Generic, &[2, 0]),
+            binary(BinarySubtype::Uuid, &[0, 0]),
+            binary(BinarySubtype::Generic, &[1, 9]),
+            binary(BinarySubtype::Uuid, &[9]),
+        ];
+        assert_eq!(
+            sorted(ids),
+            vec![
+                binary(BinarySubtype::Uuid, &[9]),
+                binary(BinarySubtype::Generic, &[1, 9]),
+                binary(BinarySubtype::Generic, &[2, 0]),
+                binary(BinarySubtype::Uuid, &[0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_mixed_types_by_type_then_value() {
+        let mut documents: Vec<Document> = (0..40)
+            .map(|i| match i % 4 {
+                0 => doc! { "_id": i },
+                1 => doc! { "_id": format!("{i}") },
+                2 => doc! { "_id": { "n": i } },
+                _ => doc! { "n": i },
+            })
+            .collect();
+        sort_documents(&mut documents, &doc! { "_id": 1 });
+
+        let ids: Vec<Option<&Bson>> = documents.iter().map(|d| d.get("_id")).collect();
+        assert!(ids[..10].iter().all(Option::is_none));
+        assert_eq!(ids[10], Some(&Bson::Int32(0)));
+        assert_eq!(ids[20], Some(&Bson::String("1".into())));
+        assert_eq!(ids[21], Some(&Bson::String("13".into())));
+        assert_eq!(ids[30], Some(&Bson::from(doc! { "n": 2 })));
+        assert!(ids
+            .windows(2)
+            .all(|pair| compare_ids(pair[0], pair[1]) != Ordering::Greater));
+    }
+
+    #[test]
+    fn nan_sorts_before_other_numbers() {
+        let ids = vec![Bson::Int32(-5), Bson::Double(f64::NAN)];
+        assert!(matches!(sorted(ids)[0], Bson::Double(nan) if nan.is_nan()));
+    }
+}
@@ -0,0 +1,713 @@
+use super::processor::{
+    check_batch_sizes, matches_query, plan_batches, task_span, MaskingLambda, Processor,
+};
+use super::{
+    indexes::IndexCopyOptions,
+    manager::ReplicationConfig,
+    plan::CollectionPlan,
+    report::{CollectionStats, ErrorStage, LeakReport, VerificationReport},
+    task::Task,
+    types::{DatabasePair, ReplicationStrategy, DEFAULT_DATABASE},
+};
+use crate::{MaskRules, TuxedoError, TuxedoResult};
+use async_trait::async_trait;
+use bson::{doc, spec::BinarySubtype, Binary, Bson, Document};
+use md5::{Digest, Md5};
+use mongodb::options::FindOptions;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, warn, Span};
+
+/// Default GridFS chunk size used when a file document does not specify one
+const DEFAULT_CHUNK_SIZE: usize = 255 * 1024;
+
+/// Number of chunks written per insert, chunks are up to `chunkSize` bytes each
+const CHUNK_WRITE_BATCH_SIZE: usize = 64;
+
+/// Processor copying a GridFS bucket file by file, keeping `<bucket>.files` and
+/// `<bucket>.chunks` consistent with each other.
+pub(crate) struct GridFsProcessor {
+    config: Arc<GridFsConfig>,
+    bucket_name: String,
+    target_bucket_name: String,
+    files_collection_name: String,
+    target_files_collection_name: String,
+}
+
+impl GridFsProcessor {
+    pub(crate) fn new(bucket_name: impl Into<String>, config: GridFsConfig) -> Self {
+        let bucket_name = bucket_name.into();
+        let target_bucket_name = config
+            .target_bucket
+            .clone()
+            .unwrap_or_else(|| bucket_name.clone());
+
+        Self {
+            files_collection_name: files_collection(&bucket_name),
+            target_files_collection_name: files_collection(&target_bucket_name),
+            config: Arc::new(config),
+            bucket_name,
+            target_bucket_name,
+        }
+    }
+}
+
+fn files_collection(bucket_name: &str) -> String {
+    format!("{bucket_name}.files")
+}
+
+fn chunks_collection(bucket_name: &str) -> String {
+    format!("{bucket_name}.chunks")
+}
+
+#[async_trait]
+impl Processor for GridFsProcessor {
+    async fn run(
+        &self,
+        dbs: Arc<DatabasePair>,
+        task_sender: mpsc::Sender<Box<dyn Task>>,
+        default_config: ReplicationConfig,
+        stats: Arc<CollectionStats>,
+        ids: Option<Arc<Vec<Bson>>>,
+    ) {
+        let batch_size = self.config.batch_size.unwrap_or(default_config.batch_size);
+        stats.set_ids(ids.clone());
+
+        let total_files = match self
+            .get_total_documents(
+                &dbs,
+                self.config.query.clone(),
+                ids.as_deref().map(Vec::as_slice),
+                &stats,
+            )
+            .await
+        {
+            Ok(total_files) => total_files,
+            Err(_) => return,
+        };
+
+        if total_files == 0 {
+            return;
+        }
+
+        // Sources which have to be scanned from the start for every slice are read at once
+        let batch_size = if dbs.supports_partitioned_reads() {
+            batch_size
+        } else {
+            (total_files as u64).max(1)
+        };
+
+        let batches = plan_batches(
+            &self.config.query,
+            total_files,
+            batch_size,
+            ids.as_deref().map(Vec::as_slice),
+        );
+        let batch_count = batches.len();
+
+        for (batch_index, batch) in batches.into_iter().enumerate() {
+            let mut read_options = default_config.read_options.clone();
+            batch.apply(&mut read_options);
+
+            let span = task_span(batch_index, batch_count, &read_options);
+            let task = Box::new(GridFsTask {
+                dbs: Arc::clone(&dbs),
+                bucket_name: self.bucket_name.clone(),
+                target_bucket_name: self.target_bucket_name.clone(),
+                config: Arc::clone(&self.config),
+                query: batch.query,
+                read_options,
+                stats: Arc::clone(&stats),
+                span,
+            });
+
+            if task_sender.send(task).await.is_err() {
+                error!(
+                    bucket = %self.bucket_name,
+                    batch = batch_index + 1,
+                    batches = batch_count,
+                    "Failed to send task to worker pool, channel closed, stopping processor"
+                );
+                break;
+            }
+        }
+    }
+
+    async fn copy_indexes(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        copy_options: &IndexCopyOptions,
+        stats: &CollectionStats,
+    ) {
+        let collections = [
+            (
+                files_collection(&self.bucket_name),
+                files_collection(&self.target_bucket_name),
+            ),
+            (
+                chunks_collection(&self.bucket_name),
+                chunks_collection(&self.target_bucket_name),
+            ),
+        ];
+
+        for (source, target) in collections {
+            match dbs.copy_indexes(&source, &target, copy_options).await {
+                Ok(result) => {
+                    stats.add_indexes_copied(&result.created);
+                    for (name, error) in result.failed {
+                        error!(
+                            index = %name,
+                            collection = %source,
+                            target_collection = %target,
+                            %error,
+                            "Error when copying index"
+                        );
+                        stats.add_index_failure(name, error);
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        collection = %source,
+                        target_collection = %target,
+                        error = %e,
+                        "Error when copying indexes"
+                    );
+                    stats.add_error(
+                        ErrorStage::Index,
+                        format!("Failed to copy indexes of `{source}`: {e}"),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Only checks the files made it, their content may be replaced
+    async fn verify(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        _strategy: &ReplicationStrategy,
+        stats: &CollectionStats,
+        compare_count: bool,
+    ) -> VerificationReport {
+        self.verify_documents(dbs, &self.config.query, stats, compare_count, None)
+            .await
+    }
+
+    /// Scans the files collection, the paths of the metadata rules are sensitive too
+    async fn detect_leaks(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        stats: &CollectionStats,
+    ) -> Option<LeakReport> {
+        let paths = self.config.sensitive_paths();
+        self.scan_leaks(dbs, &self.config.query, stats, &paths)
+            .await
+    }
+
+    /// Only the metadata and filename of the file document are masked
+    fn mask_document(
+        &self,
+        document: &Document,
+        _strategy: &ReplicationStrategy,
+    ) -> Option<Document> {
+        if !matches_query(&self.config.query, document) {
+            return None;
+        }
+
+        let mut file = document.clone();
+        self.config.mask_file(&mut file);
+        Some(file)
+    }
+
+    fn plan(&self, _strategy: &ReplicationStrategy) -> CollectionPlan {
+        CollectionPlan {
+            database: self.database_name().to_string(),
+            source_collection: self.files_collection_name.clone(),
+            target_collection: self.target_files_collection_name.clone(),
+            query: self.config.query.clone(),
+            masked: self.config.is_masked(),
+            sensitive_paths: self.config.sensitive_paths(),
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> TuxedoResult<()> {
+        check_batch_sizes(&self.files_collection_name, &[self.config.batch_size])
+    }
+
+    fn collection_name(&self) -> &str {
+        &self.files_collection_name
+    }
+
+    fn target_collection_name(&self) -> &str {
+        &self.target_files_collection_name
+    }
+
+    fn target_collection_names(&self) -> Vec<String> {
+        vec![
+            files_collection(&self.target_bucket_name),
+            chunks_collection(&self.target_bucket_name),
+        ]
+    }
+
+    fn database_name(&self) -> &str {
+        self.config.database.as_deref().unwrap_or(DEFAULT_DATABASE)
+    }
+}
+
+/// Copies a batch of files, chunks are written before the file document so a
+/// reader never sees a file without its content.
+struct GridFsTask {
+    dbs: Arc<DatabasePair>,
+    bucket_name: String,
+    target_bucket_name: String,
+    config: Arc<GridFsConfig>,
+    query: Document,
+    read_options: FindOptions,
+    stats: Arc<CollectionStats>,
+    span: Span,
+}
+
+#[async_trait]
+impl Task for GridFsTask {
+    fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    async fn run(&self) {
+        let mut cursor = match self
+            .dbs
+            .read_documents(
+                &files_collection(&self.bucket_name),
+                self.query.clone(),
+                self.read_options.clone().into(),
+            )
+            .await
+        {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                error!(
+                    bucket = %self.bucket_name,
+                    query = ?self.query,
+                    read_options = ?self.read_options,
+                    error = %e,
+                    "Failed to retrieve source cursor"
+                );
+                self.stats.add_error(
+                    ErrorStage::Read,
+                    format!("Failed to retrieve source cursor: {e}"),
+                );
+                return;
+            }
+        };
+
+        while match cursor.advance().await {
+            Ok(true) => true,
+            Ok(false) => false,
+            Err(e) => {
+                error!(
+                    bucket = %self.bucket_name,
+                    error = %e,
+                    "Error advancing cursor, stopping task"
+                );
+                self.stats.add_error(
+                    ErrorStage::Read,
+                    format!("Error advancing source cursor: {e}"),
+                );
+                false
+            }
+        } {
+            self.stats.add_read(cursor.current_size());
+
+            let file: Document = match cursor.deserialize_current() {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!(
+                        bucket = %self.bucket_name,
+                        error = %e,
+                        "Failed to deserialize file document, skipping file"
+                    );
+                    self.stats.add_failed(1);
+                    continue;
+                }
+            };
+
+            let file_id = file.get("_id").cloned().unwrap_or(Bson::Null);
+            match self.copy_file(file).await {
+                Ok(()) => {
+                    self.stats.add_written(1);
+                }
+                Err(e) => {
+                    error!(
+                        bucket = %self.bucket_name,
+                        target_bucket = %self.target_bucket_name,
+                        file_id = %file_id,
+                        error = %e,
+                        "Failed to copy file"
+                    );
+                    self.stats.add_failed(1);
+                    self.stats.add_error(
+                        ErrorStage::Write,
+                        format!("Failed to copy file {file_id}: {e}"),
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl GridFsTask {
+    async fn copy_file(&self, mut file: Document) -> TuxedoResult<()> {
+        let file_id = file
+            .get("_id")
+            .cloned()
+            .ok_or_else(|| TuxedoError::Generic("GridFS file document without _id".into()))?;
+
+        if self.config.replace_content {
+            let content_type = detect_content_type(&file);
+            let content = self.config.placeholder(content_type.as_deref());
+            let chunk_size = chunk_size(&file);
+
+            self.write_placeholder_chunks(&file_id, &content, chunk_size)
+                .await?;
+
+            // Keep the file document consistent with the new content
+            file.insert("length", content.len() as i64);
+            if file.contains_key("md5") {
+                file.insert("md5", hex_digest(&content));
+            }
+        } else {
+            self.copy_chunks(&file_id).await?;
+        }
+
+        self.config.mask_file(&mut file);
+
+        self.dbs
+            .write::<Document>(&files_collection(&self.target_bucket_name), &[file], None)
+            .await?;
+        Ok(())
+    }
+
+    /// Streams the original chunks of a file into the target bucket
+    async fn copy_chunks(&self, file_id: &Bson) -> TuxedoResult<()> {
+        let source_chunks = chunks_collection(&self.bucket_name);
+        let target_chunks = chunks_collection(&self.target_bucket_name);
+
+        let read_options = FindOptions::builder().sort(doc! { "n": 1 }).build();
+        let mut cursor = self
+            .dbs
+            .read_documents(
+                &source_chunks,
+                doc! { "files_id": file_id.clone() },
+                Some(read_options),
+            )
+            .await?;
+
+        let mut batch: Vec<Document> = Vec::with_capacity(CHUNK_WRITE_BATCH_SIZE);
+        while cursor.advance().await? {
+            batch.push(cursor.deserialize_current()?);
+            if batch.len() >= CHUNK_WRITE_BATCH_SIZE {
+                self.dbs
+                    .write::<Document>(&target_chunks, &batch, None)
+                    .await?;
+                batch.clear();
+            }
+        }
+
+        if !batch.is_empty() {
+            self.dbs
+                .write::<Document>(&target_chunks, &batch, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_placeholder_chunks(
+        &self,
+        file_id: &Bson,
+        content: &[u8],
+        chunk_size: usize,
+    ) -> TuxedoResult<()> {
+        let chunks: Vec<Document> = content
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(n, data)| {
+                doc! {
+                    "files_id": file_id.clone(),
+                    "n": n as i32,
+                    "data": Binary { subtype: BinarySubtype::Generic, bytes: data.to_vec() },
+                }
+            })
+            .collect();
+
+        for batch in chunks.chunks(CHUNK_WRITE_BATCH_SIZE) {
+            self.dbs
+                .write::<Document>(&chunks_collection(&self.target_bucket_name), batch, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn chunk_size(file: &Document) -> usize {
+    match file.get("chunkSize") {
+        Some(Bson::Int32(size)) if *size > 0 => *size as usize,
+        Some(Bson::Int64(size)) if *size > 0 => *size as usize,
+        _ => DEFAULT_CHUNK_SIZE,
+    }
+}
+
+fn hex_digest(content: &[u8]) -> String {
+    Md5::digest(content)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Content type from the (deprecated) top level `contentType`, the metadata or
+/// finally the file name extension
+fn detect_content_type(file: &Document) -> Option<String> {
+    if let Ok(content_type) = file.get_str("contentType") {
+        return Some(content_type.to_lowercase());
+    }
+
+    if let Ok(metadata) = file.get_document("metadata") {
+        for key in ["contentType", "content_type", "mimeType", "mime_type"] {
+            if let Ok(content_type) = metadata.get_str(key) {
+                return Some(content_type.to_lowercase());
+            }
+        }
+    }
+
+    let extension = file
+        .get_str("filename")
+        .ok()?
+        .rsplit_once('.')?
+        .1
+        .to_lowercase();
+
+    let content_type = match extension.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "json" => "application/json",
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "xml" => "text/xml",
+        _ => return None,
+    };
+    Some(content_type.to_string())
+}
+
+/// A single page, blank US letter PDF
+const BLANK_PDF: &[u8] = b"%PDF-1.4\n\
+1 0 obj\n\
+<< /Type /Catalog /Pages 2 0 R >>\n\
+endobj\n\
+2 0 obj\n\
+<< /Type /Pages /Kids [3 0 R] /Count 1 >>\n\
+endobj\n\
+3 0 obj\n\
+<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] >>\n\
+endobj\n\
+xref\n\
+0 4\n\
+0000000000 65535 f \n\
+0000000009 00000 n \n\
+0000000058 00000 n \n\
+0000000115 00000 n \n\
+trailer\n\
+<< /Size 4 /Root 1 0 R >>\n\
+startxref\n\
+186\n\
+%%EOF\n";
+
+/// A 1x1 pixel, 8-bit gray PNG
+const GRAY_PNG: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x7e, 0x9b,
+    0x55, 0x00, 0x00, 0x00, 0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x68, 0x00, 0x00, 0x00,
+    0x82, 0x00, 0x81, 0x77, 0xcd, 0x72, 0xb6, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+    0x42, 0x60, 0x82,
+];
+
+const MASKED_TEXT: &[u8] = b"This file has been masked.\n";
+
+#[derive(Default)]
+pub struct GridFsConfig {
+    batch_size: Option<u64>,
+    query: Document,
+    target_bucket: Option<String>,
+    database: Option<String>,
+    replace_content: bool,
+    placeholders: HashMap<String, Arc<Vec<u8>>>,
+    metadata_rules: MaskRules,
+    metadata_lambda: Option<MaskingLambda>,
+    filename_lambda: Option<FilenameLambda>,
+    sensitive_paths: Vec<String>,
+}
+
+/// Masking function applied to the `filename` of every file
+type FilenameLambda = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+impl GridFsConfig {
+    pub fn builder() -> GridFsConfigBuilder {
+        GridFsConfigBuilder::new()
+    }
+
+    /// Applies the metadata rules and lambda to the `metadata` of a file document and
+    /// the filename lambda to its `filename`
+    fn mask_file(&self, file: &mut Document) {
+        if let Ok(metadata) = file.get_document_mut("metadata") {
+            self.metadata_rules.apply(metadata);
+            if let Some(lambda) = self.metadata_lambda.as_ref() {
+                (lambda)(metadata);
+            }
+        }
+        if let Some(lambda) = self.filename_lambda.as_ref() {
+            if let Ok(filename) = file.get_str("filename") {
+                let filename = (lambda)(filename);
+                file.insert("filename", filename);
+            }
+        }
+    }
+
+    fn is_masked(&self) -> bool {
+        self.replace_content
+            || !self.metadata_rules.is_empty()
+            || self.metadata_lambda.is_some()
+            || self.filename_lambda.is_some()
+    }
+
+    /// Paths of the file documents checked for leaks: the configured ones, those of
+    /// the metadata rules and a masked `filename`
+    fn sensitive_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .sensitive_paths
+            .iter()
+            .cloned()
+            .chain(
+                self.metadata_rules
+                    .paths()
+                    .map(|path| format!("metadata.{path}")),
+            )
+            .collect();
+        if self.filename_lambda.is_some() && !paths.iter().any(|path| path == "filename") {
+            paths.push("filename".to_string());
+        }
+        paths
+    }
+
+    /// Placeholder content for a file of the given content type. Custom placeholders
+    /// take precedence over the built in PDF, PNG, JSON and text placeholders, any
+    /// other content type is replaced by an empty file.
+    fn placeholder(&self, content_type: Option<&str>) -> Vec<u8> {
+        let Some(content_type) = content_type else {
+            return Vec::new();
+        };
+
+        if let Some(placeholder) = self.placeholders.get(content_type) {
+            return placeholder.as_ref().clone();
+        }
+
+        match content_type {
+            "application/pdf" => BLANK_PDF.to_vec(),
+            "image/png" => GRAY_PNG.to_vec(),
+            "application/json" => b"{}".to_vec(),
+            text if text.starts_with("text/") => MASKED_TEXT.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct GridFsConfigBuilder {
+    config: GridFsConfig,
+}
+
+impl GridFsConfigBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of files copied per task
+    pub fn batch_size(mut self, size: impl Into<u64>) -> Self {
+        self.config.batch_size = Some(size.into());
+        self
+    }
+
+    /// Filter applied to the `<bucket>.files` collection
+    pub fn query(mut self, query: impl Into<Document>) -> Self {
+        self.config.query = query.into();
+        self
+    }
+
+    /// Writes the files into a differently named bucket on the target
+    pub fn target_bucket(mut self, bucket_name: impl Into<String>) -> Self {
+        self.config.target_bucket = Some(bucket_name.into());
+        self
+    }
+
+    /// Binds the bucket to a database mapping registered with
+    /// `ReplicationManagerBuilder::add_database`
+    pub fn database(mut self, name: impl Into<String>) -> Self {
+        self.config.database = Some(name.into());
+        self
+    }
+
+    /// Replaces the content of every file with a placeholder matching its content
+    /// type, updating `length` and `md5` to match
+    pub fn replace_content(mut self, enabled: bool) -> Self {
+        self.config.replace_content = enabled;
+        self
+    }
+
+    /// Registers the placeholder content used for files of `content_type`
+    pub fn placeholder(mut self, content_type: impl Into<String>, content: Vec<u8>) -> Self {
+        self.config
+            .placeholders
+            .insert(content_type.into().to_lowercase(), Arc::new(content));
+        self
+    }
+
+    /// Masking rules applied to the `metadata` document of every file
+    pub fn metadata_rules(mut self, rules: MaskRules) -> Self {
+        self.config.metadata_rules = rules;
+        self
+    }
+
+    /// Masking function applied to the `metadata` document of every file
+    pub fn mask_metadata<F>(mut self, lambda: F) -> Self
+    where
+        F: Fn(&mut Document) + Send + Sync + 'static,
+    {
+        self.config.metadata_lambda = Some(Arc::new(lambda));
+        self
+    }
+
+    /// Masking function applied to the `filename` of every file, which often holds
+    /// names or ids. Keep the extension so readers of the target can still tell the
+    /// file type.
+    pub fn mask_filename<F>(mut self, lambda: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.config.filename_lambda = Some(Arc::new(lambda));
+        self
+    }
+
+    /// Dotted paths of the `<bucket>.files` documents holding sensitive values, checked
+    /// for source values on the target along with the paths of the metadata rules and
+    /// a masked `filename` when `ReplicationManagerBuilder::detect_leaks` is enabled
+    pub fn sensitive_paths(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config.sensitive_paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn build(self) -> GridFsConfig {
+        self.config
+    }
+}
@@ -0,0 +1,158 @@
+use bson::{Bson, Document};
+use mongodb::IndexModel;
+
+/// Options controlling how indexes are recreated on the target
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexCopyOptions {
+    /// Remove (or skip) index options the target server version does not support
+    pub(crate) strip_unsupported_options: bool,
+    /// Recreate TTL indexes as regular indexes so masked data is not reaped
+    pub(crate) disable_ttl: bool,
+}
+
+/// Server version reported by `buildInfo`, compared as (major, minor)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ServerVersion {
+    pub major: i32,
+    pub minor: i32,
+}
+
+impl ServerVersion {
+    pub const fn new(major: i32, minor: i32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parses the `versionArray` field of a `buildInfo` response
+    pub(crate) fn from_build_info(build_info: &Document) -> Option<Self> {
+        let version = build_info.get_array("versionArray").ok()?;
+        let part = |index: usize| match version.get(index) {
+            Some(Bson::Int32(value)) => Some(*value),
+            Some(Bson::Int64(value)) => Some(*value as i32),
+            _ => None,
+        };
+        Some(Self::new(part(0)?, part(1)?))
+    }
+}
+
+/// Result of recreating the indexes of one collection
+#[derive(Debug, Default)]
+pub(crate) struct IndexCopyResult {
+    pub(crate) created: Vec<String>,
+    pub(crate) failed: Vec<(String, String)>,
+}
+
+/// Returns the name of the index, falling back to the key specification
+pub(crate) fn index_name(index: &IndexModel) -> String {
+    index
+        .options
+        .as_ref()
+        .and_then(|options| options.name.clone())
+        .unwrap_or_else(|| format!("{:?}", index.keys))
+}
+
+/// Returns true for indexes created implicitly alongside the collection
+/// (the `_id` index and the clustered index of clustered collections)
+pub(crate) fn is_implicit_index(index: &IndexModel) -> bool {
+    let is_id_index = index.keys.len() == 1 && index.keys.contains_key("_id");
+    let is_clustered = index
+        .options
+        .as_ref()
+        .and_then(|options| options.clustered())
+        .unwrap_or(false);
+
+    is_id_index || is_clustered
+}
+
+/// Adjusts a source index specification so it can be created on the target.
+/// Returns an error message when the index cannot be created at all.
+pub(crate) fn prepare_index(
+    mut index: IndexModel,
+    copy_options: &IndexCopyOptions,
+    target_version: Option<ServerVersion>,
+) -> Result<IndexModel, String> {
+    if is_text_index(&index.keys) {
+        index.keys = rebuild_text_index_keys(&index);
+    }
+
+    let Some(options) = index.options.as_mut() else {
+        return Ok(index);
+    };
+
+    if copy_options.disable_ttl {
+        options.expire_after = None;
+    }
+
+    if !copy_options.strip_unsupported_options {
+        return Ok(index);
+    }
+
+    // Let the target pick its own default index version
+    options.version = None;
+
+    let Some(target_version) = target_version else {
+        return Ok(index);
+    };
+
+    // Ignored since 4.2
+    if target_version >= ServerVersion::new(4, 2) {
+        options.background = None;
+    }
+
+    // Hidden indexes were added in 4.4
+    if target_version < ServerVersion::new(4, 4) {
+        options.hidden = None;
+    }
+
+    let is_wildcard = index.keys.keys().any(|key| key.ends_with("$**"));
+    if is_wildcard && target_version < ServerVersion::new(4, 2) {
+        return Err("wildcard indexes require MongoDB 4.2 or newer on the target".into());
+    }
+
+    // geoHaystack indexes were removed in 5.0
+    let is_geo_haystack = index
+        .keys
+        .values()
+        .any(|value| value.as_str() == Some("geoHaystack"));
+    if is_geo_haystack && target_version >= ServerVersion::new(5, 0) {
+        return Err("geoHaystack indexes are not supported by MongoDB 5.0 or newer".into());
+    }
+
+    Ok(index)
+}
+
+/// `listIndexes` reports text indexes with the internal `_fts`/`_ftsx` keys
+fn is_text_index(keys: &Document) -> bool {
+    keys.get_str("_fts") == Ok("text")
+}
+
+/// Rebuilds the user facing key specification of a text index from its weights,
+/// preserving any compound prefix and suffix fields.
+fn rebuild_text_index_keys(index: &IndexModel) -> Document {
+    let weights = index
+        .options
+        .as_ref()
+        .and_then(|options| options.weights.clone())
+        .unwrap_or_default();
+
+    let mut keys = Document::new();
+    for (key, value) in index.keys.iter() {
+        match key.as_str() {
+            "_fts" => {
+                for field in weights.keys() {
+                    keys.insert(field, "text");
+                }
+            }
+            "_ftsx" => {}
+            _ => {
+                keys.insert(key, value.clone());
+            }
+        }
+    }
+
+    // Wildcard text indexes report a single `$**` weight which maps back directly
+    if keys.is_empty() {
+        return index.keys.clone();
+    }
+
+    keys
+}
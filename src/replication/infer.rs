@@ -0,0 +1,169 @@
+use bson::{Bson, Document};
+use std::collections::BTreeMap;
+
+/// A field found in the sampled documents of a collection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldSchema {
+    /// Dotted path, the fields of documents in an array share the path of the array
+    pub path: String,
+    /// Documents or embedded documents holding the field
+    pub occurrences: u64,
+    /// Documents or embedded documents which could have held the field: every
+    /// sampled document for a top level field, the embedded documents otherwise
+    pub parents: u64,
+    /// `$type` aliases of the values and how many times they were seen
+    pub types: BTreeMap<String, u64>,
+    /// `$type` aliases of the elements of the arrays held by the field
+    pub element_types: BTreeMap<String, u64>,
+}
+
+impl FieldSchema {
+    /// Whether the field is missing or null in some documents
+    pub fn is_optional(&self) -> bool {
+        self.occurrences < self.parents || is_null_type(&self.types)
+    }
+
+    /// Types of the values without `null`, `array` standing for arrays of any element
+    pub fn value_types(&self) -> Vec<&str> {
+        non_null_types(&self.types)
+    }
+
+    /// Types of the array elements without `null`
+    pub fn element_value_types(&self) -> Vec<&str> {
+        non_null_types(&self.element_types)
+    }
+
+    /// Types joined by ` | `, arrays with the types of their elements, e.g.
+    /// `array<string | int> | null`
+    pub fn type_description(&self) -> String {
+        self.types
+            .keys()
+            .map(|type_name| match type_name.as_str() {
+                "array" if !self.element_types.is_empty() => format!(
+                    "array<{}>",
+                    self.element_types
+                        .keys()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                ),
+                type_name => type_name.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+fn is_null_type(types: &BTreeMap<String, u64>) -> bool {
+    types.contains_key("null") || types.contains_key("undefined")
+}
+
+fn non_null_types(types: &BTreeMap<String, u64>) -> Vec<&str> {
+    types
+        .keys()
+        .map(String::as_str)
+        .filter(|type_name| !matches!(*type_name, "null" | "undefined"))
+        .collect()
+}
+
+/// Infers the fields of a collection from its sampled documents
+#[derive(Debug, Default)]
+pub(crate) struct SchemaInference {
+    /// Documents seen per path, the sampled documents under the empty path
+    documents: BTreeMap<String, u64>,
+    fields: BTreeMap<String, FieldSchema>,
+}
+
+impl SchemaInference {
+    pub(crate) fn add_document(&mut self, document: &Document) {
+        self.add_embedded_document(document, "");
+    }
+
+    fn add_embedded_document(&mut self, document: &Document, prefix: &str) {
+        *self.documents.entry(prefix.to_string()).or_default() += 1;
+        for (key, value) in document.iter() {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+            self.field(&path).occurrences += 1;
+            self.add_value(value, &path);
+        }
+    }
+
+    fn add_value(&mut self, value: &Bson, path: &str) {
+        *self
+            .field(path)
+            .types
+            .entry(type_name(value).to_string())
+            .or_default() += 1;
+
+        match value {
+            Bson::Document(document) => self.add_embedded_document(document, path),
+            Bson::Array(elements) => {
+                for element in elements.iter() {
+                    *self
+                        .field(path)
+                        .element_types
+                        .entry(type_name(element).to_string())
+                        .or_default() += 1;
+                    // Nested arrays are only described by their type
+                    if let Bson::Document(document) = element {
+                        self.add_embedded_document(document, path);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn field(&mut self, path: &str) -> &mut FieldSchema {
+        self.fields
+            .entry(path.to_string())
+            .or_insert_with(|| FieldSchema {
+                path: path.to_string(),
+                ..Default::default()
+            })
+    }
+
+    /// Fields ordered by path
+    pub(crate) fn fields(self) -> Vec<FieldSchema> {
+        let documents = self.documents;
+        self.fields
+            .into_values()
+            .map(|mut field| {
+                let parent = field.path.rsplit_once('.').map_or("", |(parent, _)| parent);
+                field.parents = documents.get(parent).copied().unwrap_or_default();
+                field
+            })
+            .collect()
+    }
+}
+
+/// `$type` alias of a value
+fn type_name(value: &Bson) -> &'static str {
+    match value {
+        Bson::Double(_) => "double",
+        Bson::String(_) => "string",
+        Bson::Document(_) => "object",
+        Bson::Array(_) => "array",
+        Bson::Binary(_) => "binData",
+        Bson::Undefined => "undefined",
+        Bson::ObjectId(_) => "objectId",
+        Bson::Boolean(_) => "bool",
+        Bson::DateTime(_) => "date",
+        Bson::Null => "null",
+        Bson::RegularExpression(_) => "regex",
+        Bson::DbPointer(_) => "dbPointer",
+        Bson::JavaScriptCode(_) => "javascript",
+        Bson::Symbol(_) => "symbol",
+        Bson::JavaScriptCodeWithScope(_) => "javascriptWithScope",
+        Bson::Int32(_) => "int",
+        Bson::Timestamp(_) => "timestamp",
+        Bson::Int64(_) => "long",
+        Bson::Decimal128(_) => "decimal",
+        Bson::MinKey => "minKey",
+        Bson::MaxKey => "maxKey",
+    }
+}
@@ -0,0 +1,492 @@
+use super::dump::{apply_stage, cursor_from_documents, find_cursor};
+use super::filter::{compare_ids, Filter};
+use super::indexes::ServerVersion;
+use super::schema::CollectionValidator;
+use super::sink::Sink;
+use super::source::{DocumentCursor, Source};
+use crate::{TuxedoError, TuxedoResult};
+use async_trait::async_trait;
+use bson::{doc, Bson, Document, RawDocumentBuf};
+use mongodb::options::{AggregateOptions, CreateCollectionOptions, FindOptions, InsertManyOptions};
+use mongodb::results::CollectionSpecification;
+use mongodb::IndexModel;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const JSONL_EXTENSION: &str = ".jsonl";
+
+/// Flavour of Extended JSON written by the JSON Lines sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExtendedJsonMode {
+    /// Every value keeps its exact BSON type, e.g. `{"$numberInt": "1"}`
+    #[default]
+    Canonical,
+    /// Easier to read, numbers and dates are written as plain JSON where possible.
+    /// Integers read back as the smallest integer type holding them.
+    Relaxed,
+}
+
+/// Writes every collection as Extended JSON Lines, one `<collection>.jsonl` file per
+/// collection with one document per line, meant for fixture datasets kept in git.
+///
+/// Documents are buffered in memory and written ordered by `_id` once the run
+/// finishes, so repeated runs produce identical files. Indexes, collection options
+/// and views are not written.
+#[derive(Debug)]
+pub(crate) struct JsonlSink {
+    directory: PathBuf,
+    mode: ExtendedJsonMode,
+    collections: Mutex<BTreeMap<String, Vec<Document>>>,
+}
+
+impl JsonlSink {
+    pub(crate) fn new(directory: impl Into<PathBuf>, mode: ExtendedJsonMode) -> Self {
+        Self {
+            directory: directory.into(),
+            mode,
+            collections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn write_collection(&self, collection_name: &str, documents: &[Document]) -> TuxedoResult<()> {
+        let path = self
+            .directory
+            .join(format!("{collection_name}{JSONL_EXTENSION}"));
+        let mut writer = BufWriter::new(File::create(path)?);
+        for document in documents.iter() {
+            let document = Bson::Document(document.clone());
+            let json = match self.mode {
+                ExtendedJsonMode::Canonical => document.into_canonical_extjson(),
+                ExtendedJsonMode::Relaxed => document.into_relaxed_extjson(),
+            };
+            serde_json::to_writer(&mut writer, &json)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for JsonlSink {
+    async fn write(
+        &self,
+        collection_name: &str,
+        documents: &[RawDocumentBuf],
+        _options: Option<InsertManyOptions>,
+    ) -> TuxedoResult<()> {
+        let documents = documents
+            .iter()
+            .map(|document| bson::from_slice::<Document>(document.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.collections
+            .lock()
+            .expect("JSON Lines collections poisoned")
+            .entry(collection_name.to_string())
+            .or_default()
+            .extend(documents);
+        Ok(())
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        _options: CreateCollectionOptions,
+    ) -> TuxedoResult<()> {
+        self.collections
+            .lock()
+            .expect("JSON Lines collections poisoned")
+            .entry(collection_name.to_string())
+            .or_default();
+        Ok(())
+    }
+
+    async fn apply_validator(
+        &self,
+        _collection_name: &str,
+        _validator: &CollectionValidator,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn create_indexes(
+        &self,
+        _collection_name: &str,
+        _indexes: Vec<IndexModel>,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn create_view(
+        &self,
+        _view_name: &str,
+        _view_on: &str,
+        _pipeline: Vec<Document>,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> TuxedoResult<()> {
+        self.collections
+            .lock()
+            .expect("JSON Lines collections poisoned")
+            .remove(collection_name);
+
+        let path = self
+            .directory
+            .join(format!("{collection_name}{JSONL_EXTENSION}"));
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        let mut names = list_jsonl_collections(&self.directory)?;
+        names.extend(
+            self.collections
+                .lock()
+                .expect("JSON Lines collections poisoned")
+                .keys()
+                .cloned(),
+        );
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    async fn server_version(&self) -> Option<ServerVersion> {
+        None
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        fs::create_dir_all(&self.directory).map_err(|e| {
+            TuxedoError::ConfigError(format!(
+                "Could not create JSON Lines directory `{}`: {e}",
+                self.directory.display()
+            ))
+        })
+    }
+
+    /// Writes every collection ordered by `_id`
+    async fn finish(&self) -> TuxedoResult<()> {
+        let collections = std::mem::take(
+            &mut *self
+                .collections
+                .lock()
+                .expect("JSON Lines collections poisoned"),
+        );
+
+        for (collection_name, mut documents) in collections {
+            documents.sort_by(|a, b| compare_ids(a.get("_id"), b.get("_id")));
+            self.write_collection(&collection_name, &documents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads collections written by the JSON Lines sink, or any `<collection>.jsonl`
+/// file holding one Extended JSON document (canonical or relaxed) per line.
+///
+/// Files are read into memory as a whole, queries are evaluated in memory.
+#[derive(Debug)]
+pub(crate) struct JsonlSource {
+    directory: PathBuf,
+}
+
+impl JsonlSource {
+    /// `path` is either the directory holding the `.jsonl` files or a directory
+    /// holding a `<database>` directory of them
+    pub(crate) fn open(path: impl Into<PathBuf>, database: &str) -> TuxedoResult<Self> {
+        let path = path.into();
+        let directory = if path.join(database).is_dir() {
+            path.join(database)
+        } else if path.is_dir() {
+            path
+        } else {
+            return Err(TuxedoError::ConfigError(format!(
+                "JSON Lines directory `{}` does not exist",
+                path.display()
+            )));
+        };
+        Ok(Self { directory })
+    }
+
+    fn file_path(&self, collection_name: &str) -> PathBuf {
+        self.directory
+            .join(format!("{collection_name}{JSONL_EXTENSION}"))
+    }
+
+    /// Reads every document of a collection matching `filter`
+    async fn collect(&self, collection_name: &str, filter: Filter) -> TuxedoResult<Vec<Document>> {
+        let path = self.file_path(collection_name);
+        tokio::task::spawn_blocking(move || {
+            let mut documents = read_jsonl(&path)?;
+            documents.retain(|document| filter.matches(document));
+            Ok(documents)
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl Source for JsonlSource {
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let documents = self
+            .collect(collection_name, Filter::parse(&query)?)
+            .await?;
+        find_cursor(documents, options)
+    }
+
+    /// Supports the same stages as a dump: `$match`, `$project`, `$sort`, `$skip`,
+    /// `$limit`, `$sample` and `$count`
+    async fn aggregate(
+        &self,
+        collection_name: &str,
+        pipeline: Vec<Document>,
+        _options: Option<AggregateOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let mut documents = self
+            .collect(collection_name, Filter::parse(&Document::new())?)
+            .await?;
+        for stage in pipeline.iter() {
+            documents = apply_stage(documents, stage)?;
+        }
+        Ok(cursor_from_documents(documents.into_iter()))
+    }
+
+    async fn count_documents(&self, collection_name: &str, query: Document) -> TuxedoResult<u64> {
+        let documents = self
+            .collect(collection_name, Filter::parse(&query)?)
+            .await?;
+        Ok(documents.len() as u64)
+    }
+
+    async fn average_document_size(&self, collection_name: &str) -> TuxedoResult<u64> {
+        let documents = self
+            .collect(collection_name, Filter::parse(&Document::new())?)
+            .await?;
+        let total_size: usize = documents
+            .iter()
+            .map(|document| bson::to_vec(document).map_or(0, |bytes| bytes.len()))
+            .sum();
+        Ok((total_size as u64)
+            .checked_div(documents.len() as u64)
+            .unwrap_or(1024))
+    }
+
+    async fn collection_specification(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<Option<CollectionSpecification>> {
+        if !self.file_path(collection_name).is_file() {
+            return Ok(None);
+        }
+        let specification = doc! {
+            "name": collection_name,
+            "type": "collection",
+            "options": {},
+            "info": { "readOnly": false },
+        };
+        Ok(Some(bson::from_document(specification)?))
+    }
+
+    async fn list_indexes(&self, _collection_name: &str) -> TuxedoResult<Vec<IndexModel>> {
+        Ok(Vec::new())
+    }
+
+    async fn list_views(&self) -> TuxedoResult<Vec<CollectionSpecification>> {
+        Ok(Vec::new())
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        let mut names = list_jsonl_collections(&self.directory)?;
+        names.sort();
+        Ok(names)
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        list_jsonl_collections(&self.directory).map(|_| ())
+    }
+
+    fn supports_partitioned_reads(&self) -> bool {
+        false
+    }
+}
+
+fn list_jsonl_collections(directory: &Path) -> TuxedoResult<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(name) = file_name.strip_suffix(JSONL_EXTENSION) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Reads a JSON Lines file, a missing file holds no documents. Blank lines are skipped.
+fn read_jsonl(path: &Path) -> TuxedoResult<Vec<Document>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut documents = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let value: serde_json::Value = serde_json::from_str(&line)?;
+        match Bson::try_from(value) {
+            Ok(Bson::Document(document)) => documents.push(document),
+            Ok(_) => {
+                return Err(TuxedoError::Generic(format!(
+                    "Line {} of `{}` is not a document",
+                    number + 1,
+                    path.display()
+                )))
+            }
+            Err(e) => {
+                return Err(TuxedoError::Generic(format!(
+                    "Invalid Extended JSON on line {} of `{}`: {e}",
+                    number + 1,
+                    path.display()
+                )))
+            }
+        }
+    }
+    Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::spec::BinarySubtype;
+    use bson::{oid::ObjectId, Binary, DateTime, Decimal128};
+    use std::str::FromStr;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("tuxedo-jsonl-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn raw(documents: &[Document]) -> Vec<RawDocumentBuf> {
+        documents
+            .iter()
+            .map(|document| RawDocumentBuf::from_document(document).unwrap())
+            .collect()
+    }
+
+    async fn read_all(source: &JsonlSource, collection_name: &str) -> Vec<Document> {
+        let mut cursor = source
+            .find(collection_name, Document::new(), None)
+            .await
+            .unwrap();
+        let mut documents = Vec::new();
+        while cursor.advance().await.unwrap() {
+            documents.push(cursor.deserialize_current().unwrap());
+        }
+        documents
+    }
+
+    async fn round_trip(mode: ExtendedJsonMode, name: &str) {
+        let directory = temp_directory(name);
+        let documents = vec![
+            doc! {
+                "_id": ObjectId::parse_str("64b7f0c2a1b2c3d4e5f60718").unwrap(),
+                "price": Decimal128::from_str("1234.5600").unwrap(),
+                "created": DateTime::from_millis(1_700_000_000_123),
+                "payload": Binary { subtype: BinarySubtype::Generic, bytes: vec![0, 1, 2, 255] },
+                "uuid": Binary { subtype: BinarySubtype::Uuid, bytes: vec![7; 16] },
+                "count": 42,
+                "ratio": 0.25,
+                "tags": ["a", "b"],
+                "nested": { "at": DateTime::from_millis(0), "missing": Bson::Null },
+            },
+            doc! {
+                "_id": ObjectId::parse_str("64b7f0c2a1b2c3d4e5f60719").unwrap(),
+                "price": Decimal128::from_str("-0.01").unwrap(),
+                "created": DateTime::from_millis(-86_400_000),
+                "payload": Binary { subtype: BinarySubtype::Generic, bytes: Vec::new() },
+            },
+        ];
+
+        let sink = JsonlSink::new(&directory, mode);
+        sink.test_connection().await.unwrap();
+        sink.write("things", &raw(&documents), None).await.unwrap();
+        sink.finish().await.unwrap();
+
+        let source = JsonlSource::open(&directory, "db").unwrap();
+        assert_eq!(source.list_collection_names().await.unwrap(), ["things"]);
+        assert_eq!(read_all(&source, "things").await, documents);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn round_trips_canonical_extended_json() {
+        round_trip(ExtendedJsonMode::Canonical, "canonical").await;
+    }
+
+    #[tokio::test]
+    async fn round_trips_relaxed_extended_json() {
+        round_trip(ExtendedJsonMode::Relaxed, "relaxed").await;
+    }
+
+    #[tokio::test]
+    async fn writes_documents_ordered_by_id() {
+        let directory = temp_directory("ordered");
+        let sink = JsonlSink::new(&directory, ExtendedJsonMode::Canonical);
+        sink.test_connection().await.unwrap();
+        // Batches arrive in any order from concurrent tasks
+        let batches = [
+            vec![doc! { "_id": 10 }, doc! { "_id": "b" }, doc! { "_id": 2.5 }],
+            vec![
+                doc! { "_id": "a" },
+                doc! { "_id": 3_i64 },
+                doc! { "_id": 1 },
+            ],
+        ];
+        for batch in batches.iter() {
+            sink.write("things", &raw(batch), None).await.unwrap();
+        }
+        sink.finish().await.unwrap();
+
+        let lines = fs::read_to_string(directory.join("things.jsonl")).unwrap();
+        let ids = lines
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                Bson::try_from(value).unwrap().as_document().unwrap()["_id"].clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                Bson::Int32(1),
+                Bson::Double(2.5),
+                Bson::Int64(3),
+                Bson::Int32(10),
+                Bson::String("a".into()),
+                Bson::String("b".into()),
+            ]
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
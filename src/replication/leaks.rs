@@ -0,0 +1,253 @@
+use super::filter::compare_ids;
+use super::report::{Leak, LeakReport, MAX_REPORTED_IDS};
+use super::types::DatabasePair;
+use super::verify::{OrderedReader, Side};
+use crate::paths::{bson_key, values_at_path};
+use crate::TuxedoResult;
+use bson::{Bson, Document};
+use md5::{Digest, Md5};
+use std::collections::{BTreeSet, HashMap};
+use std::f64::consts::LN_2;
+
+/// False positive rate of the filter. Every hit is confirmed against the source, so
+/// false positives only make the confirmation pass longer.
+const FALSE_POSITIVE_RATE: f64 = 0.001;
+
+/// Smallest number of values the filter is sized for
+const MIN_CAPACITY: u64 = 1024;
+
+/// Source documents of a processor and the paths holding sensitive values
+pub(crate) struct LeakScan<'a> {
+    pub(crate) source_collection: &'a str,
+    pub(crate) target_collection: &'a str,
+    pub(crate) query: &'a Document,
+    /// `_id`s the processor was restricted to by a subset or sample
+    pub(crate) ids: Option<&'a [Bson]>,
+    pub(crate) paths: &'a [String],
+}
+
+/// Looks for source values at the sensitive paths of a processor on its target
+/// collection. Source values are added to a Bloom filter, the target is scanned for
+/// values the filter may contain and the source is read again to confirm them, so
+/// only exact matches are reported while memory stays bounded by the filter.
+///
+/// Values are compared at the same path across every document, which also catches
+/// fakes reproducing the real value of another document. Nulls, booleans and empty
+/// strings can't be told apart from masked values and are ignored.
+pub(crate) async fn detect_leaks(
+    dbs: &DatabasePair,
+    scan: LeakScan<'_>,
+    mut report: LeakReport,
+) -> LeakReport {
+    match dbs
+        .read_target_total_documents(scan.target_collection, Document::new())
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return report.skipped("The target can't be read back"),
+        Err(e) => {
+            report.error = Some(format!("Could not count target documents: {e}"));
+            return report;
+        }
+    }
+
+    if let Err(e) = scan_target(dbs, &scan, &mut report).await {
+        report.error = Some(format!("Could not scan for leaks: {e}"));
+    }
+    report
+}
+
+/// Target values found in the filter, with where they were found
+#[derive(Default)]
+struct Candidate {
+    occurrences: u64,
+    /// Up to `MAX_REPORTED_IDS` path indexes and `_id`s of the documents
+    found_at: Vec<(usize, Bson)>,
+}
+
+async fn scan_target(
+    dbs: &DatabasePair,
+    scan: &LeakScan<'_>,
+    report: &mut LeakReport,
+) -> TuxedoResult<()> {
+    let ids = scan.ids.map(|ids| {
+        let mut ids = ids.to_vec();
+        ids.sort_by(|a, b| compare_ids(Some(a), Some(b)));
+        ids
+    });
+    let source_documents = match ids.as_ref() {
+        Some(ids) => ids.len(),
+        None => {
+            dbs.read_total_documents(scan.source_collection, scan.query.clone())
+                .await?
+        }
+    } as u64;
+    let mut filter = BloomFilter::new(
+        (source_documents * scan.paths.len() as u64).max(MIN_CAPACITY),
+        FALSE_POSITIVE_RATE,
+    );
+    let projection = projection(scan.paths);
+
+    let mut source = OrderedReader::new(
+        Side::Source,
+        scan.source_collection,
+        scan.query,
+        ids.as_deref(),
+        Some(projection.clone()),
+    );
+    while let Some(document) = source.next(dbs).await? {
+        for (_, key) in sensitive_values(&document, scan.paths) {
+            filter.insert(&key);
+            report.source_values += 1;
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut candidate_keys: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut target = OrderedReader::new(
+        Side::Target,
+        scan.target_collection,
+        &Document::new(),
+        None,
+        Some(projection.clone()),
+    );
+    while let Some(document) = target.next(dbs).await? {
+        report.documents_scanned += 1;
+        for (path, key) in sensitive_values(&document, scan.paths) {
+            if !filter.contains(&key) {
+                continue;
+            }
+            let index = *candidate_keys.entry(key).or_insert_with(|| {
+                candidates.push(Candidate::default());
+                candidates.len() - 1
+            });
+            let candidate = &mut candidates[index];
+            candidate.occurrences += 1;
+            if candidate.found_at.len() < MAX_REPORTED_IDS {
+                let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+                candidate.found_at.push((path, id));
+            }
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    // Filter hits may be false positives, only values read again are leaks
+    let mut confirmed = vec![false; candidates.len()];
+    let mut source = OrderedReader::new(
+        Side::Source,
+        scan.source_collection,
+        scan.query,
+        ids.as_deref(),
+        Some(projection),
+    );
+    while let Some(document) = source.next(dbs).await? {
+        for (_, key) in sensitive_values(&document, scan.paths) {
+            if let Some(index) = candidate_keys.get(&key) {
+                confirmed[*index] = true;
+            }
+        }
+    }
+
+    let (occurrences, mut found_at): (Vec<u64>, Vec<_>) = candidates
+        .into_iter()
+        .zip(confirmed)
+        .filter(|(_, confirmed)| *confirmed)
+        .map(|(candidate, _)| (candidate.occurrences, candidate.found_at.into_iter()))
+        .unzip();
+
+    // Interleaves the leaked values so a common one doesn't hide the others
+    let mut leaked = Vec::new();
+    while leaked.len() < MAX_REPORTED_IDS {
+        let round: Vec<_> = found_at.iter_mut().filter_map(Iterator::next).collect();
+        if round.is_empty() {
+            break;
+        }
+        leaked.extend(round.into_iter().map(|(path, id)| Leak {
+            path: scan.paths[path].clone(),
+            id,
+        }));
+    }
+    report.add_leaks(occurrences.iter().sum(), leaked);
+    Ok(())
+}
+
+/// Projects the top level fields of the paths, numeric array indexes and
+/// overlapping paths can't be projected as such
+fn projection(paths: &[String]) -> Document {
+    let fields: BTreeSet<&str> = paths
+        .iter()
+        .filter_map(|path| path.split('.').next())
+        .collect();
+
+    let mut projection = Document::new();
+    projection.insert("_id", 1);
+    for field in fields {
+        projection.insert(field, 1);
+    }
+    projection
+}
+
+/// Keys of the values found at every path, prefixed by the index of their path so
+/// values only match at the same path
+fn sensitive_values(document: &Document, paths: &[String]) -> Vec<(usize, Vec<u8>)> {
+    let mut values = Vec::new();
+    for (index, path) in paths.iter().enumerate() {
+        for value in values_at_path(document, path) {
+            match value {
+                Bson::Null | Bson::Undefined | Bson::Boolean(_) => continue,
+                Bson::String(value) if value.is_empty() => continue,
+                _ => {}
+            }
+            let mut key = (index as u32).to_le_bytes().to_vec();
+            key.extend(bson_key(value));
+            values.push((index, key));
+        }
+    }
+    values
+}
+
+/// Set of byte strings answering membership with false positives but no false
+/// negatives, in a fixed amount of memory
+struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u64,
+}
+
+impl BloomFilter {
+    /// Sizes the filter so `capacity` values give the expected false positive rate
+    fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        let bits = (-(capacity as f64) * false_positive_rate.ln() / LN_2.powi(2)).ceil();
+        let words = (bits as u64).div_ceil(64).max(1);
+        let hashes = ((words * 64) as f64 / capacity as f64 * LN_2)
+            .round()
+            .max(1.0);
+
+        Self {
+            bits: vec![0; words as usize],
+            hashes: hashes as u64,
+        }
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        for position in self.positions(key) {
+            self.bits[(position / 64) as usize] |= 1 << (position % 64);
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.positions(key)
+            .all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    /// Double hashing over both halves of the key's digest
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let digest = Md5::digest(key);
+        let first = u64::from_le_bytes(digest[..8].try_into().expect("Digest is 16 bytes"));
+        let second = u64::from_le_bytes(digest[8..].try_into().expect("Digest is 16 bytes")) | 1;
+        let size = self.bits.len() as u64 * 64;
+
+        (0..self.hashes).map(move |i| first.wrapping_add(i.wrapping_mul(second)) % size)
+    }
+}
@@ -1,13 +1,29 @@
+use super::indexes::IndexCopyOptions;
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+use super::plan::RunPlan;
+use super::progress::{Progress, ProgressEvent, ProgressListener};
+use super::report::{
+    CollectionStats, ErrorStage, LeakReport, RunReport, VerificationReport, ViewOutcome, ViewReport,
+};
+use super::scan::{scan_collection, ScanConfig, ScanReport};
+use super::schema::{self, DeferredValidator};
+use super::subset::{compute_subset, SubsetConfig};
+use super::views::plan_view_creation;
 use super::{processor::Processor, task::Task};
 use crate::replication::types::{DatabasePair, ReplicationStrategy};
-use crate::TuxedoResult;
+use crate::{TuxedoError, TuxedoResult};
+use bson::{Bson, Document};
 use futures_util::future::join_all;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use futures_util::stream::{self, StreamExt};
 use mongodb::options::{FindOptions, InsertManyOptions};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc;
 use tokio::task;
-use tokio::task::JoinSet;
+use tokio::task::{JoinError, JoinSet};
+use tracing::{error, info, info_span, warn, Instrument};
 
 #[derive(Debug, Clone)]
 pub(crate) struct ReplicationConfig {
@@ -19,6 +35,13 @@ pub(crate) struct ReplicationConfig {
     pub(crate) write_options: InsertManyOptions,
     pub(crate) read_options: FindOptions,
     pub(crate) copy_views: bool,
+    pub(crate) copy_collection_options: bool,
+    pub(crate) defer_validators: bool,
+    pub(crate) strip_unsupported_index_options: bool,
+    pub(crate) disable_ttl_indexes: bool,
+    pub(crate) skip_views_with_missing_dependencies: bool,
+    pub(crate) verify: bool,
+    pub(crate) detect_leaks: bool,
 }
 
 impl Default for ReplicationConfig {
@@ -35,6 +58,13 @@ impl Default for ReplicationConfig {
             read_options: Default::default(),
             adaptive_batching: false,
             copy_views: false,
+            copy_collection_options: true,
+            defer_validators: false,
+            strip_unsupported_index_options: false,
+            disable_ttl_indexes: false,
+            skip_views_with_missing_dependencies: true,
+            verify: false,
+            detect_leaks: false,
         }
     }
 }
@@ -44,70 +74,196 @@ pub struct ReplicationManager {
     pub(crate) task_receiver: mpsc::Receiver<Box<dyn Task>>,
     pub(crate) task_sender: mpsc::Sender<Box<dyn Task>>,
     pub(crate) config: ReplicationConfig,
-    pub(crate) dbs: Arc<DatabasePair>,
+    /// Source/target database pairs keyed by mapping name, sharing the same clients
+    pub(crate) databases: HashMap<String, Arc<DatabasePair>>,
+    pub(crate) subset: Option<SubsetConfig>,
+    pub(crate) progress: Arc<dyn ProgressListener>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<Metrics>,
 }
 
 impl ReplicationManager {
-    pub async fn run(self) -> TuxedoResult<()> {
-        let multi_progress = Arc::new(MultiProgress::new());
-        let progress_style = ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-        )
-        .expect("Expected to set progress bar styling")
-        .progress_chars("█▓▒░");
+    /// Maps each source collection name to the target collection it is written to
+    /// within the given database mapping.
+    /// When a source collection is processed more than once the first mapping wins.
+    fn collection_mapping(&self, database_name: &str) -> HashMap<String, String> {
+        let mut mapping = HashMap::new();
+        for processor in self
+            .processors
+            .iter()
+            .filter(|p| p.database_name() == database_name)
+        {
+            mapping
+                .entry(processor.collection_name().to_string())
+                .or_insert_with(|| processor.target_collection_name().to_string());
+        }
+        mapping
+    }
+
+    fn database_pair(&self, processor: &dyn Processor) -> TuxedoResult<Arc<DatabasePair>> {
+        self.databases
+            .get(processor.database_name())
+            .cloned()
+            .ok_or_else(|| {
+                TuxedoError::ConfigError(format!(
+                    "Collection `{}` is bound to unknown database mapping `{}`",
+                    processor.collection_name(),
+                    processor.database_name()
+                ))
+            })
+    }
+
+    /// Runs every processor, then copies indexes and views. Everything is logged
+    /// under a `run` span, with a span per collection, task and written batch.
+    #[tracing::instrument(name = "run", skip_all)]
+    pub async fn run(self) -> TuxedoResult<RunReport> {
+        let started_at = Instant::now();
+
+        // Views referencing renamed collections need to point at the target names
+        let collection_mappings: HashMap<String, HashMap<String, String>> = self
+            .databases
+            .keys()
+            .map(|name| (name.clone(), self.collection_mapping(name)))
+            .collect();
+
+        let progress = Progress::new(Arc::clone(&self.progress));
+
+        let stats: Vec<Arc<CollectionStats>> = self
+            .processors
+            .iter()
+            .map(|processor| {
+                let stats = CollectionStats::new(
+                    processor.database_name(),
+                    processor.collection_name(),
+                    processor.target_collection_name(),
+                    progress.clone(),
+                );
+                #[cfg(feature = "metrics")]
+                let stats = match self.metrics.as_ref() {
+                    Some(metrics) => stats.with_metrics(metrics.collection(
+                        processor.database_name(),
+                        processor.collection_name(),
+                        processor.target_collection_name(),
+                    )),
+                    None => stats,
+                };
+                Arc::new(stats)
+            })
+            .collect();
+
+        let processor_dbs: Vec<Arc<DatabasePair>> = self
+            .processors
+            .iter()
+            .map(|processor| self.database_pair(processor.as_ref().as_ref()))
+            .collect::<TuxedoResult<_>>()?;
+
+        // Restrict the processors of the subset's collections to the related documents
+        let subset_ids = match self.subset.as_ref() {
+            Some(subset) => {
+                info!("Computing subset");
+                let dbs = self.databases.get(subset.database_name()).ok_or_else(|| {
+                    TuxedoError::ConfigError(format!(
+                        "Subset is bound to unknown database mapping `{}`",
+                        subset.database_name()
+                    ))
+                })?;
+                compute_subset(dbs, subset)
+                    .await?
+                    .into_iter()
+                    .map(|(collection, ids)| (collection, Arc::new(ids)))
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+        let processor_ids: Vec<Option<Arc<Vec<Bson>>>> = self
+            .processors
+            .iter()
+            .map(|processor| {
+                let in_subset = self
+                    .subset
+                    .as_ref()
+                    .is_some_and(|subset| subset.database_name() == processor.database_name());
+                if in_subset {
+                    subset_ids.get(processor.collection_name()).cloned()
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        // Create the target collections with the source options before any data is written
+        let deferred_validators = if self.config.copy_collection_options {
+            info!("Creating target collections");
+            self.create_target_collections(&processor_dbs, &stats).await
+        } else {
+            Vec::new()
+        };
 
         // Spawn processor runners
-        let processor_handles: Vec<_> = self
+        let mut processor_handles = Vec::with_capacity(self.processors.len());
+        for (((processor_arc, stats), dbs), ids) in self
             .processors
             .iter()
-            .map(|processor_arc| {
-                let dbs = Arc::clone(&self.dbs);
-                let task_sender = self.task_sender.clone();
-                let default_config = self.config.clone();
-                let progress_bar = multi_progress.add(ProgressBar::new(0));
-                let processor = Arc::clone(processor_arc);
-                progress_bar.set_style(progress_style.clone());
-
-                task::spawn(async move {
+            .zip(stats.iter())
+            .zip(processor_dbs.iter())
+            .zip(processor_ids)
+        {
+            let dbs = Arc::clone(dbs);
+            let task_sender = self.task_sender.clone();
+            let default_config = self.config.clone();
+            let processor = Arc::clone(processor_arc);
+            let stats = Arc::clone(stats);
+            let span = info_span!(
+                "collection",
+                database = processor.database_name(),
+                collection = processor.collection_name(),
+                target_collection = processor.target_collection_name(),
+            );
+
+            processor_handles.push(task::spawn(
+                async move {
                     processor
-                        .run(dbs, task_sender, default_config, progress_bar)
+                        .run(dbs, task_sender, default_config, stats, ids)
                         .await;
-                })
-            })
-            .collect();
+                }
+                .instrument(span),
+            ));
+        }
 
         // Spawn ReplicationTask runners
         let runner_handle = task::spawn({
             let mut task_receiver = self.task_receiver;
+            #[cfg(feature = "metrics")]
+            let metrics = self.metrics.clone();
             async move {
                 let mut join_set = JoinSet::new();
 
                 loop {
                     tokio::select! {
                         Some(task) = task_receiver.recv() => {
+                            let span = task.span();
+                            #[cfg(feature = "metrics")]
+                            let in_flight = metrics.as_ref().map(Metrics::task_started);
                             join_set.spawn(async move {
-                                task.run().await
-                            });
+                                task.run().await;
+                                #[cfg(feature = "metrics")]
+                                drop(in_flight);
+                            }.instrument(span));
                         }
                         else => break,
                     }
 
                     while join_set.len() >= self.config.thread_count {
-                        if join_set
-                            .join_next()
-                            .await
-                            .transpose()
-                            .expect("Transpose failed")
-                            .is_none()
-                        {
+                        if join_set.join_next().await.transpose()?.is_none() {
                             break;
                         }
                     }
                 }
 
                 while let Some(result) = join_set.join_next().await {
-                    result.expect("Join next failed");
+                    result?;
                 }
+                Ok::<_, JoinError>(())
             }
         });
 
@@ -121,21 +277,36 @@ impl ReplicationManager {
         drop(self.task_sender);
 
         // Wait for the task runner to finish running all the tasks
-        runner_handle.await.expect("Runner failed");
+        runner_handle.await??;
 
         // Iterate the processors again and call copy_indexes in individual threads
         // We do this after all the other data has transferred to prevent the overhead
         // of validations on every insert
-        println!("Copying Indexes...");
+        info!("Copying indexes");
+
+        let index_copy_options = IndexCopyOptions {
+            strip_unsupported_options: self.config.strip_unsupported_index_options,
+            disable_ttl: self.config.disable_ttl_indexes,
+        };
 
         let copy_index_handles: Vec<_> = self
             .processors
-            .into_iter()
-            .map(|processor| {
-                let dbs = Arc::clone(&self.dbs);
-                tokio::spawn(async move {
-                    processor.copy_indexes(&dbs).await;
-                })
+            .iter()
+            .zip(processor_dbs.iter())
+            .zip(stats.iter())
+            .map(|((processor, dbs), stats)| {
+                let dbs = Arc::clone(dbs);
+                let processor = Arc::clone(processor);
+                let stats = Arc::clone(stats);
+                let index_copy_options = index_copy_options.clone();
+                tokio::spawn(
+                    async move {
+                        processor
+                            .copy_indexes(&dbs, &index_copy_options, &stats)
+                            .await;
+                    }
+                    .in_current_span(),
+                )
             })
             .collect();
 
@@ -145,42 +316,540 @@ impl ReplicationManager {
             .into_iter()
             .collect::<Result<Vec<()>, _>>()?;
 
+        // Validators are applied last so they don't slow down or reject the load
+        if !deferred_validators.is_empty() {
+            info!("Applying deferred validators");
+            Self::apply_deferred_validators(deferred_validators).await;
+        }
+
         // Copy views if enabled
+        let mut views = Vec::new();
         if self.config.copy_views {
-            println!("Copying Views...");
+            info!("Copying views");
+
+            for (database_name, dbs) in self.databases.iter() {
+                let collection_mapping = collection_mappings
+                    .get(database_name)
+                    .cloned()
+                    .unwrap_or_default();
+                let outcomes = Self::copy_views(
+                    dbs,
+                    collection_mapping,
+                    self.config.skip_views_with_missing_dependencies,
+                )
+                .await;
+                for (name, outcome) in outcomes.into_iter() {
+                    progress.emit(ProgressEvent::ViewCopied {
+                        database: database_name.clone(),
+                        view: name.clone(),
+                        outcome: outcome.clone(),
+                    });
+                    views.push(ViewReport {
+                        database: database_name.clone(),
+                        name,
+                        outcome,
+                    });
+                }
+            }
+        }
+
+        // File sinks only become complete once everything has been written
+        for dbs in self.databases.values() {
+            dbs.finish_target().await?;
+        }
+
+        let verifications = if self.config.verify {
+            info!("Verifying collections");
+            Self::verify(
+                &self.processors,
+                &processor_dbs,
+                &stats,
+                &self.config.strategy,
+            )
+            .await?
+        } else {
+            Vec::new()
+        };
+
+        let leaks = if self.config.detect_leaks {
+            info!("Scanning for leaks");
+            Self::detect_leaks(&self.processors, &processor_dbs, &stats).await?
+        } else {
+            Vec::new()
+        };
+
+        let report = RunReport {
+            collections: stats.iter().map(|stats| stats.report()).collect(),
+            views,
+            verifications,
+            leaks,
+            elapsed: started_at.elapsed(),
+        };
+        progress.emit(ProgressEvent::Finished {
+            documents_written: report.documents_written(),
+            elapsed: report.elapsed,
+            success: report.is_success(),
+        });
+
+        Ok(report)
+    }
+
+    /// Samples the documents of every source collection, in every database mapping,
+    /// and looks for personal information in their string values. Each finding is
+    /// flagged as uncovered when the processors of the collection would write the
+    /// detected values unmasked, or when no processor copies the collection. Nothing
+    /// is written to the target.
+    #[tracing::instrument(name = "scan", skip_all)]
+    pub async fn scan(&self, config: ScanConfig) -> TuxedoResult<ScanReport> {
+        let started_at = Instant::now();
+        let detector = config.detector();
+
+        let mut database_names: Vec<&String> = self.databases.keys().collect();
+        database_names.sort();
+
+        let mut collections = Vec::new();
+        for database_name in database_names {
+            let dbs = &self.databases[database_name];
+            let processors: Vec<&dyn Processor> = self
+                .processors
+                .iter()
+                .map(|processor| processor.as_ref().as_ref())
+                .filter(|processor| processor.database_name() == database_name)
+                .collect();
+
+            let mut collection_names: BTreeSet<String> =
+                dbs.list_source_collections().await?.into_iter().collect();
+            collection_names.extend(
+                processors
+                    .iter()
+                    .map(|processor| processor.collection_name().to_string()),
+            );
+            // GridFS chunks only hold file content
+            collection_names.retain(|name| !name.ends_with(".chunks"));
+
+            let scans: Vec<_> = stream::iter(collection_names.iter())
+                .map(|collection_name| {
+                    let processors: Vec<&dyn Processor> = processors
+                        .iter()
+                        .filter(|processor| processor.collection_name() == collection_name)
+                        .copied()
+                        .collect();
+                    let (config, detector) = (&config, &detector);
+                    async move {
+                        info!(
+                            database = %database_name,
+                            collection = %collection_name,
+                            "Scanning collection"
+                        );
+                        scan_collection(
+                            dbs,
+                            database_name,
+                            collection_name,
+                            &processors,
+                            &self.config.strategy,
+                            config,
+                            detector,
+                        )
+                        .await
+                    }
+                })
+                .buffered(self.config.thread_count.max(1))
+                .collect()
+                .await;
+            collections.extend(scans);
+        }
+
+        let report = ScanReport {
+            collections,
+            elapsed: started_at.elapsed(),
+        };
+        for (collection, finding) in report.uncovered() {
+            warn!(
+                database = %collection.database,
+                collection = %collection.collection,
+                path = %finding.path,
+                kind = %finding.kind,
+                hit_rate = finding.hit_rate(),
+                "Personal information is not masked"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Describes what `run` would copy: the source and target collection of every
+    /// processor, whether it masks, and how many documents its query matches on the
+    /// source and already holds on the target. Nothing is written.
+    pub async fn plan(&self) -> TuxedoResult<RunPlan> {
+        let mut collections = Vec::with_capacity(self.processors.len());
+        for processor in self.processors.iter() {
+            let dbs = self.database_pair(processor.as_ref().as_ref())?;
+            let mut plan = processor.plan(&self.config.strategy);
+
+            match dbs
+                .read_total_documents(&plan.source_collection, plan.query.clone())
+                .await
+            {
+                Ok(count) => plan.source_documents = Some(count as u64),
+                Err(e) => plan.error = Some(format!("Could not count source documents: {e}")),
+            }
+            match dbs
+                .read_target_total_documents(&plan.target_collection, Document::new())
+                .await
+            {
+                Ok(count) => plan.target_documents = count,
+                Err(e) => plan.error = Some(format!("Could not count target documents: {e}")),
+            }
+            collections.push(plan);
+        }
+
+        Ok(RunPlan {
+            strategy: self.config.strategy.clone(),
+            collections,
+            subset: self.subset.is_some(),
+        })
+    }
+
+    /// Verifies the target written by an earlier run without copying anything, and
+    /// scans it for leaks when `detect_leaks` is enabled. Samples and subsets are
+    /// selected during the run, so their collections are compared in full and report
+    /// the documents left out as missing.
+    #[tracing::instrument(name = "verify", skip_all)]
+    pub async fn verify_target(&self) -> TuxedoResult<RunReport> {
+        let started_at = Instant::now();
+        let progress = Progress::new(Arc::clone(&self.progress));
+
+        let stats: Vec<Arc<CollectionStats>> = self
+            .processors
+            .iter()
+            .map(|processor| {
+                Arc::new(CollectionStats::new(
+                    processor.database_name(),
+                    processor.collection_name(),
+                    processor.target_collection_name(),
+                    progress.clone(),
+                ))
+            })
+            .collect();
+        let processor_dbs: Vec<Arc<DatabasePair>> = self
+            .processors
+            .iter()
+            .map(|processor| self.database_pair(processor.as_ref().as_ref()))
+            .collect::<TuxedoResult<_>>()?;
+
+        info!("Verifying collections");
+        let verifications = Self::verify(
+            &self.processors,
+            &processor_dbs,
+            &stats,
+            &self.config.strategy,
+        )
+        .await?;
+        let leaks = if self.config.detect_leaks {
+            info!("Scanning for leaks");
+            Self::detect_leaks(&self.processors, &processor_dbs, &stats).await?
+        } else {
+            Vec::new()
+        };
+
+        Ok(RunReport {
+            collections: Vec::new(),
+            views: Vec::new(),
+            verifications,
+            leaks,
+            elapsed: started_at.elapsed(),
+        })
+    }
+
+    /// Verification phase: compares the source documents of every processor with its
+    /// target collection. Counts are only compared for target collections written by
+    /// a single processor.
+    async fn verify(
+        processors: &[Arc<Box<dyn Processor>>],
+        processor_dbs: &[Arc<DatabasePair>],
+        stats: &[Arc<CollectionStats>],
+        strategy: &ReplicationStrategy,
+    ) -> TuxedoResult<Vec<VerificationReport>> {
+        let mut writers: HashMap<(&str, &str), usize> = HashMap::new();
+        for processor in processors.iter() {
+            *writers
+                .entry((
+                    processor.database_name(),
+                    processor.target_collection_name(),
+                ))
+                .or_default() += 1;
+        }
+
+        let handles: Vec<_> = processors
+            .iter()
+            .zip(processor_dbs.iter())
+            .zip(stats.iter())
+            .map(|((processor, dbs), stats)| {
+                let compare_count = writers[&(
+                    processor.database_name(),
+                    processor.target_collection_name(),
+                )] == 1;
+                let processor = Arc::clone(processor);
+                let dbs = Arc::clone(dbs);
+                let stats = Arc::clone(stats);
+                let strategy = strategy.clone();
+                tokio::spawn(
+                    async move {
+                        let report = processor
+                            .verify(&dbs, &strategy, &stats, compare_count)
+                            .await;
+                        if !report.is_success() {
+                            warn!(
+                                collection = processor.collection_name(),
+                                target_collection = processor.target_collection_name(),
+                                source_count = report.source_count,
+                                target_count = ?report.target_count,
+                                missing = report.missing,
+                                mismatched = report.mismatched,
+                                error = ?report.error,
+                                "Verification found discrepancies"
+                            );
+                        }
+                        report
+                    }
+                    .in_current_span(),
+                )
+            })
+            .collect();
+
+        Ok(join_all(handles)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Leak detection phase: scans the target collection of every processor with
+    /// sensitive paths for values of its source documents
+    async fn detect_leaks(
+        processors: &[Arc<Box<dyn Processor>>],
+        processor_dbs: &[Arc<DatabasePair>],
+        stats: &[Arc<CollectionStats>],
+    ) -> TuxedoResult<Vec<LeakReport>> {
+        let handles: Vec<_> = processors
+            .iter()
+            .zip(processor_dbs.iter())
+            .zip(stats.iter())
+            .map(|((processor, dbs), stats)| {
+                let processor = Arc::clone(processor);
+                let dbs = Arc::clone(dbs);
+                let stats = Arc::clone(stats);
+                tokio::spawn(
+                    async move {
+                        let report = processor.detect_leaks(&dbs, &stats).await?;
+                        if !report.is_success() {
+                            warn!(
+                                collection = processor.collection_name(),
+                                target_collection = processor.target_collection_name(),
+                                leaks = report.leaks,
+                                error = ?report.error,
+                                "Sensitive source values found on the target"
+                            );
+                        }
+                        Some(report)
+                    }
+                    .in_current_span(),
+                )
+            })
+            .collect();
+
+        Ok(join_all(handles)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    /// Schema phase: creates each distinct target collection once, using the options of
+    /// the first processor's source collection. Returns the validators held back until
+    /// after the load.
+    async fn create_target_collections(
+        &self,
+        processor_dbs: &[Arc<DatabasePair>],
+        stats: &[Arc<CollectionStats>],
+    ) -> Vec<DeferredValidator> {
+        let mut created: Vec<(String, String)> = Vec::new();
+        let mut deferred_validators: Vec<DeferredValidator> = Vec::new();
 
-            // Get all source views to copy
-            let source_views = match self.dbs.list_source_views().await {
-                Ok(views) => views,
+        for ((processor, dbs), processor_stats) in self
+            .processors
+            .iter()
+            .zip(processor_dbs.iter())
+            .zip(stats.iter())
+        {
+            let target = (
+                processor.database_name().to_string(),
+                processor.target_collection_name().to_string(),
+            );
+            if created.contains(&target) {
+                // Merged targets share the validator of the collection that created them
+                if let Some(deferred) = deferred_validators
+                    .iter_mut()
+                    .find(|d| Arc::ptr_eq(&d.dbs, dbs) && d.collection_name == target.1)
+                {
+                    deferred.stats.push(Arc::clone(processor_stats));
+                }
+                continue;
+            }
+
+            match schema::create_target_collection(
+                dbs,
+                processor.collection_name(),
+                processor.target_collection_name(),
+                self.config.defer_validators,
+            )
+            .await
+            {
+                Ok(Some(validator)) => deferred_validators.push(DeferredValidator {
+                    dbs: Arc::clone(dbs),
+                    collection_name: target.1.clone(),
+                    validator,
+                    stats: vec![Arc::clone(processor_stats)],
+                }),
+                Ok(None) => {}
                 Err(e) => {
-                    println!("Error listing source views: {:?}", e);
-                    return Ok(());
+                    error!(
+                        collection = processor.collection_name(),
+                        target_collection = processor.target_collection_name(),
+                        error = %e,
+                        "Error creating target collection with the source options"
+                    );
+                    processor_stats.add_error(
+                        ErrorStage::Schema,
+                        format!("Failed to create target collection: {e}"),
+                    );
                 }
-            };
+            }
 
-            if !source_views.is_empty() {
-                // Copy all views in parallel (like index copying)
-                let copy_view_handles: Vec<_> = source_views
-                    .into_iter()
-                    .map(|view_spec| {
-                        let dbs = Arc::clone(&self.dbs);
-                        tokio::spawn(async move {
-                            if let Err(e) = dbs.copy_single_view(&view_spec).await {
-                                println!("Error copying view '{}': {:?}", view_spec.name, e);
-                            } else {
-                                println!("Successfully copied view: {}", view_spec.name);
+            created.push(target);
+        }
+
+        deferred_validators
+    }
+
+    async fn apply_deferred_validators(deferred_validators: Vec<DeferredValidator>) {
+        let handles: Vec<_> = deferred_validators
+            .into_iter()
+            .map(|deferred| {
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = deferred
+                            .dbs
+                            .apply_target_validator(&deferred.collection_name, &deferred.validator)
+                            .await
+                        {
+                            error!(
+                                target_collection = %deferred.collection_name,
+                                error = %e,
+                                "Error applying validator to target collection"
+                            );
+                            for stats in deferred.stats.iter() {
+                                stats.add_error(
+                                    ErrorStage::Schema,
+                                    format!("Failed to apply deferred validator: {e}"),
+                                );
                             }
-                        })
-                    })
-                    .collect();
-
-                // Wait for all views to complete
-                let results = join_all(copy_view_handles).await;
-                let successful_count = results.into_iter().filter(|r| r.is_ok()).count();
-                println!("Copied {} views successfully", successful_count);
+                        }
+                    }
+                    .in_current_span(),
+                )
+            })
+            .collect();
+
+        join_all(handles).await;
+    }
+
+    /// Recreates the source views on the target in dependency order. Views are created
+    /// layer by layer, the views within a layer are created in parallel.
+    async fn copy_views(
+        dbs: &Arc<DatabasePair>,
+        collection_mapping: HashMap<String, String>,
+        skip_missing_dependencies: bool,
+    ) -> Vec<(String, ViewOutcome)> {
+        // Get all source views to copy
+        let source_views = match dbs.list_source_views().await {
+            Ok(views) => views,
+            Err(e) => {
+                error!(error = %e, "Error listing source views");
+                return Vec::new();
+            }
+        };
+
+        if source_views.is_empty() {
+            return Vec::new();
+        }
+
+        // Collections already loaded on the target satisfy view dependencies
+        let target_collections: HashSet<String> = match dbs.list_target_collection_names().await {
+            Ok(names) => names.into_iter().collect(),
+            Err(e) => {
+                error!(error = %e, "Error listing target collections");
+                return Vec::new();
+            }
+        };
+
+        let plan = plan_view_creation(
+            source_views,
+            &target_collections,
+            &collection_mapping,
+            skip_missing_dependencies,
+        );
+
+        let mut outcomes: Vec<(String, ViewOutcome)> = Vec::new();
+        for (name, reason) in plan.skipped {
+            warn!(view = %name, %reason, "Skipping view");
+            outcomes.push((name, ViewOutcome::Skipped(reason)));
+        }
+
+        let collection_mapping = Arc::new(collection_mapping);
+        let mut successful_count = 0;
+
+        for layer in plan.layers {
+            let copy_view_handles: Vec<_> = layer
+                .into_iter()
+                .map(|view_spec| {
+                    let dbs = Arc::clone(dbs);
+                    let collection_mapping = Arc::clone(&collection_mapping);
+                    tokio::spawn(
+                        async move {
+                            let outcome =
+                                match dbs.copy_single_view(&view_spec, &collection_mapping).await {
+                                    Ok(()) => {
+                                        info!(view = %view_spec.name, "Copied view");
+                                        ViewOutcome::Created
+                                    }
+                                    Err(e) => {
+                                        error!(view = %view_spec.name, error = %e, "Error copying view");
+                                        ViewOutcome::Failed(e.to_string())
+                                    }
+                                };
+                            (view_spec.name, outcome)
+                        }
+                        .in_current_span(),
+                    )
+                })
+                .collect();
+
+            // Wait for the whole layer before creating the views depending on it
+            for result in join_all(copy_view_handles).await {
+                match result {
+                    Ok((name, outcome)) => {
+                        if outcome == ViewOutcome::Created {
+                            successful_count += 1;
+                        }
+                        outcomes.push((name, outcome));
+                    }
+                    Err(e) => error!(error = %e, "View copy task failed"),
+                }
             }
         }
 
-        Ok(())
+        info!(views = successful_count, "Copied views");
+        outcomes
     }
 }
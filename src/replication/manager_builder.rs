@@ -1,28 +1,90 @@
 use super::manager::{ReplicationConfig, ReplicationManager};
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+use super::plan::CollectionPlan;
 use super::processor::{Processor, ProcessorConfig, ReplicatorConfig};
+use crate::replication::dump::{DumpSink, DumpSource};
+use crate::replication::gridfs::{GridFsConfig, GridFsProcessor};
+use crate::replication::jsonl::{ExtendedJsonMode, JsonlSink, JsonlSource};
 use crate::replication::processor::{ModelProcessor, ReplicatorProcessor};
-use crate::replication::types::{DatabasePair, ReplicationStrategy};
-use crate::{Mask, TuxedoError, TuxedoResult};
+use crate::replication::progress::{default_listener, ProgressListener};
+use crate::replication::sink::{MongoSink, Sink};
+use crate::replication::source::{MongoSource, Source};
+#[cfg(feature = "sqlite")]
+use crate::replication::sqlite::SqliteSink;
+use crate::replication::subset::SubsetConfig;
+use crate::replication::types::{DatabasePair, ReplicationStrategy, DEFAULT_DATABASE};
+use crate::{Mask, Policy, TuxedoError, TuxedoResult};
 use bson::Document;
 use mongodb::options::FindOptions;
 use mongodb::{
-    options::{ClientOptions, Compressor, InsertManyOptions, ReadConcern},
+    options::{
+        ClientOptions, Compressor, InsertManyOptions, ReadConcern, ReadPreference,
+        ReadPreferenceOptions, SelectionCriteria,
+    },
     Client,
 };
 use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use url::Url;
+use tracing::info;
+
+/// Smallest max staleness accepted by the servers
+const MIN_MAX_STALENESS: Duration = Duration::from_secs(90);
+
+/// Customizes the client options of one side after the defaults were applied
+pub(crate) type ClientOptionsCallback = Box<dyn Fn(&mut ClientOptions) + Send + Sync>;
 
 pub struct ReplicationManagerBuilder {
     source_uri: Option<String>,
     target_uri: Option<String>,
+    source_client_options: Option<ClientOptions>,
+    target_client_options: Option<ClientOptions>,
+    configure_source_client: Option<ClientOptionsCallback>,
+    configure_target_client: Option<ClientOptionsCallback>,
+    source_read_preference: Option<ReadPreference>,
+    source_max_staleness: Option<Duration>,
     source_db: Option<String>,
     target_db: Option<String>,
-    thread_count: usize,
+    source: Option<SourceKind>,
+    target: Option<TargetKind>,
+    gzip_dump: bool,
+    extended_json_mode: ExtendedJsonMode,
+    clear_target: bool,
     config: ReplicationConfig,
     compressors: Option<Vec<Compressor>>,
+    databases: Vec<DatabaseMapping>,
+    subset: Option<SubsetConfig>,
     processors: Vec<Box<dyn Processor>>,
+    progress: Option<Arc<dyn ProgressListener>>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+}
+
+/// Read instead of a source database
+enum SourceKind {
+    Dump(PathBuf),
+    Jsonl(PathBuf),
+    Custom(Arc<dyn Source>),
+}
+
+/// Written instead of a target database
+enum TargetKind {
+    Dump(PathBuf),
+    Jsonl(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+    Custom(Arc<dyn Sink>),
+}
+
+/// A named source/target database pair sharing the manager's connection pools
+struct DatabaseMapping {
+    name: String,
+    source_db: String,
+    target_db: String,
 }
 
 impl Default for ReplicationManagerBuilder {
@@ -33,18 +95,30 @@ impl Default for ReplicationManagerBuilder {
 
 impl ReplicationManagerBuilder {
     pub fn new() -> Self {
-        // Default to all available threads
-        let available_threads = num_cpus::get();
-
         Self {
             source_uri: None,
             target_uri: None,
+            source_client_options: None,
+            target_client_options: None,
+            configure_source_client: None,
+            configure_target_client: None,
+            source_read_preference: None,
+            source_max_staleness: None,
             source_db: None,
             target_db: None,
-            thread_count: available_threads,
+            source: None,
+            target: None,
+            gzip_dump: false,
+            extended_json_mode: ExtendedJsonMode::default(),
+            clear_target: true,
             config: ReplicationConfig::default(),
             compressors: None,
+            databases: Vec::new(),
+            subset: None,
             processors: Vec::new(),
+            progress: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
         }
     }
 
@@ -58,6 +132,51 @@ impl ReplicationManagerBuilder {
         self
     }
 
+    /// Connects to the source with fully configured client options instead of
+    /// `source_uri`, e.g. for TLS certificates or an auth mechanism built in code.
+    /// The database is `default_database` unless `source_db` is set.
+    pub fn source_client_options(mut self, options: ClientOptions) -> Self {
+        self.source_client_options = Some(options);
+        self
+    }
+
+    pub fn target_client_options(mut self, options: ClientOptions) -> Self {
+        self.target_client_options = Some(options);
+        self
+    }
+
+    /// Customizes the source client options once the defaults are applied, the last
+    /// word on the connection
+    pub fn configure_source_client<F>(mut self, configure: F) -> Self
+    where
+        F: Fn(&mut ClientOptions) + Send + Sync + 'static,
+    {
+        self.configure_source_client = Some(Box::new(configure));
+        self
+    }
+
+    pub fn configure_target_client<F>(mut self, configure: F) -> Self
+    where
+        F: Fn(&mut ClientOptions) + Send + Sync + 'static,
+    {
+        self.configure_target_client = Some(Box::new(configure));
+        self
+    }
+
+    /// Where the source is read from. Defaults to `secondaryPreferred` to keep the
+    /// load off the primary, unless the URI sets a read preference.
+    pub fn source_read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.source_read_preference = Some(read_preference);
+        self
+    }
+
+    /// How far behind the primary a secondary may be to serve source reads, at least
+    /// 90 seconds. Applies to any read preference but `primary`, which is rejected.
+    pub fn source_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.source_max_staleness = Some(max_staleness);
+        self
+    }
+
     pub fn source_db<S: Into<String>>(mut self, db_name: S) -> Self {
         self.source_db = Some(db_name.into());
         self
@@ -68,8 +187,115 @@ impl ReplicationManagerBuilder {
         self
     }
 
+    /// Reads the data from `mongodump` output instead of a source database, so a
+    /// production snapshot can be masked without restoring it anywhere first. `path`
+    /// is either an `--archive` file or the dump directory, the database read from it
+    /// is given by `source_db` (or the databases registered with `add_database`).
+    /// Dumps are read by a single task per collection and queries are evaluated in memory.
+    pub fn source_dump<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.source = Some(SourceKind::Dump(path.into()));
+        self
+    }
+
+    /// Writes the masked data into a `mongodump` style directory instead of a target
+    /// database, which `mongorestore` can load later on. Each database mapping is
+    /// written into `<directory>/<target_db>`, the target database name defaults to
+    /// the source database name when no `target_uri` or `target_db` is given.
+    pub fn target_dump<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.target = Some(TargetKind::Dump(directory.into()));
+        self
+    }
+
+    /// Gzip compresses the dump files, restore them with `mongorestore --gzip`
+    pub fn gzip_dump(mut self, enabled: bool) -> Self {
+        self.gzip_dump = enabled;
+        self
+    }
+
+    /// Reads the data from Extended JSON Lines files instead of a source database,
+    /// `<collection>.jsonl` holding one document per line. `directory` holds the files
+    /// or a `<source_db>` directory of them, as written by `target_jsonl`.
+    pub fn source_jsonl<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.source = Some(SourceKind::Jsonl(directory.into()));
+        self
+    }
+
+    /// Writes the masked data as Extended JSON Lines instead of into a target database,
+    /// one `<directory>/<target_db>/<collection>.jsonl` file per collection ordered by
+    /// `_id`, for fixture datasets kept in git. Indexes, options and views are not written.
+    pub fn target_jsonl<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.target = Some(TargetKind::Jsonl(directory.into()));
+        self
+    }
+
+    /// Extended JSON flavour written by `target_jsonl`, canonical by default
+    pub fn extended_json_mode(mut self, mode: ExtendedJsonMode) -> Self {
+        self.extended_json_mode = mode;
+        self
+    }
+
+    /// Writes the masked data into a single SQLite file instead of a target database,
+    /// one table per collection with nested fields flattened into dotted columns.
+    /// Tables are prefixed with the target database name when several databases
+    /// are replicated.
+    #[cfg(feature = "sqlite")]
+    pub fn target_sqlite<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.target = Some(TargetKind::Sqlite(path.into()));
+        self
+    }
+
+    /// Reads the data from a custom `Source` instead of a source database. The
+    /// source is shared by every database mapping, the database names of
+    /// `source_db` and `add_database` are not passed to it.
+    pub fn source<S: Source + 'static>(mut self, source: S) -> Self {
+        self.source = Some(SourceKind::Custom(Arc::new(source)));
+        self
+    }
+
+    /// Writes the masked data into a custom `Sink` instead of a target database.
+    /// The sink is shared by every database mapping.
+    pub fn target<S: Sink + 'static>(mut self, sink: S) -> Self {
+        self.target = Some(TargetKind::Custom(Arc::new(sink)));
+        self
+    }
+
+    /// Receives the progress events of the run instead of the default rendering, which
+    /// draws progress bars on a terminal (`progress-bars` feature) and plain lines
+    /// otherwise. Pass `|_: &ProgressEvent| {}` to report nothing.
+    pub fn progress<L: ProgressListener + 'static>(mut self, listener: L) -> Self {
+        self.progress = Some(Arc::new(listener));
+        self
+    }
+
+    /// Records the run into a Prometheus metrics registry, served with
+    /// `Metrics::serve`
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Registers an additional source/target database pair on the same clusters.
+    /// Processors are bound to it through `ProcessorConfigBuilder::database` or
+    /// `ReplicationConfigBuilder::database` using the given `name`.
+    pub fn add_database(
+        mut self,
+        name: impl Into<String>,
+        source_db: impl Into<String>,
+        target_db: impl Into<String>,
+    ) -> Self {
+        self.databases.push(DatabaseMapping {
+            name: name.into(),
+            source_db: source_db.into(),
+            target_db: target_db.into(),
+        });
+        self
+    }
+
+    /// Tasks run concurrently, defaults to the number of CPUs. Each side's
+    /// connection pool holds up to twice as many connections.
     pub fn thread_count<S: Into<usize>>(mut self, count: S) -> Self {
-        self.thread_count = count.into();
+        self.config.thread_count = count.into();
         self
     }
 
@@ -119,11 +345,81 @@ impl ReplicationManagerBuilder {
         self
     }
 
+    /// Drops the target collections and views of the processors while building, so
+    /// the run starts from an empty target. Enabled by default, disable it to only
+    /// read from the target with `plan`, `verify_target` or `scan`.
+    pub fn clear_target(mut self, enabled: bool) -> Self {
+        self.clear_target = enabled;
+        self
+    }
+
     pub fn copy_views(mut self, enabled: bool) -> Self {
         self.config.copy_views = enabled;
         self
     }
 
+    /// Compares every processor's source documents with its target collection once
+    /// the run completed: the counts, that every source `_id` exists on the target
+    /// and, with the clone strategy, the content of the documents. Discrepancies are
+    /// reported in `RunReport::verifications` and make the run unsuccessful.
+    pub fn verify(mut self, enabled: bool) -> Self {
+        self.config.verify = enabled;
+        self
+    }
+
+    /// Scans the target collections for source values at the sensitive paths of each
+    /// processor once the run completed, catching fields a `Mask` impl forgot or fakes
+    /// reproducing real values. Leaks are reported by path and `_id` in
+    /// `RunReport::leaks` and make the run unsuccessful.
+    pub fn detect_leaks(mut self, enabled: bool) -> Self {
+        self.config.detect_leaks = enabled;
+        self
+    }
+
+    /// Creates each target collection with the options of its source collection
+    /// (validator, collation, capped, clustered, time-series, ...) before writing data.
+    /// Enabled by default.
+    pub fn copy_collection_options(mut self, enabled: bool) -> Self {
+        self.config.copy_collection_options = enabled;
+        self
+    }
+
+    /// Holds back collection validators until all data and indexes have been loaded
+    pub fn defer_validators(mut self, enabled: bool) -> Self {
+        self.config.defer_validators = enabled;
+        self
+    }
+
+    /// Removes index options the target server version does not support and skips
+    /// indexes which cannot be created there at all, reporting them as failures
+    pub fn strip_unsupported_index_options(mut self, enabled: bool) -> Self {
+        self.config.strip_unsupported_index_options = enabled;
+        self
+    }
+
+    /// Recreates TTL indexes without `expireAfterSeconds` so masked data on the
+    /// target is not reaped
+    pub fn disable_ttl_indexes(mut self, enabled: bool) -> Self {
+        self.config.disable_ttl_indexes = enabled;
+        self
+    }
+
+    /// Views whose `viewOn` or pipeline references a collection that is not present
+    /// on the target are skipped (the default). When disabled they are created with
+    /// a warning instead.
+    pub fn skip_views_with_missing_dependencies(mut self, enabled: bool) -> Self {
+        self.config.skip_views_with_missing_dependencies = enabled;
+        self
+    }
+
+    /// Copies a referentially consistent subset of the database. Processors of the
+    /// collections reached from the subset's root only copy the related documents,
+    /// every other collection is copied in full.
+    pub fn subset(mut self, subset: SubsetConfig) -> Self {
+        self.subset = Some(subset);
+        self
+    }
+
     pub fn optimize_for_performance(self, compression: bool) -> Self {
         let mut builder = self;
 
@@ -175,84 +471,330 @@ impl ReplicationManagerBuilder {
         self
     }
 
+    /// Adds a replicator per collection of a masking policy, masking the documents
+    /// with the collection's rules
+    pub fn policy(self, policy: Policy) -> Self {
+        policy
+            .collections
+            .into_iter()
+            .fold(self, |builder, collection| {
+                let mut config = ReplicatorConfig::builder().rules(collection.mask_rules());
+                if let Some(database) = collection.database {
+                    config = config.database(database);
+                }
+                if let Some(target_collection) = collection.target_collection {
+                    config = config.target_collection(target_collection);
+                }
+                builder.add_replicator_with_config(collection.name, config.build())
+            })
+    }
+
+    /// Copies a GridFS bucket (`<bucket>.files` and `<bucket>.chunks`) file by file
+    pub fn add_gridfs_bucket(self, bucket_name: impl Into<String>) -> Self {
+        let config = GridFsConfig::default();
+        self.add_gridfs_bucket_with_config(bucket_name, config)
+    }
+
+    pub fn add_gridfs_bucket_with_config(
+        mut self,
+        bucket_name: impl Into<String>,
+        config: GridFsConfig,
+    ) -> Self {
+        self.processors
+            .push(Box::new(GridFsProcessor::new(bucket_name, config)));
+        self
+    }
+
+    /// Rejects configurations which can't run, before any connection is made
+    fn validate(&self) -> TuxedoResult<()> {
+        if self.config.thread_count == 0 {
+            return Err(TuxedoError::ConfigError(
+                "thread_count must be at least 1".into(),
+            ));
+        }
+        if self.source_uri.is_some() && self.source_client_options.is_some() {
+            return Err(TuxedoError::ConfigError(
+                "Set either source_uri or source_client_options, not both".into(),
+            ));
+        }
+        if self.target_uri.is_some() && self.target_client_options.is_some() {
+            return Err(TuxedoError::ConfigError(
+                "Set either target_uri or target_client_options, not both".into(),
+            ));
+        }
+        if self
+            .source_max_staleness
+            .is_some_and(|max_staleness| max_staleness < MIN_MAX_STALENESS)
+        {
+            return Err(TuxedoError::ConfigError(
+                "source_max_staleness must be at least 90 seconds".into(),
+            ));
+        }
+        if self.config.batch_size == 0 || self.config.write_batch_size == 0 {
+            return Err(TuxedoError::ConfigError(
+                "batch_size and write_batch_size must be at least 1".into(),
+            ));
+        }
+        // Read only managers, e.g. scanning the source, don't need any processor
+        if self.clear_target && self.processors.is_empty() {
+            return Err(TuxedoError::ConfigError(
+                "No collection to replicate, add a model, replicator or GridFS bucket".into(),
+            ));
+        }
+
+        let mut plans: Vec<CollectionPlan> = Vec::new();
+        for processor in self.processors.iter() {
+            processor.validate()?;
+
+            let plan = processor.plan(&self.config.strategy);
+            if plans.iter().any(|other| {
+                other.database == plan.database
+                    && other.source_collection == plan.source_collection
+                    && other.target_collection == plan.target_collection
+                    && other.query == plan.query
+            }) {
+                return Err(TuxedoError::ConfigError(format!(
+                    "Collection `{}` was added more than once to database mapping `{}` with the same target and query",
+                    plan.source_collection, plan.database
+                )));
+            }
+            plans.push(plan);
+        }
+        Ok(())
+    }
+
     pub async fn build(self) -> TuxedoResult<ReplicationManager> {
-        let source_uri = self
-            .source_uri
-            .clone()
-            .ok_or(TuxedoError::ConfigError("No source_uri provided.".into()))?;
-        let target_uri = self
-            .target_uri
-            .clone()
-            .ok_or(TuxedoError::ConfigError("No target_uri provided.".into()))?;
-
-        let compressors = self.compressors.clone();
-
-        let max_pool_size = (self.config.thread_count * 2) as u32;
-        let min_pool_size = self.config.thread_count as u32;
-        let max_connecting = self.config.thread_count as u32;
-
-        let mut source_client_options = ClientOptions::parse(&source_uri).await?;
-        source_client_options.max_pool_size = max_pool_size.into();
-        source_client_options.min_pool_size = min_pool_size.into();
-        source_client_options.max_connecting = max_connecting.into();
-        source_client_options.compressors = compressors.clone();
-        // Make the database Read only as much as we have control to do
-        source_client_options.read_concern = ReadConcern::majority().into();
-        let source_client = Client::with_options(source_client_options)?;
-
-        let mut target_client_options = ClientOptions::parse(&target_uri).await?;
-        target_client_options.max_pool_size = max_pool_size.into();
-        target_client_options.min_pool_size = min_pool_size.into();
-        target_client_options.max_connecting = max_connecting.into();
-        target_client_options.compressors = compressors;
-        let target_client = Client::with_options(target_client_options)?;
+        self.validate()?;
+
+        // Files or a custom source replace the source database
+        let source_options = match self.source {
+            Some(_) => None,
+            None => Some(
+                self.client_options(
+                    self.source_uri.as_deref(),
+                    self.source_client_options.as_ref(),
+                    "source",
+                )
+                .await?,
+            ),
+        };
+        // Files or a custom sink replace the target database
+        let target_options = match self.target {
+            Some(_) => None,
+            None => Some(
+                self.client_options(
+                    self.target_uri.as_deref(),
+                    self.target_client_options.as_ref(),
+                    "target",
+                )
+                .await?,
+            ),
+        };
+
+        let source_client = match source_options.clone() {
+            Some(mut source_client_options) => {
+                // Make the database Read only as much as we have control to do
+                source_client_options
+                    .read_concern
+                    .get_or_insert_with(ReadConcern::majority);
+                // Spare the primary of production traffic
+                let selection_criteria = match (
+                    self.source_read_preference.clone(),
+                    source_client_options.selection_criteria.take(),
+                ) {
+                    (Some(read_preference), _) => read_preference.into(),
+                    (None, Some(selection_criteria)) => selection_criteria,
+                    (None, None) => ReadPreference::SecondaryPreferred { options: None }.into(),
+                };
+                source_client_options.selection_criteria = Some(match self.source_max_staleness {
+                    Some(max_staleness) => with_max_staleness(selection_criteria, max_staleness)?,
+                    None => selection_criteria,
+                });
+                if let Some(configure) = self.configure_source_client.as_ref() {
+                    configure(&mut source_client_options);
+                }
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = self.metrics.as_ref() {
+                    let forward = source_client_options.command_event_handler.take();
+                    source_client_options.command_event_handler =
+                        Some(metrics.command_event_handler("source", forward));
+                }
+                Some(Client::with_options(source_client_options)?)
+            }
+            None => None,
+        };
+
+        let target_client = match target_options.clone() {
+            Some(mut target_client_options) => {
+                if let Some(configure) = self.configure_target_client.as_ref() {
+                    configure(&mut target_client_options);
+                }
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = self.metrics.as_ref() {
+                    let forward = target_client_options.command_event_handler.take();
+                    target_client_options.command_event_handler =
+                        Some(metrics.command_event_handler("target", forward));
+                }
+                Some(Client::with_options(target_client_options)?)
+            }
+            None => None,
+        };
 
         // target_client.warm_connection_pool().await;
         // source_client.warm_connection_pool().await;
 
-        let source_db_name = self.get_db_name(&source_uri, self.source_db.clone())?;
-        let target_db_name = self.get_db_name(&target_uri, self.target_db.clone())?;
-
-        // Ensure our database connections are actually valid and we can make the connection
-        // We intentionally want to blow up here if we can't connect to *either* DB to avoid a giant mess
-        let dbs = Arc::new(DatabasePair::new(
-            source_client.database(&source_db_name),
-            target_client.database(&target_db_name),
-        ));
-        dbs.test_database_collection_source()
-            .await
-            .expect("Could not create test connection to source database");
-        dbs.test_database_collection_target()
-            .await
-            .expect("Could not create test connection to target database");
-
-        println!("Dropping collections and views from target database before beginning...");
-        // Collect collection names from processors
-        let mut items_to_drop: Vec<String> = self
-            .processors
-            .iter()
-            .map(|p| p.collection_name().to_string())
-            .collect();
+        let mut database_names =
+            self.database_names(source_options.as_ref(), target_options.as_ref())?;
+        database_names.extend(self.databases.iter().map(|mapping| {
+            (
+                mapping.name.clone(),
+                mapping.source_db.clone(),
+                mapping.target_db.clone(),
+            )
+        }));
+
+        for processor in self.processors.iter() {
+            if !database_names
+                .iter()
+                .any(|(name, _, _)| name == processor.database_name())
+            {
+                return Err(TuxedoError::ConfigError(format!(
+                    "Collection `{}` is bound to unknown database mapping `{}`",
+                    processor.collection_name(),
+                    processor.database_name()
+                )));
+            }
+        }
+
+        if let Some(subset) = self.subset.as_ref() {
+            if !database_names
+                .iter()
+                .any(|(name, _, _)| name == subset.database_name())
+            {
+                return Err(TuxedoError::ConfigError(format!(
+                    "Subset is bound to unknown database mapping `{}`",
+                    subset.database_name()
+                )));
+            }
+        }
 
-        // Add view names if view copying is enabled
-        if self.config.copy_views {
-            let view_names = dbs
-                .get_source_view_names()
+        #[cfg(feature = "sqlite")]
+        let sqlite_connection = match self.target.as_ref() {
+            Some(TargetKind::Sqlite(path)) => Some(SqliteSink::open_connection(path)?),
+            _ => None,
+        };
+        #[cfg(feature = "sqlite")]
+        let multiple_databases = database_names.len() > 1;
+
+        let mut databases = HashMap::new();
+        for (name, source_db_name, target_db_name) in database_names {
+            if databases.contains_key(&name) {
+                return Err(TuxedoError::ConfigError(format!(
+                    "Database mapping `{name}` was registered more than once"
+                )));
+            }
+
+            let target: Arc<dyn Sink> = match (self.target.as_ref(), target_client.as_ref()) {
+                (Some(TargetKind::Dump(directory)), _) => Arc::new(DumpSink::new(
+                    directory.join(&target_db_name),
+                    self.gzip_dump,
+                )),
+                (Some(TargetKind::Jsonl(directory)), _) => Arc::new(JsonlSink::new(
+                    directory.join(&target_db_name),
+                    self.extended_json_mode,
+                )),
+                #[cfg(feature = "sqlite")]
+                (Some(TargetKind::Sqlite(_)), _) => {
+                    let connection = sqlite_connection.as_ref().ok_or_else(|| {
+                        TuxedoError::ConfigError("The SQLite target file is not open".into())
+                    })?;
+                    Arc::new(SqliteSink::new(
+                        Arc::clone(connection),
+                        multiple_databases.then(|| target_db_name.clone()),
+                    ))
+                }
+                (Some(TargetKind::Custom(sink)), _) => Arc::clone(sink),
+                (None, Some(target_client)) => {
+                    Arc::new(MongoSink::new(target_client.database(&target_db_name)))
+                }
+                (None, None) => {
+                    return Err(TuxedoError::ConfigError("No target_uri provided.".into()))
+                }
+            };
+            let source: Arc<dyn Source> = match (self.source.as_ref(), source_client.as_ref()) {
+                (Some(SourceKind::Dump(path)), _) => {
+                    Arc::new(DumpSource::open(path, &source_db_name)?)
+                }
+                (Some(SourceKind::Jsonl(directory)), _) => {
+                    Arc::new(JsonlSource::open(directory, &source_db_name)?)
+                }
+                (Some(SourceKind::Custom(source)), _) => Arc::clone(source),
+                (None, Some(source_client)) => {
+                    Arc::new(MongoSource::new(source_client.database(&source_db_name)))
+                }
+                (None, None) => {
+                    return Err(TuxedoError::ConfigError("No source_uri provided.".into()))
+                }
+            };
+            let dbs = Arc::new(DatabasePair::new(source, target));
+            // Test every connection before anything is dropped, so a mapping failing
+            // to connect leaves all targets untouched
+            dbs.test_database_collection_source()
+                .await
+                .map_err(|e| source_error(&source_db_name, e))?;
+            dbs.test_database_collection_target()
                 .await
-                .expect("Expected to successfully get source view names");
-            items_to_drop.extend(view_names);
+                .map_err(|e| target_error(&target_db_name, e))?;
+
+            databases.insert(name, (dbs, source_db_name, target_db_name));
         }
 
-        dbs.clear_target_collections(&items_to_drop)
-            .await
-            .expect("Expected to successfully drop target database collections and views before replication");
+        if self.clear_target {
+            for (name, (dbs, source_db_name, target_db_name)) in databases.iter() {
+                info!(
+                    database = %target_db_name,
+                    "Dropping collections and views from target database before beginning"
+                );
+                // Collect target collection names from processors, several processors
+                // may write into the same target collection
+                let mut items_to_drop: Vec<String> = Vec::new();
+                for processor in self.processors.iter().filter(|p| p.database_name() == name) {
+                    for target_collection_name in processor.target_collection_names() {
+                        if !items_to_drop.contains(&target_collection_name) {
+                            items_to_drop.push(target_collection_name);
+                        }
+                    }
+                }
+
+                // Add view names if view copying is enabled
+                if self.config.copy_views {
+                    let view_names = dbs
+                        .get_source_view_names()
+                        .await
+                        .map_err(|e| source_error(source_db_name, e))?;
+                    items_to_drop.extend(view_names);
+                }
+
+                dbs.clear_target_collections(&items_to_drop)
+                    .await
+                    .map_err(|e| target_error(target_db_name, e))?;
+            }
+        }
+        let databases = databases
+            .into_iter()
+            .map(|(name, (dbs, _, _))| (name, dbs))
+            .collect();
 
         let (task_sender, task_receiver) = mpsc::channel(self.config.thread_count);
 
         let manager = ReplicationManager {
-            dbs,
+            databases,
             processors: self.processors.into_iter().map(|p| p.into()).collect(),
             config: self.config,
+            subset: self.subset,
+            progress: self.progress.unwrap_or_else(default_listener),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
             task_receiver,
             task_sender,
         };
@@ -260,10 +802,82 @@ impl ReplicationManagerBuilder {
         Ok(manager)
     }
 
-    fn get_db_name(&self, uri: &str, db_name: Option<String>) -> TuxedoResult<String> {
-        let parsed_db_name = self.parse_db_name_from_uri(uri)?;
+    /// Client options of one side, parsed from its URI or given in full, with the
+    /// pool sized for the concurrency. Options set by the URI are kept.
+    async fn client_options(
+        &self,
+        uri: Option<&str>,
+        options: Option<&ClientOptions>,
+        side: &str,
+    ) -> TuxedoResult<ClientOptions> {
+        let mut options = match (uri, options) {
+            (_, Some(options)) => options.clone(),
+            (Some(uri), None) => ClientOptions::parse(uri)
+                .await
+                .map_err(|e| TuxedoError::ConfigError(format!("Invalid {side}_uri: {e}")))?,
+            (None, None) => {
+                return Err(TuxedoError::ConfigError(format!("No {side}_uri provided.")))
+            }
+        };
+
+        // A reader and a writer task may each hold a connection
+        let thread_count = self.config.thread_count as u32;
+        options.max_pool_size.get_or_insert(thread_count * 2);
+        options.min_pool_size.get_or_insert(thread_count);
+        options.max_connecting.get_or_insert(thread_count);
+        if let Some(compressors) = self.compressors.as_ref() {
+            options.compressors = Some(compressors.clone());
+        }
+        options.app_name.get_or_insert_with(|| "tuxedo".to_string());
+        Ok(options)
+    }
+
+    /// Resolves the default database mapping from `source_db` / `target_db` or the URIs.
+    /// The default mapping may be omitted entirely when named mappings were added instead.
+    fn database_names(
+        &self,
+        source_options: Option<&ClientOptions>,
+        target_options: Option<&ClientOptions>,
+    ) -> TuxedoResult<Vec<(String, String, String)>> {
+        let source_db_name = match (source_options, &self.source) {
+            (Some(source_options), _) => self.get_db_name(source_options, self.source_db.clone()),
+            // The name only labels the mapping of a custom source
+            (None, Some(SourceKind::Custom(_))) => Ok(self
+                .source_db
+                .clone()
+                .unwrap_or_else(|| DEFAULT_DATABASE.to_string())),
+            (None, _) => self.source_db.clone().ok_or(TuxedoError::ConfigError(
+                "No source_uri or source_db provided.".into(),
+            )),
+        };
+        let target_db_name = match (target_options, self.target_db.clone(), &source_db_name) {
+            (Some(target_options), target_db, _) => self.get_db_name(target_options, target_db),
+            (None, Some(target_db), _) => Ok(target_db),
+            // Target directories are named after the source database by default
+            (None, None, Ok(source_db_name)) => Ok(source_db_name.clone()),
+            (None, None, Err(_)) => Err(TuxedoError::ConfigError(
+                "Could not parse database name from URI and no database name provided.".into(),
+            )),
+        };
+
+        match (source_db_name, target_db_name) {
+            (Ok(source_db_name), Ok(target_db_name)) => Ok(vec![(
+                DEFAULT_DATABASE.to_string(),
+                source_db_name,
+                target_db_name,
+            )]),
+            (Err(_), Err(_)) if !self.databases.is_empty() => Ok(Vec::new()),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        }
+    }
 
-        match parsed_db_name {
+    /// The database of the URI path wins over the configured name
+    fn get_db_name(
+        &self,
+        options: &ClientOptions,
+        db_name: Option<String>,
+    ) -> TuxedoResult<String> {
+        match options.default_database.clone() {
             Some(db_name) => Ok(db_name),
             None => match db_name {
                 Some(db_name) => Ok(db_name),
@@ -273,21 +887,80 @@ impl ReplicationManagerBuilder {
             },
         }
     }
+}
 
-    fn parse_db_name_from_uri(&self, url: &str) -> TuxedoResult<Option<String>> {
-        let url = Url::parse(url).map_err(|e| TuxedoError::ConfigError(e.to_string()))?;
-        let path = url.path();
-        if path.is_empty() || path == "/" {
-            return Ok(None);
+/// Bounds the lag of the secondaries selected by `selection_criteria`
+fn with_max_staleness(
+    selection_criteria: SelectionCriteria,
+    max_staleness: Duration,
+) -> TuxedoResult<SelectionCriteria> {
+    let bounded = |options: Option<ReadPreferenceOptions>| {
+        let mut options = options.unwrap_or_default();
+        options.max_staleness = Some(max_staleness);
+        Some(options)
+    };
+    let read_preference = match selection_criteria {
+        SelectionCriteria::ReadPreference(ReadPreference::Secondary { options }) => {
+            ReadPreference::Secondary {
+                options: bounded(options),
+            }
+        }
+        SelectionCriteria::ReadPreference(ReadPreference::SecondaryPreferred { options }) => {
+            ReadPreference::SecondaryPreferred {
+                options: bounded(options),
+            }
+        }
+        SelectionCriteria::ReadPreference(ReadPreference::PrimaryPreferred { options }) => {
+            ReadPreference::PrimaryPreferred {
+                options: bounded(options),
+            }
         }
+        SelectionCriteria::ReadPreference(ReadPreference::Nearest { options }) => {
+            ReadPreference::Nearest {
+                options: bounded(options),
+            }
+        }
+        selection_criteria => {
+            return Err(TuxedoError::ConfigError(format!(
+            "source_max_staleness can't apply to the source read preference {selection_criteria}"
+        )))
+        }
+    };
+    Ok(read_preference.into())
+}
 
-        // Remove leading '/' and split by '?' to get rid of any query parameters
-        let db_name = path.trim_start_matches('/').split('?').next().unwrap();
+/// Describes an error of the source database, keeping a missing database as is
+fn source_error(database: &str, error: TuxedoError) -> TuxedoError {
+    match error {
+        TuxedoError::DatabaseMissing(_) => error,
+        error if error.is_permission_denied() => TuxedoError::PermissionDenied {
+            database: database.to_string(),
+            reason: error_reason(error),
+        },
+        error => TuxedoError::SourceConnectionFailed {
+            database: database.to_string(),
+            reason: error_reason(error),
+        },
+    }
+}
 
-        if db_name.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(db_name.to_string()))
-        }
+fn target_error(database: &str, error: TuxedoError) -> TuxedoError {
+    match error {
+        error if error.is_permission_denied() => TuxedoError::PermissionDenied {
+            database: database.to_string(),
+            reason: error_reason(error),
+        },
+        error => TuxedoError::TargetConnectionFailed {
+            database: database.to_string(),
+            reason: error_reason(error),
+        },
+    }
+}
+
+/// Message of the driver error without the `Database driver error` prefix
+fn error_reason(error: TuxedoError) -> String {
+    match error {
+        TuxedoError::Database(error) => error.to_string(),
+        error => error.to_string(),
     }
 }
@@ -0,0 +1,395 @@
+use super::dump::{apply_stage, cursor_from_documents, find_cursor};
+use super::filter::Filter;
+use super::indexes::ServerVersion;
+use super::schema::CollectionValidator;
+use super::sink::Sink;
+use super::source::{DocumentCursor, Source};
+use crate::TuxedoResult;
+use async_trait::async_trait;
+use bson::{doc, Document, RawDocumentBuf};
+use mongodb::options::{AggregateOptions, CreateCollectionOptions, FindOptions, InsertManyOptions};
+use mongodb::results::CollectionSpecification;
+use mongodb::IndexModel;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Collections, indexes and views held in memory
+#[derive(Debug, Default)]
+struct MemoryDatabase {
+    collections: BTreeMap<String, Vec<Document>>,
+    indexes: BTreeMap<String, Vec<IndexModel>>,
+    views: BTreeMap<String, MemoryView>,
+}
+
+impl MemoryDatabase {
+    /// Documents of a collection matching `filter`
+    fn collect(&self, collection_name: &str, filter: &Filter) -> Vec<Document> {
+        self.collections
+            .get(collection_name)
+            .map(|documents| {
+                documents
+                    .iter()
+                    .filter(|document| filter.matches(document))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn specification(&self, name: &str) -> TuxedoResult<Option<CollectionSpecification>> {
+        let specification = match self.views.get(name) {
+            Some(view) => doc! {
+                "name": name,
+                "type": "view",
+                "options": { "viewOn": &view.view_on, "pipeline": view.pipeline.clone() },
+                "info": { "readOnly": true },
+            },
+            None if self.collections.contains_key(name) => doc! {
+                "name": name,
+                "type": "collection",
+                "options": {},
+                "info": { "readOnly": false },
+            },
+            None => return Ok(None),
+        };
+        Ok(Some(bson::from_document(specification)?))
+    }
+}
+
+/// A view held by a `MemorySource` or created in a `MemorySink`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryView {
+    pub view_on: String,
+    pub pipeline: Vec<Document>,
+}
+
+/// A source seeded from documents or typed values, so masking can be tested in
+/// `cargo test` without a MongoDB instance.
+///
+/// Queries and pipelines are evaluated in memory and support the same operators and
+/// stages as a dump source. Sorts, skips, limits and projections of finds are applied.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    database: Arc<Mutex<MemoryDatabase>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds documents to a collection, creating it if needed
+    pub fn documents(
+        self,
+        collection_name: impl Into<String>,
+        documents: impl IntoIterator<Item = Document>,
+    ) -> Self {
+        self.lock()
+            .collections
+            .entry(collection_name.into())
+            .or_default()
+            .extend(documents);
+        self
+    }
+
+    /// Adds typed values to a collection, serialized the way a processor writes them
+    pub fn records<T: Serialize>(
+        self,
+        collection_name: impl Into<String>,
+        records: impl IntoIterator<Item = T>,
+    ) -> TuxedoResult<Self> {
+        let documents = records
+            .into_iter()
+            .map(|record| bson::to_document(&record))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.documents(collection_name, documents))
+    }
+
+    pub fn index(self, collection_name: impl Into<String>, index: IndexModel) -> Self {
+        self.lock()
+            .indexes
+            .entry(collection_name.into())
+            .or_default()
+            .push(index);
+        self
+    }
+
+    pub fn view(
+        self,
+        view_name: impl Into<String>,
+        view_on: impl Into<String>,
+        pipeline: impl IntoIterator<Item = Document>,
+    ) -> Self {
+        self.lock().views.insert(
+            view_name.into(),
+            MemoryView {
+                view_on: view_on.into(),
+                pipeline: pipeline.into_iter().collect(),
+            },
+        );
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryDatabase> {
+        self.database.lock().expect("Memory source poisoned")
+    }
+
+    /// Documents of a collection matching `filter`
+    fn collect(&self, collection_name: &str, filter: &Filter) -> Vec<Document> {
+        self.lock().collect(collection_name, filter)
+    }
+}
+
+#[async_trait]
+impl Source for MemorySource {
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let documents = self.collect(collection_name, &Filter::parse(&query)?);
+        find_cursor(documents, options)
+    }
+
+    async fn aggregate(
+        &self,
+        collection_name: &str,
+        pipeline: Vec<Document>,
+        _options: Option<AggregateOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let mut documents = self.collect(collection_name, &Filter::parse(&Document::new())?);
+        for stage in pipeline.iter() {
+            documents = apply_stage(documents, stage)?;
+        }
+        Ok(cursor_from_documents(documents.into_iter()))
+    }
+
+    async fn count_documents(&self, collection_name: &str, query: Document) -> TuxedoResult<u64> {
+        Ok(self.collect(collection_name, &Filter::parse(&query)?).len() as u64)
+    }
+
+    async fn average_document_size(&self, collection_name: &str) -> TuxedoResult<u64> {
+        let documents = self.collect(collection_name, &Filter::parse(&Document::new())?);
+        let total_size: usize = documents
+            .iter()
+            .map(|document| bson::to_vec(document).map_or(0, |bytes| bytes.len()))
+            .sum();
+        Ok((total_size as u64)
+            .checked_div(documents.len() as u64)
+            .unwrap_or(1024))
+    }
+
+    async fn collection_specification(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<Option<CollectionSpecification>> {
+        self.lock().specification(collection_name)
+    }
+
+    async fn list_indexes(&self, collection_name: &str) -> TuxedoResult<Vec<IndexModel>> {
+        Ok(self
+            .lock()
+            .indexes
+            .get(collection_name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn list_views(&self) -> TuxedoResult<Vec<CollectionSpecification>> {
+        let database = self.lock();
+        let mut views = Vec::new();
+        for view_name in database.views.keys() {
+            views.extend(database.specification(view_name)?);
+        }
+        Ok(views)
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        Ok(self.lock().collections.keys().cloned().collect())
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        Ok(())
+    }
+}
+
+/// A sink capturing everything written to it, so tests can assert on the masked
+/// output. Clones share the captured data: keep a clone before handing it to
+/// `ReplicationManagerBuilder::target` and inspect it once the run finished.
+///
+/// Documents are kept in the order they were written, which is not deterministic
+/// when a collection is copied by several tasks.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    database: Arc<Mutex<MemoryDatabase>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryDatabase> {
+        self.database.lock().expect("Memory sink poisoned")
+    }
+
+    /// Names of the collections and views written
+    pub fn collection_names(&self) -> Vec<String> {
+        let database = self.lock();
+        let mut names: Vec<String> = database
+            .collections
+            .keys()
+            .chain(database.views.keys())
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Documents written into a collection
+    pub fn documents(&self, collection_name: &str) -> Vec<Document> {
+        self.lock()
+            .collections
+            .get(collection_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Documents written into a collection, deserialized into `T`
+    pub fn records<T: DeserializeOwned>(&self, collection_name: &str) -> TuxedoResult<Vec<T>> {
+        self.documents(collection_name)
+            .into_iter()
+            .map(|document| Ok(bson::from_document(document)?))
+            .collect()
+    }
+
+    /// Indexes created on a collection
+    pub fn indexes(&self, collection_name: &str) -> Vec<IndexModel> {
+        self.lock()
+            .indexes
+            .get(collection_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn view(&self, view_name: &str) -> Option<MemoryView> {
+        self.lock().views.get(view_name).cloned()
+    }
+}
+
+#[async_trait]
+impl Sink for MemorySink {
+    async fn write(
+        &self,
+        collection_name: &str,
+        documents: &[RawDocumentBuf],
+        _options: Option<InsertManyOptions>,
+    ) -> TuxedoResult<()> {
+        let documents = documents
+            .iter()
+            .map(|document| bson::from_slice::<Document>(document.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.lock()
+            .collections
+            .entry(collection_name.to_string())
+            .or_default()
+            .extend(documents);
+        Ok(())
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        _options: CreateCollectionOptions,
+    ) -> TuxedoResult<()> {
+        self.lock()
+            .collections
+            .entry(collection_name.to_string())
+            .or_default();
+        Ok(())
+    }
+
+    async fn apply_validator(
+        &self,
+        _collection_name: &str,
+        _validator: &CollectionValidator,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn create_indexes(
+        &self,
+        collection_name: &str,
+        indexes: Vec<IndexModel>,
+    ) -> TuxedoResult<()> {
+        self.lock()
+            .indexes
+            .entry(collection_name.to_string())
+            .or_default()
+            .extend(indexes);
+        Ok(())
+    }
+
+    async fn create_view(
+        &self,
+        view_name: &str,
+        view_on: &str,
+        pipeline: Vec<Document>,
+    ) -> TuxedoResult<()> {
+        self.lock().views.insert(
+            view_name.to_string(),
+            MemoryView {
+                view_on: view_on.to_string(),
+                pipeline,
+            },
+        );
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> TuxedoResult<()> {
+        let mut database = self.lock();
+        database.collections.remove(collection_name);
+        database.indexes.remove(collection_name);
+        database.views.remove(collection_name);
+        Ok(())
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        Ok(self.collection_names())
+    }
+
+    async fn server_version(&self) -> Option<ServerVersion> {
+        None
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<Option<DocumentCursor>> {
+        let documents = self
+            .lock()
+            .collect(collection_name, &Filter::parse(&query)?);
+        find_cursor(documents, options).map(Some)
+    }
+
+    async fn count_documents(
+        &self,
+        collection_name: &str,
+        query: Document,
+    ) -> TuxedoResult<Option<u64>> {
+        let documents = self
+            .lock()
+            .collect(collection_name, &Filter::parse(&query)?);
+        Ok(Some(documents.len() as u64))
+    }
+}
@@ -0,0 +1,552 @@
+use super::report::ErrorStage;
+use crate::TuxedoResult;
+use bson::{Bson, Document};
+use md5::{Digest, Md5};
+use mongodb::event::command::CommandEvent;
+use mongodb::event::EventHandler;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// Upper bounds in seconds of the batch write latency histogram buckets
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Reads one of the counters of a collection
+type Counter = fn(&CollectionMetrics) -> &AtomicU64;
+
+/// Largest request head read before answering, scrapers send a few hundred bytes
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Commands the driver retries without a transaction number
+const RETRYABLE_READS: [&str; 7] = [
+    "aggregate",
+    "count",
+    "distinct",
+    "find",
+    "listCollections",
+    "listDatabases",
+    "listIndexes",
+];
+
+/// Prometheus metrics of the runs of a `ReplicationManager`, see
+/// `ReplicationManagerBuilder::metrics`.
+///
+/// Clones share the same registry, so a registry can outlive a manager and keep
+/// counting across runs. Serve it with `serve` or render it into an existing endpoint
+/// with `render`.
+///
+/// Tasks don't retry failed reads and writes, they report them as errors. Retries
+/// are the ones of the MongoDB driver's retryable reads and writes, counted from the
+/// command events of the source and target clients the manager builds from URIs or
+/// client options.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// Keyed by database mapping, source and target collection
+    collections: Mutex<BTreeMap<(String, String, String), Arc<CollectionMetrics>>>,
+    tasks_in_flight: AtomicI64,
+    /// Keyed by client, `source` or `target`
+    retries: Mutex<BTreeMap<&'static str, Arc<RetryTracker>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counters of a processor, shared by processors writing the same collection
+    pub(crate) fn collection(
+        &self,
+        database: &str,
+        collection: &str,
+        target_collection: &str,
+    ) -> Arc<CollectionMetrics> {
+        let key = (
+            database.to_string(),
+            collection.to_string(),
+            target_collection.to_string(),
+        );
+        let mut collections = self
+            .registry
+            .collections
+            .lock()
+            .expect("Metrics registry poisoned");
+        Arc::clone(collections.entry(key).or_default())
+    }
+
+    /// Counts a task as in flight until the guard is dropped
+    pub(crate) fn task_started(&self) -> TaskGuard {
+        self.registry
+            .tasks_in_flight
+            .fetch_add(1, Ordering::Relaxed);
+        TaskGuard(Arc::clone(&self.registry))
+    }
+
+    /// Command event handler counting the retries of a client, forwarding the events
+    /// to the handler already configured if any
+    pub(crate) fn command_event_handler(
+        &self,
+        client: &'static str,
+        forward: Option<EventHandler<CommandEvent>>,
+    ) -> EventHandler<CommandEvent> {
+        let tracker = Arc::clone(
+            self.registry
+                .retries
+                .lock()
+                .expect("Metrics registry poisoned")
+                .entry(client)
+                .or_default(),
+        );
+        EventHandler::callback(move |event: CommandEvent| {
+            tracker.observe(&event);
+            match forward.as_ref() {
+                Some(EventHandler::Callback(callback)) => callback(event),
+                Some(EventHandler::AsyncCallback(callback)) => {
+                    tokio::spawn(callback(event));
+                }
+                Some(EventHandler::TokioMpsc(sender)) => {
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        let _ = sender.send(event).await;
+                    });
+                }
+                _ => {}
+            }
+        })
+    }
+
+    /// Renders every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let collections = self
+            .registry
+            .collections
+            .lock()
+            .expect("Metrics registry poisoned")
+            .iter()
+            .map(|((database, collection, target_collection), metrics)| {
+                let labels = format!(
+                    "database=\"{}\",collection=\"{}\",target_collection=\"{}\"",
+                    escape(database),
+                    escape(collection),
+                    escape(target_collection)
+                );
+                (labels, Arc::clone(metrics))
+            })
+            .collect::<Vec<_>>();
+
+        let mut output = String::new();
+        let counters: [(&str, &str, Counter); 6] = [
+            (
+                "tuxedo_documents_read_total",
+                "Documents read from the source",
+                |metrics| &metrics.documents_read,
+            ),
+            (
+                "tuxedo_documents_masked_total",
+                "Documents masked before being written",
+                |metrics| &metrics.documents_masked,
+            ),
+            (
+                "tuxedo_documents_written_total",
+                "Documents written into the target",
+                |metrics| &metrics.documents_written,
+            ),
+            (
+                "tuxedo_documents_failed_total",
+                "Documents which could not be read or written",
+                |metrics| &metrics.documents_failed,
+            ),
+            (
+                "tuxedo_bytes_read_total",
+                "Bytes of BSON read from the source",
+                |metrics| &metrics.bytes_read,
+            ),
+            (
+                "tuxedo_bytes_written_total",
+                "Bytes of BSON written into the target",
+                |metrics| &metrics.bytes_written,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            header(&mut output, name, help, "counter");
+            for (labels, metrics) in collections.iter() {
+                let value = counter(metrics).load(Ordering::Relaxed);
+                let _ = writeln!(output, "{name}{{{labels}}} {value}");
+            }
+        }
+
+        let name = "tuxedo_errors_total";
+        header(
+            &mut output,
+            name,
+            "Errors by collection and stage",
+            "counter",
+        );
+        for (labels, metrics) in collections.iter() {
+            let errors = metrics.errors.lock().expect("Metrics errors poisoned");
+            for (stage, count) in errors.iter() {
+                let _ = writeln!(output, "{name}{{{labels},stage=\"{stage}\"}} {count}");
+            }
+        }
+
+        let name = "tuxedo_batch_write_duration_seconds";
+        header(
+            &mut output,
+            name,
+            "Time taken to write a batch into the target",
+            "histogram",
+        );
+        for (labels, metrics) in collections.iter() {
+            metrics.batch_latency.render(&mut output, name, labels);
+        }
+
+        let name = "tuxedo_retries_total";
+        header(
+            &mut output,
+            name,
+            "Commands retried by the MongoDB driver after a retryable error",
+            "counter",
+        );
+        for (client, tracker) in self
+            .registry
+            .retries
+            .lock()
+            .expect("Metrics registry poisoned")
+            .iter()
+        {
+            let retries = tracker.retries.load(Ordering::Relaxed);
+            let _ = writeln!(output, "{name}{{client=\"{client}\"}} {retries}");
+        }
+
+        let name = "tuxedo_tasks_in_flight";
+        header(
+            &mut output,
+            name,
+            "Tasks currently reading or writing",
+            "gauge",
+        );
+        let in_flight = self.registry.tasks_in_flight.load(Ordering::Relaxed);
+        let _ = writeln!(output, "{name} {in_flight}");
+
+        output
+    }
+
+    /// Serves the metrics on `http://<address>/metrics` until the returned handle is
+    /// aborted or the runtime shuts down
+    pub async fn serve(&self, address: impl ToSocketAddrs) -> TuxedoResult<JoinHandle<()>> {
+        let listener = TcpListener::bind(address).await?;
+        info!(address = %listener.local_addr()?, "Serving metrics");
+
+        let metrics = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        debug!(error = %e, "Failed to accept metrics connection");
+                        continue;
+                    }
+                };
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = metrics.respond(stream).await {
+                        debug!(error = %e, "Failed to answer metrics request");
+                    }
+                });
+            }
+        }))
+    }
+
+    /// Answers a single HTTP request, closing the connection afterwards
+    async fn respond(&self, mut stream: TcpStream) -> TuxedoResult<()> {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n")
+            && request.len() < MAX_REQUEST_SIZE
+        {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+
+        let request_line = String::from_utf8_lossy(&request);
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            _ => ("404 Not Found", "Not Found\n".to_string()),
+        };
+
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Decrements the in flight tasks gauge when dropped
+pub(crate) struct TaskGuard(Arc<Registry>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.tasks_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters of a single source/target collection pair
+#[derive(Debug, Default)]
+pub(crate) struct CollectionMetrics {
+    documents_read: AtomicU64,
+    documents_masked: AtomicU64,
+    documents_written: AtomicU64,
+    documents_failed: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    errors: Mutex<BTreeMap<ErrorStage, u64>>,
+    batch_latency: Histogram,
+}
+
+impl CollectionMetrics {
+    pub(crate) fn add_read(&self, size: u64) {
+        self.documents_read.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_masked(&self, count: u64) {
+        self.documents_masked.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_written(&self, count: u64) {
+        self.documents_written.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_written_batch(&self, size: u64, elapsed: Duration) {
+        self.bytes_written.fetch_add(size, Ordering::Relaxed);
+        self.batch_latency.observe(elapsed);
+    }
+
+    pub(crate) fn add_failed(&self, count: u64) {
+        self.documents_failed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_error(&self, stage: ErrorStage) {
+        *self
+            .errors
+            .lock()
+            .expect("Metrics errors poisoned")
+            .entry(stage)
+            .or_default() += 1;
+    }
+}
+
+/// Counts the retries of a client. The driver retries a command on the same session:
+/// a write resends its transaction number, a read resends the same command after a
+/// failed attempt.
+#[derive(Debug, Default)]
+struct RetryTracker {
+    retries: AtomicU64,
+    attempts: Mutex<Attempts>,
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    /// Session and attempt of every command in flight, by request id
+    in_flight: HashMap<i32, (Vec<u8>, Attempt)>,
+    /// Last attempt of every session and whether it failed
+    completed: HashMap<Vec<u8>, (Attempt, bool)>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Attempt {
+    Write(i64),
+    /// Digest of the command
+    Read(Vec<u8>),
+}
+
+impl RetryTracker {
+    fn observe(&self, event: &CommandEvent) {
+        match event {
+            CommandEvent::Started(event) => {
+                self.started(event.request_id, &event.command_name, &event.command)
+            }
+            CommandEvent::Succeeded(event) => self.completed(event.request_id, false),
+            CommandEvent::Failed(event) => self.completed(event.request_id, true),
+            _ => {}
+        }
+    }
+
+    fn started(&self, request_id: i32, command_name: &str, command: &Document) {
+        let Some((session, attempt)) = attempt(command_name, command) else {
+            return;
+        };
+        let mut attempts = self.attempts.lock().expect("Metrics retries poisoned");
+        let retried = attempts
+            .completed
+            .remove(&session)
+            .is_some_and(|(previous, failed)| {
+                previous == attempt && (failed || matches!(attempt, Attempt::Write(_)))
+            });
+        if retried {
+            self.retries.fetch_add(1, Ordering::Relaxed);
+        }
+        attempts.in_flight.insert(request_id, (session, attempt));
+    }
+
+    fn completed(&self, request_id: i32, failed: bool) {
+        let mut attempts = self.attempts.lock().expect("Metrics retries poisoned");
+        if let Some((session, attempt)) = attempts.in_flight.remove(&request_id) {
+            attempts.completed.insert(session, (attempt, failed));
+        }
+    }
+}
+
+/// Session and attempt of a command the driver may retry
+fn attempt(command_name: &str, command: &Document) -> Option<(Vec<u8>, Attempt)> {
+    let session = bson::to_vec(command.get_document("lsid").ok()?).ok()?;
+    if let Some(Bson::Int64(transaction)) = command.get("txnNumber") {
+        return Some((session, Attempt::Write(*transaction)));
+    }
+    if !RETRYABLE_READS.contains(&command_name) {
+        return None;
+    }
+
+    let mut command = command.clone();
+    command.remove("lsid");
+    command.remove("$clusterTime");
+    let digest = Md5::digest(bson::to_vec(&command).ok()?).to_vec();
+    Some((session, Attempt::Read(digest)))
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket of `LATENCY_BUCKETS`, not cumulative
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                output,
+                "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(output, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(output, "{name}_sum{{{labels}}} {sum}");
+        let _ = writeln!(output, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+fn header(output: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(output, "# HELP {name} {help}");
+    let _ = writeln!(output, "# TYPE {name} {kind}");
+}
+
+/// Escapes a Prometheus label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn find(session: i32, filter: Document) -> Document {
+        doc! { "find": "users", "filter": filter, "lsid": { "id": session } }
+    }
+
+    fn insert(session: i32, transaction: i64) -> Document {
+        doc! { "insert": "users", "lsid": { "id": session }, "txnNumber": transaction }
+    }
+
+    #[test]
+    fn counts_reads_sent_again_after_a_failure() {
+        let tracker = RetryTracker::default();
+        tracker.started(1, "find", &find(1, doc! { "a": 1 }));
+        tracker.completed(1, true);
+        tracker.started(2, "find", &find(1, doc! { "a": 1 }));
+        tracker.completed(2, false);
+        // The same read on the session once the previous one succeeded is a new one
+        tracker.started(3, "find", &find(1, doc! { "a": 1 }));
+        tracker.completed(3, true);
+        // Another read after a failure is not a retry either
+        tracker.started(4, "find", &find(1, doc! { "a": 2 }));
+        assert_eq!(tracker.retries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn counts_writes_sending_their_transaction_number_again() {
+        let tracker = RetryTracker::default();
+        tracker.started(1, "insert", &insert(1, 1));
+        tracker.completed(1, false);
+        tracker.started(2, "insert", &insert(1, 2));
+        tracker.completed(2, true);
+        tracker.started(3, "insert", &insert(1, 2));
+        tracker.completed(3, false);
+        // Other sessions are tracked apart
+        tracker.started(4, "insert", &insert(2, 2));
+        assert_eq!(tracker.retries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn ignores_commands_without_session_or_retries() {
+        let tracker = RetryTracker::default();
+        for request_id in 0..2 {
+            tracker.started(request_id, "find", &doc! { "find": "users" });
+            tracker.completed(request_id, true);
+            tracker.started(request_id + 10, "getMore", &find(1, doc! {}));
+            tracker.completed(request_id + 10, true);
+        }
+        assert_eq!(tracker.retries.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn renders_retries_by_client() {
+        let metrics = Metrics::new();
+        metrics.command_event_handler("source", None);
+        metrics.command_event_handler("target", None);
+        metrics.registry.retries.lock().unwrap()["target"]
+            .retries
+            .fetch_add(3, Ordering::Relaxed);
+
+        let output = metrics.render();
+        assert!(output.contains("tuxedo_retries_total{client=\"source\"} 0\n"));
+        assert!(output.contains("tuxedo_retries_total{client=\"target\"} 3\n"));
+    }
+}
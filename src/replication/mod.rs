@@ -1,5 +1,30 @@
+pub(crate) mod dump;
+pub(crate) mod filter;
+pub(crate) mod gridfs;
+pub(crate) mod indexes;
+pub(crate) mod infer;
+pub(crate) mod jsonl;
+pub(crate) mod leaks;
 pub(crate) mod manager;
 pub(crate) mod manager_builder;
+pub(crate) mod memory;
+#[cfg(feature = "metrics")]
+pub(crate) mod metrics;
+pub(crate) mod pipeline;
+pub(crate) mod plan;
 pub(crate) mod processor;
+pub(crate) mod progress;
+pub(crate) mod report;
+pub(crate) mod sample;
+pub(crate) mod scan;
+pub(crate) mod schema;
+pub(crate) mod sink;
+pub(crate) mod skeleton;
+pub(crate) mod source;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+pub(crate) mod subset;
 pub(crate) mod task;
 pub(crate) mod types;
+pub(crate) mod verify;
+pub(crate) mod views;
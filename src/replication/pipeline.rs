@@ -0,0 +1,261 @@
+use crate::{TuxedoError, TuxedoResult};
+use bson::{doc, Document};
+use mongodb::options::{AggregateOptions, FindOptions};
+
+/// Stages which need to see every input document at once. A pipeline containing
+/// any of them can not be split over several tasks and runs as a single task.
+const NON_PARTITIONABLE_STAGES: [&str; 20] = [
+    "$group",
+    "$sort",
+    "$limit",
+    "$skip",
+    "$sample",
+    "$bucket",
+    "$bucketAuto",
+    "$facet",
+    "$setWindowFields",
+    "$count",
+    "$sortByCount",
+    "$densify",
+    "$fill",
+    "$geoNear",
+    "$search",
+    "$searchMeta",
+    "$vectorSearch",
+    "$collStats",
+    "$indexStats",
+    "$unionWith",
+];
+
+/// Stages which write to the server themselves and are therefore never allowed
+const WRITE_STAGES: [&str; 2] = ["$out", "$merge"];
+
+/// Stages which must be the first stage of a pipeline, no `$match` can be put in front
+const LEADING_STAGES: [&str; 7] = [
+    "$geoNear",
+    "$search",
+    "$searchMeta",
+    "$vectorSearch",
+    "$collStats",
+    "$indexStats",
+    "$changeStream",
+];
+
+fn stage_name(stage: &Document) -> Option<&str> {
+    stage.keys().next().map(String::as_str)
+}
+
+/// Rejects pipelines tuxedo can't run as a source
+pub(crate) fn validate_pipeline(pipeline: &[Document]) -> TuxedoResult<()> {
+    for stage in pipeline.iter() {
+        let Some(name) = stage_name(stage) else {
+            return Err(TuxedoError::ConfigError(
+                "Aggregation pipeline contains an empty stage".into(),
+            ));
+        };
+
+        if WRITE_STAGES.contains(&name) {
+            return Err(TuxedoError::ConfigError(format!(
+                "Aggregation pipeline stage `{name}` is not supported, documents are written by tuxedo"
+            )));
+        }
+        if name == "$changeStream" {
+            return Err(TuxedoError::ConfigError(
+                "Aggregation pipeline stage `$changeStream` is not supported".into(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the input of the pipeline can be split over several tasks, i.e. every
+/// stage handles each document on its own (`$match`, `$project`, `$lookup`, `$unwind`, ...)
+pub(crate) fn is_partitionable(pipeline: &[Document]) -> bool {
+    !pipeline
+        .iter()
+        .filter_map(stage_name)
+        .any(|name| NON_PARTITIONABLE_STAGES.contains(&name))
+}
+
+/// Whether the pipeline has to be run as is, without a `$match` in front of it
+fn requires_leading_position(pipeline: &[Document]) -> bool {
+    pipeline
+        .first()
+        .and_then(stage_name)
+        .is_some_and(|name| LEADING_STAGES.contains(&name))
+}
+
+/// Builds the pipeline run for a slice of the input: the documents matching `query`,
+/// ordered by `_id` and paginated when `skip`/`limit` are set, fed into `pipeline`
+pub(crate) fn partition_pipeline(
+    query: &Document,
+    skip: Option<u64>,
+    limit: Option<i64>,
+    pipeline: &[Document],
+) -> TuxedoResult<Vec<Document>> {
+    if requires_leading_position(pipeline) {
+        if !query.is_empty() {
+            return Err(TuxedoError::ConfigError(format!(
+                "Aggregation pipeline starting with `{}` can not be combined with a query, subset or sample",
+                pipeline.first().and_then(stage_name).unwrap_or_default()
+            )));
+        }
+        return Ok(pipeline.to_vec());
+    }
+
+    let mut stages = vec![doc! { "$match": query.clone() }];
+    if let Some(limit) = limit {
+        stages.push(doc! { "$sort": { "_id": 1 } });
+        stages.push(doc! { "$skip": skip.unwrap_or_default() as i64 });
+        stages.push(doc! { "$limit": limit });
+    }
+    stages.extend(pipeline.iter().cloned());
+
+    Ok(stages)
+}
+
+/// Pipeline counting the documents `pipeline` produces for the input matching `query`
+pub(crate) fn count_pipeline(
+    query: &Document,
+    pipeline: &[Document],
+) -> TuxedoResult<Vec<Document>> {
+    let mut stages = partition_pipeline(query, None, None, pipeline)?;
+    stages.push(doc! { "$count": "total" });
+    Ok(stages)
+}
+
+/// Carries the read settings of a `find` over to an aggregation
+pub(crate) fn aggregate_options(read_options: &FindOptions) -> AggregateOptions {
+    let mut options = AggregateOptions::default();
+    options.allow_disk_use = Some(read_options.allow_disk_use.unwrap_or(true));
+    options.batch_size = read_options.batch_size;
+    options.collation = read_options.collation.clone();
+    options.comment = read_options.comment.clone();
+    options.hint = read_options.hint.clone();
+    options.max_await_time = read_options.max_await_time;
+    options.max_time = read_options.max_time;
+    options.read_concern = read_options.read_concern.clone();
+    options.selection_criteria = read_options.selection_criteria.clone();
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_non_partitionable_stages() {
+        assert!(is_partitionable(&[]));
+        assert!(is_partitionable(&[
+            doc! { "$match": { "active": true } },
+            doc! { "$project": { "name": 1 } },
+            doc! { "$lookup": { "from": "orders", "localField": "_id", "foreignField": "user", "as": "orders" } },
+            doc! { "$unwind": "$orders" },
+            doc! { "$addFields": { "total": "$orders.total" } },
+        ]));
+
+        for name in NON_PARTITIONABLE_STAGES {
+            let pipeline = [doc! { "$match": {} }, doc! { name: {} }];
+            assert!(!is_partitionable(&pipeline), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_write_and_change_stream_stages() {
+        assert!(
+            validate_pipeline(&[doc! { "$match": {} }, doc! { "$group": { "_id": "$a" } }]).is_ok()
+        );
+        for stage in [
+            doc! { "$out": "copy" },
+            doc! { "$merge": { "into": "copy" } },
+            doc! { "$changeStream": {} },
+            Document::new(),
+        ] {
+            assert!(
+                validate_pipeline(std::slice::from_ref(&stage)).is_err(),
+                "{stage}"
+            );
+        }
+    }
+
+    #[test]
+    fn puts_the_query_in_front_of_the_pipeline() {
+        let pipeline = [doc! { "$project": { "name": 1 } }];
+        let query = doc! { "plan": "pro" };
+
+        assert_eq!(
+            partition_pipeline(&query, None, None, &pipeline).unwrap(),
+            [
+                doc! { "$match": { "plan": "pro" } },
+                doc! { "$project": { "name": 1 } }
+            ]
+        );
+        // A skip alone doesn't slice the input
+        assert_eq!(
+            partition_pipeline(&query, Some(10), None, &pipeline).unwrap(),
+            [
+                doc! { "$match": { "plan": "pro" } },
+                doc! { "$project": { "name": 1 } }
+            ]
+        );
+    }
+
+    #[test]
+    fn slices_the_input_ordered_by_id() {
+        let pipeline = [doc! { "$unwind": "$tags" }];
+
+        assert_eq!(
+            partition_pipeline(&doc! { "plan": "pro" }, Some(200), Some(100), &pipeline).unwrap(),
+            [
+                doc! { "$match": { "plan": "pro" } },
+                doc! { "$sort": { "_id": 1 } },
+                doc! { "$skip": 200_i64 },
+                doc! { "$limit": 100_i64 },
+                doc! { "$unwind": "$tags" },
+            ]
+        );
+        assert_eq!(
+            partition_pipeline(&Document::new(), None, Some(100), &pipeline).unwrap(),
+            [
+                doc! { "$match": {} },
+                doc! { "$sort": { "_id": 1 } },
+                doc! { "$skip": 0_i64 },
+                doc! { "$limit": 100_i64 },
+                doc! { "$unwind": "$tags" },
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_leading_stages_first() {
+        let pipeline = [
+            doc! { "$geoNear": { "near": [0, 0], "distanceField": "distance" } },
+            doc! { "$limit": 5 },
+        ];
+
+        assert_eq!(
+            partition_pipeline(&Document::new(), None, None, &pipeline).unwrap(),
+            pipeline
+        );
+        assert!(matches!(
+            partition_pipeline(&doc! { "plan": "pro" }, None, None, &pipeline),
+            Err(TuxedoError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn counts_the_pipeline_output() {
+        let pipeline = [doc! { "$unwind": "$tags" }];
+
+        assert_eq!(
+            count_pipeline(&doc! { "plan": "pro" }, &pipeline).unwrap(),
+            [
+                doc! { "$match": { "plan": "pro" } },
+                doc! { "$unwind": "$tags" },
+                doc! { "$count": "total" },
+            ]
+        );
+        assert!(count_pipeline(&doc! { "plan": "pro" }, &[doc! { "$search": {} }]).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use super::sample::Sample;
+use super::types::ReplicationStrategy;
+use bson::Document;
+
+/// What `ReplicationManager::run` would copy, see `ReplicationManager::plan`
+#[derive(Debug, Clone)]
+pub struct RunPlan {
+    pub strategy: ReplicationStrategy,
+    /// In the order the processors were added
+    pub collections: Vec<CollectionPlan>,
+    /// Whether the documents are restricted to a subset, which is only computed
+    /// during the run
+    pub subset: bool,
+}
+
+/// Source and target of a single processor
+#[derive(Debug, Clone, Default)]
+pub struct CollectionPlan {
+    /// Name of the database mapping the processor is bound to
+    pub database: String,
+    pub source_collection: String,
+    pub target_collection: String,
+    pub query: Document,
+    /// Whether an aggregation pipeline reshapes the documents
+    pub pipeline: bool,
+    pub sample: Option<Sample>,
+    /// Whether the documents are masked: by a model, rules or a lambda
+    pub masked: bool,
+    pub sensitive_paths: Vec<String>,
+    /// Source documents matching the query, before any sample or subset
+    pub source_documents: Option<u64>,
+    /// Documents already in the target collection, dropped by the run. `None` when
+    /// the target can't be read back.
+    pub target_documents: Option<u64>,
+    pub error: Option<String>,
+}
@@ -1,17 +1,26 @@
 use super::{
+    filter::Filter,
+    indexes::IndexCopyOptions,
+    leaks::{detect_leaks, LeakScan},
     manager::ReplicationConfig,
+    pipeline::{count_pipeline, is_partitionable, partition_pipeline, validate_pipeline},
+    plan::CollectionPlan,
+    report::{CollectionStats, ErrorStage, LeakReport, VerificationReport},
+    sample::{select_sample_ids, Sample},
     task::{ModelTask, ReplicatorTask, Task},
-    types::DatabasePair,
+    types::{DatabasePair, ReplicationStrategy, DEFAULT_DATABASE},
+    verify::{verify_collection, ExpectedDocument, Verification},
 };
 use crate::replication::task::TaskConfig;
-use crate::{Mask, TuxedoResult};
+use crate::{Mask, MaskRules, TuxedoError, TuxedoResult};
 use async_trait::async_trait;
-use bson::{Document, RawDocumentBuf};
-use indicatif::ProgressBar;
+use bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
 use serde::{de::DeserializeOwned, Serialize};
 use std::marker::PhantomData;
 use std::sync::Arc;
 use tokio::sync::mpsc;
+use tracing::{error, info_span, Span};
 
 #[async_trait]
 pub(crate) trait Processor: Send + Sync {
@@ -20,41 +29,192 @@ pub(crate) trait Processor: Send + Sync {
         dbs: Arc<DatabasePair>,
         task_sender: mpsc::Sender<Box<dyn Task>>,
         default_config: ReplicationConfig,
-        progress_bar: ProgressBar,
+        stats: Arc<CollectionStats>,
+        ids: Option<Arc<Vec<Bson>>>,
     );
 
     async fn get_total_documents(
         &self,
         dbs: &Arc<DatabasePair>,
         query: Document,
+        ids: Option<&[Bson]>,
+        stats: &CollectionStats,
     ) -> TuxedoResult<usize> {
-        match dbs
-            .read_total_documents::<RawDocumentBuf>(self.collection_name(), query)
-            .await
-        {
-            Ok(total_documents) => Ok(total_documents),
+        let total_documents = match ids {
+            // Every id exists in the source, only a query can narrow them down further
+            Some(ids) if query.is_empty() => Ok(ids.len()),
+            Some(ids) => {
+                let mut total_documents = 0;
+                let mut result = Ok(());
+                for chunk in ids.chunks(ID_CHUNK_SIZE) {
+                    match dbs
+                        .read_total_documents(
+                            self.collection_name(),
+                            restrict_to_ids(&query, chunk),
+                        )
+                        .await
+                    {
+                        Ok(count) => total_documents += count,
+                        Err(e) => {
+                            result = Err(e);
+                            break;
+                        }
+                    }
+                }
+                result.map(|_| total_documents)
+            }
+            None => {
+                dbs.read_total_documents(self.collection_name(), query)
+                    .await
+            }
+        };
+
+        match total_documents {
+            Ok(total_documents) => {
+                stats.set_total(total_documents as u64);
+                Ok(total_documents)
+            }
             Err(e) => {
-                println!(
-                    "Could not get total number of documents for collection: `{}`. Collection will be skipped. Encountered error: {e}",
-                    self.collection_name(),
+                error!(
+                    collection = self.collection_name(),
+                    error = %e,
+                    "Could not get total number of documents, collection will be skipped"
+                );
+                stats.add_error(
+                    ErrorStage::Count,
+                    format!("Could not count source documents: {e}"),
                 );
                 Err(e)
             }
         }
     }
 
-    fn setup_progress_bar(
+    /// Rejects an aggregation pipeline which can't be used as the source of the processor
+    fn check_pipeline(
+        &self,
+        pipeline: Option<&[Document]>,
+        stats: &CollectionStats,
+    ) -> TuxedoResult<()> {
+        let Some(pipeline) = pipeline else {
+            return Ok(());
+        };
+
+        validate_pipeline(pipeline).inspect_err(|e| {
+            error!(
+                collection = self.collection_name(),
+                error = %e,
+                "Invalid aggregation pipeline, collection will be skipped"
+            );
+            stats.add_error(
+                ErrorStage::Pipeline,
+                format!("Invalid aggregation pipeline: {e}"),
+            );
+        })
+    }
+
+    /// Number of documents the aggregation pipeline produces from the documents matching
+    /// `query`, which can differ from the number of input documents (`$unwind`, `$match`, ...)
+    async fn get_total_pipeline_documents(
         &self,
-        progress_bar: ProgressBar,
-        total_documents: usize,
-        entity_name: &str,
-    ) -> Arc<ProgressBar> {
-        let progress_bar = Arc::new(progress_bar);
+        dbs: &Arc<DatabasePair>,
+        query: &Document,
+        pipeline: &[Document],
+        ids: Option<&[Bson]>,
+        stats: &CollectionStats,
+    ) -> TuxedoResult<usize> {
+        let inputs = match ids {
+            Some(ids) if is_partitionable(pipeline) => ids
+                .chunks(ID_CHUNK_SIZE)
+                .map(|chunk| restrict_to_ids(query, chunk))
+                .collect(),
+            Some(ids) => match restrict_unpartitioned_to_ids(query, ids) {
+                Ok(input) => vec![input],
+                Err(e) => {
+                    error!(
+                        collection = self.collection_name(),
+                        error = %e,
+                        "Aggregation pipeline can't be restricted to the selected documents, collection will be skipped"
+                    );
+                    stats.add_error(ErrorStage::Pipeline, e.to_string());
+                    return Err(e);
+                }
+            },
+            None => vec![query.clone()],
+        };
+
+        let mut result = Ok(0);
+        for input in inputs.iter() {
+            let count = match count_pipeline(input, pipeline) {
+                Ok(count_pipeline) => {
+                    count_pipeline_documents(dbs, self.collection_name(), count_pipeline).await
+                }
+                Err(e) => Err(e),
+            };
+            match count {
+                Ok(count) => result = result.map(|total| total + count),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        match result {
+            Ok(total_documents) => {
+                stats.set_total(total_documents as u64);
+                Ok(total_documents)
+            }
+            Err(e) => {
+                error!(
+                    collection = self.collection_name(),
+                    error = %e,
+                    "Could not get total number of documents produced by the aggregation pipeline, collection will be skipped"
+                );
+                stats.add_error(
+                    ErrorStage::Count,
+                    format!("Could not count pipeline documents: {e}"),
+                );
+                Err(e)
+            }
+        }
+    }
 
-        progress_bar.set_length(total_documents as u64);
-        progress_bar.set_message(format!("{} ({})", self.collection_name(), entity_name,));
+    /// Narrows the processor down to a random sample of its documents when a sample
+    /// is configured, otherwise returns the `ids` it was restricted to unchanged
+    async fn apply_sample(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        query: &Document,
+        ids: Option<Arc<Vec<Bson>>>,
+        sample: Option<Sample>,
+        seed: Option<u64>,
+        stats: &CollectionStats,
+    ) -> TuxedoResult<Option<Arc<Vec<Bson>>>> {
+        let Some(sample) = sample else {
+            return Ok(ids);
+        };
 
-        progress_bar
+        match select_sample_ids(
+            dbs,
+            self.collection_name(),
+            query,
+            ids.as_deref().map(Vec::as_slice),
+            sample,
+            seed,
+        )
+        .await
+        {
+            Ok(sample_ids) => Ok(Some(Arc::new(sample_ids))),
+            Err(e) => {
+                error!(
+                    collection = self.collection_name(),
+                    error = %e,
+                    "Could not select a sample, collection will be skipped"
+                );
+                stats.add_error(ErrorStage::Sample, format!("Could not select sample: {e}"));
+                Err(e)
+            }
+        }
     }
 
     async fn setup_adaptive_batching(&self, dbs: &Arc<DatabasePair>) -> TuxedoResult<u64> {
@@ -69,46 +229,424 @@ pub(crate) trait Processor: Send + Sync {
         Ok(batch_size)
     }
 
-    async fn copy_indexes(&self, dbs: &Arc<DatabasePair>) {
-        if let Err(e) = dbs.copy_indexes(self.collection_name()).await {
-            println!(
-                "Error when copying indexes for collection `{}` from source to target - Error: {:?}",
+    async fn copy_indexes(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        copy_options: &IndexCopyOptions,
+        stats: &CollectionStats,
+    ) {
+        match dbs
+            .copy_indexes(
                 self.collection_name(),
-                e
+                self.target_collection_name(),
+                copy_options,
             )
+            .await
+        {
+            Ok(result) => {
+                stats.add_indexes_copied(&result.created);
+                for (name, error) in result.failed {
+                    error!(
+                        index = %name,
+                        collection = self.collection_name(),
+                        target_collection = self.target_collection_name(),
+                        %error,
+                        "Error when copying index"
+                    );
+                    stats.add_index_failure(name, error);
+                }
+            }
+            Err(e) => {
+                error!(
+                    collection = self.collection_name(),
+                    target_collection = self.target_collection_name(),
+                    error = %e,
+                    "Error when copying indexes"
+                );
+                stats.add_error(ErrorStage::Index, format!("Failed to copy indexes: {e}"));
+            }
+        }
+    }
+
+    /// Compares the source documents of the processor with its target collection once
+    /// the run completed. Counts are only compared when `compare_count` is set, i.e. no
+    /// other processor writes into the same target collection.
+    async fn verify(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        strategy: &ReplicationStrategy,
+        stats: &CollectionStats,
+        compare_count: bool,
+    ) -> VerificationReport;
+
+    /// Scans the target collection for source values at the processor's sensitive
+    /// paths once the run completed, `None` when it has none
+    async fn detect_leaks(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        stats: &CollectionStats,
+    ) -> Option<LeakReport>;
+
+    /// Compares the source documents matching `query` with the target collection,
+    /// their content too when `expected` gives the target document of a source one
+    async fn verify_documents(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        query: &Document,
+        stats: &CollectionStats,
+        compare_count: bool,
+        expected: Option<ExpectedDocument<'_>>,
+    ) -> VerificationReport {
+        let report = VerificationReport::new(
+            self.database_name(),
+            self.collection_name(),
+            self.target_collection_name(),
+        );
+        let ids = stats.ids();
+        let verification = Verification {
+            source_collection: self.collection_name(),
+            target_collection: self.target_collection_name(),
+            query,
+            ids: ids.as_deref().map(Vec::as_slice),
+            compare_count,
+            expected,
+        };
+        verify_collection(dbs, verification, report).await
+    }
+
+    /// Scans the target collection for the values of the source documents matching
+    /// `query` at `paths`, `None` without paths
+    async fn scan_leaks(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        query: &Document,
+        stats: &CollectionStats,
+        paths: &[String],
+    ) -> Option<LeakReport> {
+        if paths.is_empty() {
+            return None;
         }
+
+        let report = LeakReport::new(
+            self.database_name(),
+            self.collection_name(),
+            self.target_collection_name(),
+            paths.to_vec(),
+        );
+        let ids = stats.ids();
+        let scan = LeakScan {
+            source_collection: self.collection_name(),
+            target_collection: self.target_collection_name(),
+            query,
+            ids: ids.as_deref().map(Vec::as_slice),
+            paths,
+        };
+        Some(detect_leaks(dbs, scan, report).await)
     }
 
+    /// A source document as the run would write it, `None` when the processor does
+    /// not write it as read: outside of its query, reshaped by a pipeline or failing
+    /// to deserialize
+    fn mask_document(
+        &self,
+        document: &Document,
+        strategy: &ReplicationStrategy,
+    ) -> Option<Document>;
+
+    /// What the processor would copy under `strategy`, without document counts
+    fn plan(&self, strategy: &ReplicationStrategy) -> CollectionPlan;
+
+    /// Rejects a configuration the processor can't run with, checked by the builder
+    /// before the target is touched
+    fn validate(&self) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    /// Name of the collection read from the source database
     fn collection_name(&self) -> &str;
+
+    /// Name of the collection written to in the target database.
+    /// Defaults to the source collection name unless a mapping was configured.
+    fn target_collection_name(&self) -> &str;
+
+    /// Every collection this processor writes to on the target, dropped before the run
+    fn target_collection_names(&self) -> Vec<String> {
+        vec![self.target_collection_name().to_string()]
+    }
+
+    /// Name of the source/target database mapping this processor is bound to
+    fn database_name(&self) -> &str;
+}
+
+/// Rejects batch sizes of 0 set on a single collection
+pub(crate) fn check_batch_sizes(collection_name: &str, sizes: &[Option<u64>]) -> TuxedoResult<()> {
+    if sizes.contains(&Some(0)) {
+        return Err(TuxedoError::ConfigError(format!(
+            "Collection `{collection_name}` has a batch size of 0"
+        )));
+    }
+    Ok(())
+}
+
+/// Optional masking function applied to each document by a replicator
+pub(crate) type MaskingLambda = Arc<dyn Fn(&mut Document) + Send + Sync>;
+
+/// A processor copying a collection document by document, sharing the counting,
+/// sampling and batching of models and replicators
+#[async_trait]
+pub(crate) trait DocumentProcessor: Processor {
+    fn config(&self) -> &ProcessorConfig;
+
+    /// Task reading and writing one batch of documents
+    fn new_task(
+        &self,
+        dbs: Arc<DatabasePair>,
+        config: TaskConfig,
+        strategy: &ReplicationStrategy,
+        stats: Arc<CollectionStats>,
+    ) -> Box<dyn Task>;
+
+    /// Counts the documents of the processor and sends a task per batch to the workers
+    async fn run_batches(
+        &self,
+        dbs: Arc<DatabasePair>,
+        task_sender: mpsc::Sender<Box<dyn Task>>,
+        default_config: ReplicationConfig,
+        stats: Arc<CollectionStats>,
+        ids: Option<Arc<Vec<Bson>>>,
+    ) {
+        let mut batch_size = self
+            .config()
+            .batch_size
+            .unwrap_or(default_config.batch_size);
+        let write_batch_size = self
+            .config()
+            .write_batch_size
+            .unwrap_or(default_config.write_batch_size);
+
+        if self
+            .check_pipeline(self.config().pipeline.as_deref(), &stats)
+            .is_err()
+        {
+            return;
+        }
+
+        let ids = match self
+            .apply_sample(
+                &dbs,
+                &self.config().query,
+                ids,
+                self.config().sample,
+                self.config().sample_seed,
+                &stats,
+            )
+            .await
+        {
+            Ok(ids) => ids,
+            Err(_) => return,
+        };
+        stats.set_ids(ids.clone());
+
+        let total_documents = match self
+            .get_total_documents(
+                &dbs,
+                self.config().query.clone(),
+                ids.as_deref().map(Vec::as_slice),
+                &stats,
+            )
+            .await
+        {
+            Ok(total_documents) => total_documents,
+            Err(_) => return,
+        };
+
+        // The progress follows the documents written, which a pipeline may reshape
+        let total_output_documents = match self.config().pipeline.as_deref() {
+            Some(pipeline) => match self
+                .get_total_pipeline_documents(
+                    &dbs,
+                    &self.config().query,
+                    pipeline,
+                    ids.as_deref().map(Vec::as_slice),
+                    &stats,
+                )
+                .await
+            {
+                Ok(total_output_documents) => total_output_documents,
+                Err(_) => return,
+            },
+            None => total_documents,
+        };
+
+        if total_documents == 0 || total_output_documents == 0 {
+            return;
+        }
+
+        if self.config().adaptive_batching == Some(true) || default_config.adaptive_batching {
+            if let Ok(adaptive_batch_size) = self.setup_adaptive_batching(&dbs).await {
+                batch_size = adaptive_batch_size;
+            }
+        }
+
+        // Sources which have to be scanned from the start for every slice are read at once
+        if !dbs.supports_partitioned_reads() {
+            batch_size = (total_documents as u64).max(1);
+        }
+
+        let batches = match self.config().pipeline.as_deref() {
+            Some(pipeline) => plan_pipeline_batches(
+                &self.config().query,
+                total_documents,
+                batch_size,
+                ids.as_deref().map(Vec::as_slice),
+                pipeline,
+            ),
+            None => plan_batches(
+                &self.config().query,
+                total_documents,
+                batch_size,
+                ids.as_deref().map(Vec::as_slice),
+            ),
+        };
+        let batch_count = batches.len();
+        let strategy = default_config.strategy;
+        let write_options = default_config.write_options;
+
+        for (batch_index, batch) in batches.into_iter().enumerate() {
+            let dbs = Arc::clone(&dbs);
+            let stats = Arc::clone(&stats);
+
+            let mut read_options = default_config.read_options.clone();
+            batch.apply(&mut read_options);
+
+            let pipeline = match self.config().pipeline.as_deref().map(|p| batch.pipeline(p)) {
+                Some(Ok(pipeline)) => Some(pipeline),
+                Some(Err(e)) => {
+                    error!(
+                        collection = %self.collection_name(),
+                        batch = batch_index + 1,
+                        batches = batch_count,
+                        error = %e,
+                        "Could not build aggregation pipeline, stopping processor"
+                    );
+                    stats.add_error(
+                        ErrorStage::Pipeline,
+                        format!("Could not build aggregation pipeline: {e}"),
+                    );
+                    break;
+                }
+                None => None,
+            };
+
+            let span = task_span(batch_index, batch_count, &read_options);
+            let task = self.new_task(
+                dbs,
+                TaskConfig {
+                    query: batch.query,
+                    pipeline,
+                    write_batch_size,
+                    read_options,
+                    write_options: write_options.clone(),
+                    span,
+                },
+                &strategy,
+                stats,
+            );
+
+            if task_sender.send(task).await.is_err() {
+                error!(
+                    collection = %self.collection_name(),
+                    batch = batch_index + 1,
+                    batches = batch_count,
+                    "Failed to send task to worker pool, channel closed, stopping processor"
+                );
+                // Channel closed, stop sending tasks
+                break;
+            }
+        }
+    }
+
+    /// Verifies the documents unless a pipeline reshapes them
+    async fn verify_copy(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        stats: &CollectionStats,
+        compare_count: bool,
+        expected: Option<ExpectedDocument<'_>>,
+    ) -> VerificationReport {
+        if self.config().pipeline.is_some() {
+            return VerificationReport::new(
+                self.database_name(),
+                self.collection_name(),
+                self.target_collection_name(),
+            )
+            .skipped("An aggregation pipeline reshapes the documents");
+        }
+        self.verify_documents(dbs, &self.config().query, stats, compare_count, expected)
+            .await
+    }
+
+    fn collection_plan(&self, masked: bool) -> CollectionPlan {
+        let config = self.config();
+        CollectionPlan {
+            database: self.database_name().to_string(),
+            source_collection: self.collection_name().to_string(),
+            target_collection: self.target_collection_name().to_string(),
+            query: config.query.clone(),
+            pipeline: config.pipeline.is_some(),
+            sample: config.sample,
+            masked,
+            sensitive_paths: config.sensitive_paths.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 pub(crate) struct ModelProcessor<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> {
     config: ProcessorConfig,
     collection_name: String,
+    target_collection_name: String,
     _phantom_data: PhantomData<T>,
 }
 
 impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> ModelProcessor<T> {
     pub(crate) fn new(collection_name: impl Into<String>, config: ProcessorConfig) -> Self {
+        let collection_name = collection_name.into();
+        let target_collection_name = config
+            .target_collection
+            .clone()
+            .unwrap_or_else(|| collection_name.clone());
+
         Self {
             config,
-            collection_name: collection_name.into(),
+            collection_name,
+            target_collection_name,
             _phantom_data: PhantomData,
         }
     }
 }
 
 pub(crate) struct ReplicatorProcessor<T: Send + Sync> {
-    config: ReplicatorConfig,
+    config: ProcessorConfig,
+    lambda: Option<MaskingLambda>,
     collection_name: String,
+    target_collection_name: String,
     _phantom_data: PhantomData<T>,
 }
 
 impl<T: Send + Sync> ReplicatorProcessor<T> {
     pub(crate) fn new(config: ReplicatorConfig, collection_name: String) -> Self {
+        let ReplicatorConfig { config, lambda } = config;
+        let target_collection_name = config
+            .target_collection
+            .clone()
+            .unwrap_or_else(|| collection_name.clone());
+
         Self {
             config,
+            lambda,
             collection_name,
+            target_collection_name,
             _phantom_data: PhantomData,
         }
     }
@@ -123,100 +661,258 @@ impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin + 'static> Pro
         dbs: Arc<DatabasePair>,
         task_sender: mpsc::Sender<Box<dyn Task>>,
         default_config: ReplicationConfig,
-        progress_bar: ProgressBar,
+        stats: Arc<CollectionStats>,
+        ids: Option<Arc<Vec<Bson>>>,
     ) {
-        let mut batch_size = self.config.batch_size.unwrap_or(default_config.batch_size);
-        let write_batch_size = self
-            .config
-            .write_batch_size
-            .unwrap_or(default_config.write_batch_size);
-
-        let total_documents = match self
-            .get_total_documents(&dbs, self.config.query.clone())
+        self.run_batches(dbs, task_sender, default_config, stats, ids)
             .await
-        {
-            Ok(total_documents) => total_documents,
-            Err(_) => return,
+    }
+
+    async fn verify(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        strategy: &ReplicationStrategy,
+        stats: &CollectionStats,
+        compare_count: bool,
+    ) -> VerificationReport {
+        // Cloned documents are written as they round-trip through the model
+        let round_trip = |document: Document| -> TuxedoResult<Document> {
+            Ok(bson::to_document(&bson::from_document::<T>(document)?)?)
         };
+        let expected = matches!(strategy, ReplicationStrategy::Clone)
+            .then_some(&round_trip as ExpectedDocument);
+        self.verify_copy(dbs, stats, compare_count, expected).await
+    }
 
-        let progress_bar = self.setup_progress_bar(
-            progress_bar,
-            total_documents,
-            std::any::type_name::<T>()
-                .split("::")
-                .last()
-                .expect("Expected to get model name for progress bar"),
-        );
+    async fn detect_leaks(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        stats: &CollectionStats,
+    ) -> Option<LeakReport> {
+        self.scan_leaks(dbs, &self.config.query, stats, &self.config.sensitive_paths)
+            .await
+    }
 
-        if total_documents == 0 {
-            progress_bar.finish_and_clear();
-            return;
+    fn mask_document(
+        &self,
+        document: &Document,
+        strategy: &ReplicationStrategy,
+    ) -> Option<Document> {
+        if self.config.pipeline.is_some() || !matches_query(&self.config.query, document) {
+            return None;
         }
 
-        if self.config.adaptive_batching == Some(true) || default_config.adaptive_batching {
-            if let Ok(adaptive_batch_size) = self.setup_adaptive_batching(&dbs).await {
-                batch_size = adaptive_batch_size;
-            }
+        let mut record = bson::from_document::<T>(document.clone()).ok()?;
+        if matches!(strategy, ReplicationStrategy::Mask) {
+            record.mask();
         }
+        bson::to_document(&record).ok()
+    }
 
-        let batch_count = total_documents.div_ceil(batch_size as usize);
-        let strategy = default_config.strategy;
-        let write_options = default_config.write_options;
+    fn plan(&self, strategy: &ReplicationStrategy) -> CollectionPlan {
+        self.collection_plan(matches!(strategy, ReplicationStrategy::Mask))
+    }
+
+    fn validate(&self) -> TuxedoResult<()> {
+        check_batch_sizes(
+            &self.collection_name,
+            &[self.config.batch_size, self.config.write_batch_size],
+        )
+    }
+
+    fn collection_name(&self) -> &str {
+        &self.collection_name
+    }
+
+    fn target_collection_name(&self) -> &str {
+        &self.target_collection_name
+    }
+
+    fn database_name(&self) -> &str {
+        self.config.database.as_deref().unwrap_or(DEFAULT_DATABASE)
+    }
+}
+
+impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin + 'static> DocumentProcessor
+    for ModelProcessor<T>
+{
+    fn config(&self) -> &ProcessorConfig {
+        &self.config
+    }
+
+    fn new_task(
+        &self,
+        dbs: Arc<DatabasePair>,
+        config: TaskConfig,
+        strategy: &ReplicationStrategy,
+        stats: Arc<CollectionStats>,
+    ) -> Box<dyn Task> {
+        Box::new(ModelTask::<T>::new(
+            dbs,
+            self.collection_name.clone(),
+            self.target_collection_name.clone(),
+            config,
+            strategy.clone(),
+            stats,
+        ))
+    }
+}
+
+/// Whether a document matches a processor's query, queries the in memory filter
+/// does not support are assumed to match
+pub(crate) fn matches_query(query: &Document, document: &Document) -> bool {
+    Filter::parse(query).map_or(true, |filter| filter.matches(document))
+}
+
+/// Span of the task reading one batch, nested under the span of its collection
+pub(crate) fn task_span(
+    batch_index: usize,
+    batch_count: usize,
+    read_options: &FindOptions,
+) -> Span {
+    info_span!(
+        "task",
+        batch = batch_index + 1,
+        batches = batch_count,
+        skip = read_options.skip,
+        limit = read_options.limit,
+    )
+}
+
+/// Maximum number of `_id`s in a single `$in` filter when counting
+pub(crate) const ID_CHUNK_SIZE: usize = 10_000;
+
+/// Largest `_id` restriction of a pipeline which can't be split by `_id`, leaving
+/// room below the 16 MB BSON limit for the rest of the command
+const MAX_UNPARTITIONED_RESTRICTION_BYTES: usize = 8 * 1024 * 1024;
+
+/// Query and pagination of a single task
+pub(crate) struct TaskBatch {
+    pub(crate) query: Document,
+    skip: Option<u64>,
+    limit: Option<i64>,
+}
+
+impl TaskBatch {
+    /// Aggregation pipeline reading the documents of this batch through `pipeline`
+    pub(crate) fn pipeline(&self, pipeline: &[Document]) -> TuxedoResult<Vec<Document>> {
+        partition_pipeline(&self.query, self.skip, self.limit, pipeline)
+    }
+
+    pub(crate) fn apply(&self, read_options: &mut FindOptions) {
+        if let Some(limit) = self.limit {
+            read_options.skip = self.skip;
+            read_options.limit = Some(limit);
+            read_options.batch_size = Some(limit as u32);
+        }
+    }
+}
 
-        for batch_index in 0..batch_count {
+/// Splits the work of a processor into tasks, either by skip/limit over the query or,
+/// when the processor is restricted to an explicit set of `_id`s (subsetting), by
+/// chunks of `batch_size` ids.
+pub(crate) fn plan_batches(
+    query: &Document,
+    total_documents: usize,
+    batch_size: u64,
+    ids: Option<&[Bson]>,
+) -> Vec<TaskBatch> {
+    let batch_size = batch_size.max(1);
+
+    if let Some(ids) = ids {
+        return ids
+            .chunks(batch_size as usize)
+            .map(|chunk| TaskBatch {
+                query: restrict_to_ids(query, chunk),
+                skip: None,
+                limit: None,
+            })
+            .collect();
+    }
+
+    let batch_count = total_documents.div_ceil(batch_size as usize);
+    (0..batch_count)
+        .map(|batch_index| {
             let skip = batch_index * batch_size as usize;
             let remaining_documents = total_documents.saturating_sub(skip);
             let limit = batch_size.min(remaining_documents as u64) as i64;
 
-            // This should never happen in theory
-            if limit == 0 {
-                // No more documents to process
-                break;
+            TaskBatch {
+                query: query.clone(),
+                skip: Some(skip as u64),
+                limit: Some(limit),
             }
+        })
+        .collect()
+}
 
-            let dbs = Arc::clone(&dbs);
-            let query = self.config.query.clone();
-            let strategy = strategy.clone();
-            let progress_bar = Arc::clone(&progress_bar);
-
-            let mut read_options = default_config.read_options.clone();
-            // Ensure stable sort order for skip/limit pagination
-            // if read_options.sort.is_none() {
-            //     read_options.sort = Some(doc! { "_id": 1 });
-            // }
-            read_options.skip = (skip as u64).into();
-            read_options.limit = limit.into();
-            read_options.batch_size = Some(limit as u32);
+/// Restricts a pipeline which can't be split by `_id` to every id at once, which has
+/// to fit in a single command
+fn restrict_unpartitioned_to_ids(query: &Document, ids: &[Bson]) -> TuxedoResult<Document> {
+    let restricted = restrict_to_ids(query, ids);
+    if bson::to_vec(&restricted)?.len() > MAX_UNPARTITIONED_RESTRICTION_BYTES {
+        return Err(TuxedoError::ConfigError(format!(
+            "{} subset or sample documents are too many for an aggregation pipeline which can't be split by `_id`, \
+             select fewer documents or use per-document stages only",
+            ids.len()
+        )));
+    }
+    Ok(restricted)
+}
 
-            let task = Box::new(ModelTask::<T>::new(
-                dbs,
-                self.collection_name.clone(),
-                TaskConfig {
-                    query,
-                    write_batch_size,
-                    read_options,
-                    write_options: write_options.clone(),
-                },
-                strategy,
-                progress_bar,
-            ));
+/// Narrows `query` down to the documents with the given `_id`s
+pub(crate) fn restrict_to_ids(query: &Document, ids: &[Bson]) -> Document {
+    let id_filter = doc! { "_id": { "$in": ids } };
+    if query.is_empty() {
+        id_filter
+    } else {
+        doc! { "$and": [query.clone(), id_filter] }
+    }
+}
 
-            if task_sender.send(task).await.is_err() {
-                println!(
-                    "Failed to send task to worker pool for collection '{}' (batch {}/{}). Channel closed, stopping processor.",
-                    &self.collection_name,
-                    batch_index + 1,
-                    batch_count
-                );
-                // Channel closed, stop sending tasks
-                break;
-            }
-        }
+/// Splits the work of a processor reading through an aggregation pipeline into tasks.
+/// The input documents are partitioned like a plain query unless a stage of the
+/// pipeline needs to see all of them at once, in which case a single task is used.
+pub(crate) fn plan_pipeline_batches(
+    query: &Document,
+    total_documents: usize,
+    batch_size: u64,
+    ids: Option<&[Bson]>,
+    pipeline: &[Document],
+) -> Vec<TaskBatch> {
+    if is_partitionable(pipeline) {
+        return plan_batches(query, total_documents, batch_size, ids);
     }
 
-    fn collection_name(&self) -> &str {
-        &self.collection_name
+    vec![TaskBatch {
+        query: match ids {
+            // Its size was checked while counting the pipeline documents
+            Some(ids) => restrict_to_ids(query, ids),
+            None => query.clone(),
+        },
+        skip: None,
+        limit: None,
+    }]
+}
+
+async fn count_pipeline_documents(
+    dbs: &DatabasePair,
+    collection_name: &str,
+    pipeline: Vec<Document>,
+) -> TuxedoResult<usize> {
+    let mut cursor = dbs.aggregate(collection_name, pipeline, None).await?;
+
+    // `$count` produces no document at all when there is nothing to count
+    if !cursor.advance().await? {
+        return Ok(0);
     }
+    let document: Document = cursor.deserialize_current()?;
+    let total = match document.get("total") {
+        Some(Bson::Int32(total)) => *total as usize,
+        Some(Bson::Int64(total)) => *total as usize,
+        _ => 0,
+    };
+    Ok(total)
 }
 
 fn calculate_optimal_target_bytes(average_document_size: u64) -> u64 {
@@ -256,91 +952,96 @@ impl<T: Send + Sync + 'static> Processor for ReplicatorProcessor<T> {
         dbs: Arc<DatabasePair>,
         task_sender: mpsc::Sender<Box<dyn Task>>,
         default_config: ReplicationConfig,
-        progress_bar: ProgressBar,
+        stats: Arc<CollectionStats>,
+        ids: Option<Arc<Vec<Bson>>>,
     ) {
-        let mut batch_size = self.config.batch_size.unwrap_or(default_config.batch_size);
-        let write_batch_size = self
-            .config
-            .write_batch_size
-            .unwrap_or(default_config.write_batch_size);
-
-        let total_documents = match self
-            .get_total_documents(&dbs, self.config.query.clone())
+        self.run_batches(dbs, task_sender, default_config, stats, ids)
             .await
-        {
-            Ok(total_documents) => total_documents,
-            Err(_) => return,
-        };
+    }
 
-        let progress_bar = self.setup_progress_bar(progress_bar, total_documents, "Document");
+    async fn verify(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        strategy: &ReplicationStrategy,
+        stats: &CollectionStats,
+        compare_count: bool,
+    ) -> VerificationReport {
+        let unchanged = |document: Document| -> TuxedoResult<Document> { Ok(document) };
+        let expected = (matches!(strategy, ReplicationStrategy::Clone) && self.lambda.is_none())
+            .then_some(&unchanged as ExpectedDocument);
+        self.verify_copy(dbs, stats, compare_count, expected).await
+    }
 
-        if total_documents == 0 {
-            progress_bar.finish_and_clear();
-            return;
-        }
+    async fn detect_leaks(
+        &self,
+        dbs: &Arc<DatabasePair>,
+        stats: &CollectionStats,
+    ) -> Option<LeakReport> {
+        self.scan_leaks(dbs, &self.config.query, stats, &self.config.sensitive_paths)
+            .await
+    }
 
-        if self.config.adaptive_batching == Some(true) || default_config.adaptive_batching {
-            if let Ok(adaptive_batch_size) = self.setup_adaptive_batching(&dbs).await {
-                batch_size = adaptive_batch_size;
-            }
+    /// The masking lambda applies whatever the strategy, like in the tasks
+    fn mask_document(
+        &self,
+        document: &Document,
+        _strategy: &ReplicationStrategy,
+    ) -> Option<Document> {
+        if self.config.pipeline.is_some() || !matches_query(&self.config.query, document) {
+            return None;
         }
 
-        let batch_count = total_documents.div_ceil(batch_size as usize);
-        let write_options = default_config.write_options;
+        let mut document = document.clone();
+        if let Some(lambda) = self.lambda.as_ref() {
+            (lambda)(&mut document);
+        }
+        Some(document)
+    }
 
-        for batch_index in 0..batch_count {
-            let skip = batch_index * batch_size as usize;
-            let remaining_documents = total_documents.saturating_sub(skip);
-            let limit = batch_size.min(remaining_documents as u64) as i64;
+    fn plan(&self, _strategy: &ReplicationStrategy) -> CollectionPlan {
+        self.collection_plan(self.lambda.is_some())
+    }
 
-            // This should never happen in theory
-            if limit == 0 {
-                // No more documents to process
-                break;
-            }
+    fn validate(&self) -> TuxedoResult<()> {
+        check_batch_sizes(
+            &self.collection_name,
+            &[self.config.batch_size, self.config.write_batch_size],
+        )
+    }
 
-            let dbs = Arc::clone(&dbs);
-            let query = self.config.query.clone();
-            let progress_bar = Arc::clone(&progress_bar);
+    fn collection_name(&self) -> &str {
+        &self.collection_name
+    }
 
-            let mut read_options = default_config.read_options.clone();
-            // Ensure stable sort order for skip/limit pagination
-            // if read_options.sort.is_none() {
-            //     read_options.sort = Some(doc! { "_id": 1 });
-            // }
-            read_options.skip = (skip as u64).into();
-            read_options.limit = limit.into();
-            read_options.batch_size = Some(limit as u32);
+    fn target_collection_name(&self) -> &str {
+        &self.target_collection_name
+    }
 
-            let task = Box::new(ReplicatorTask::<T>::new(
-                dbs,
-                self.collection_name.clone(),
-                TaskConfig {
-                    query,
-                    write_batch_size,
-                    read_options,
-                    write_options: write_options.clone(),
-                },
-                // QueryConfig::new(query, skip, limit, batch_size),
-                self.config.lambda.clone(),
-                progress_bar,
-            ));
+    fn database_name(&self) -> &str {
+        self.config.database.as_deref().unwrap_or(DEFAULT_DATABASE)
+    }
+}
 
-            if task_sender.send(task).await.is_err() {
-                println!(
-                    "Failed to send task to worker pool for collection '{}' (batch {}/{}). Channel closed, stopping processor.",
-                    &self.collection_name,
-                    batch_index + 1,
-                    batch_count
-                );
-                // Channel closed, stop sending tasks
-                break;
-            }
-        }
+impl<T: Send + Sync + 'static> DocumentProcessor for ReplicatorProcessor<T> {
+    fn config(&self) -> &ProcessorConfig {
+        &self.config
     }
 
-    fn collection_name(&self) -> &str {
-        &self.collection_name
+    fn new_task(
+        &self,
+        dbs: Arc<DatabasePair>,
+        config: TaskConfig,
+        _strategy: &ReplicationStrategy,
+        stats: Arc<CollectionStats>,
+    ) -> Box<dyn Task> {
+        Box::new(ReplicatorTask::<T>::new(
+            dbs,
+            self.collection_name.clone(),
+            self.target_collection_name.clone(),
+            config,
+            self.lambda.clone(),
+            stats,
+        ))
     }
 }
 
@@ -350,6 +1051,12 @@ pub struct ProcessorConfig {
     batch_size: Option<u64>,
     write_batch_size: Option<u64>,
     query: Document,
+    pipeline: Option<Vec<Document>>,
+    target_collection: Option<String>,
+    database: Option<String>,
+    sample: Option<Sample>,
+    sample_seed: Option<u64>,
+    sensitive_paths: Vec<String>,
 }
 
 #[derive(Debug, Default)]
@@ -383,11 +1090,53 @@ impl ProcessorConfigBuilder {
         self
     }
 
+    /// Reads the documents matching the query through an aggregation pipeline instead
+    /// of a plain `find`, e.g. to `$project`, `$unwind` or enrich them with `$lookup`.
+    /// Stages needing every document at once (`$group`, `$sort`, ...) run as a single task.
+    pub fn pipeline(mut self, pipeline: impl IntoIterator<Item = Document>) -> Self {
+        self.config.pipeline = Some(pipeline.into_iter().collect());
+        self
+    }
+
     pub fn adaptive_batching(mut self, enabled: bool) -> Self {
         self.config.adaptive_batching = Some(enabled);
         self
     }
 
+    /// Writes the processed documents into a differently named collection on the target.
+    /// Several processors may share a target collection to merge polymorphic sources.
+    pub fn target_collection(mut self, collection_name: impl Into<String>) -> Self {
+        self.config.target_collection = Some(collection_name.into());
+        self
+    }
+
+    /// Binds the processor to a database mapping registered with
+    /// `ReplicationManagerBuilder::add_database`
+    pub fn database(mut self, name: impl Into<String>) -> Self {
+        self.config.database = Some(name.into());
+        self
+    }
+
+    /// Copies a random sample of the documents matching the query instead of all of them
+    pub fn sample(mut self, sample: Sample) -> Self {
+        self.config.sample = Some(sample);
+        self
+    }
+
+    /// Seeds the sample so repeated runs select the same documents
+    pub fn sample_seed(mut self, seed: u64) -> Self {
+        self.config.sample_seed = Some(seed);
+        self
+    }
+
+    /// Dotted paths holding sensitive values the masking must replace, checked for
+    /// source values on the target when `ReplicationManagerBuilder::detect_leaks` is
+    /// enabled
+    pub fn sensitive_paths(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config.sensitive_paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
     pub fn build(self) -> ProcessorConfig {
         self.config
     }
@@ -395,30 +1144,11 @@ impl ProcessorConfigBuilder {
 
 #[derive(Default)]
 pub struct ReplicatorConfig {
-    adaptive_batching: Option<bool>,
-    batch_size: Option<u64>,
-    write_batch_size: Option<u64>,
-    query: Document,
-    lambda: Option<Arc<dyn Fn(&mut Document) + Send + Sync>>,
+    config: ProcessorConfig,
+    lambda: Option<MaskingLambda>,
 }
 
 impl ReplicatorConfig {
-    fn new(
-        batch_size: Option<u64>,
-        write_batch_size: Option<u64>,
-        query: Document,
-        adaptive_batching: Option<bool>,
-        lambda: Option<Arc<dyn Fn(&mut Document) + Send + Sync>>,
-    ) -> Self {
-        Self {
-            batch_size,
-            write_batch_size,
-            query,
-            adaptive_batching,
-            lambda,
-        }
-    }
-
     pub fn builder() -> ReplicationConfigBuilder {
         ReplicationConfigBuilder::new()
     }
@@ -429,8 +1159,15 @@ pub struct ReplicationConfigBuilder {
     batch_size: Option<u64>,
     write_batch_size: Option<u64>,
     query: Document,
+    pipeline: Option<Vec<Document>>,
     adaptive_batching: Option<bool>,
-    lambda: Option<Arc<dyn Fn(&mut Document) + Send + Sync>>,
+    target_collection: Option<String>,
+    database: Option<String>,
+    sample: Option<Sample>,
+    sample_seed: Option<u64>,
+    sensitive_paths: Vec<String>,
+    rules: MaskRules,
+    lambda: Option<MaskingLambda>,
 }
 
 impl ReplicationConfigBuilder {
@@ -453,11 +1190,58 @@ impl ReplicationConfigBuilder {
         self
     }
 
+    /// Reads the documents matching the query through an aggregation pipeline instead
+    /// of a plain `find`. Stages needing every document at once run as a single task.
+    pub fn pipeline(mut self, pipeline: impl IntoIterator<Item = Document>) -> Self {
+        self.pipeline = Some(pipeline.into_iter().collect());
+        self
+    }
+
     pub fn adaptive_batching(mut self, enabled: impl Into<bool>) -> Self {
         self.adaptive_batching = Some(enabled.into());
         self
     }
 
+    /// Writes the replicated documents into a differently named collection on the target.
+    pub fn target_collection(mut self, collection_name: impl Into<String>) -> Self {
+        self.target_collection = Some(collection_name.into());
+        self
+    }
+
+    /// Binds the replicator to a database mapping registered with
+    /// `ReplicationManagerBuilder::add_database`
+    pub fn database(mut self, name: impl Into<String>) -> Self {
+        self.database = Some(name.into());
+        self
+    }
+
+    /// Replicates a random sample of the documents matching the query instead of all of them
+    pub fn sample(mut self, sample: Sample) -> Self {
+        self.sample = Some(sample);
+        self
+    }
+
+    /// Seeds the sample so repeated runs select the same documents
+    pub fn sample_seed(mut self, seed: u64) -> Self {
+        self.sample_seed = Some(seed);
+        self
+    }
+
+    /// Dotted paths holding sensitive values the mask lambda must replace, checked for
+    /// source values on the target when `ReplicationManagerBuilder::detect_leaks` is
+    /// enabled
+    pub fn sensitive_paths(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.sensitive_paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Masks the documents with path based rules, applied before the mask lambda. The
+    /// rule paths are sensitive paths too.
+    pub fn rules(mut self, rules: MaskRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
     pub fn mask<F>(mut self, lambda: F) -> Self
     where
         F: Fn(&mut Document) + Send + Sync + 'static,
@@ -467,12 +1251,36 @@ impl ReplicationConfigBuilder {
     }
 
     pub fn build(self) -> ReplicatorConfig {
-        ReplicatorConfig::new(
-            self.batch_size,
-            self.write_batch_size,
-            self.query,
-            self.adaptive_batching,
-            self.lambda,
-        )
+        let mut sensitive_paths = self.sensitive_paths;
+        for path in self.rules.paths() {
+            if !sensitive_paths.iter().any(|sensitive| sensitive == path) {
+                sensitive_paths.push(path.to_string());
+            }
+        }
+        let lambda: Option<MaskingLambda> = match (self.rules, self.lambda) {
+            (rules, lambda) if rules.is_empty() => lambda,
+            (rules, lambda) => Some(Arc::new(move |document: &mut Document| {
+                rules.apply(document);
+                if let Some(lambda) = lambda.as_ref() {
+                    (lambda)(document);
+                }
+            })),
+        };
+
+        ReplicatorConfig {
+            config: ProcessorConfig {
+                batch_size: self.batch_size,
+                write_batch_size: self.write_batch_size,
+                query: self.query,
+                pipeline: self.pipeline,
+                adaptive_batching: self.adaptive_batching,
+                target_collection: self.target_collection,
+                database: self.database,
+                sample: self.sample,
+                sample_seed: self.sample_seed,
+                sensitive_paths,
+            },
+            lambda,
+        }
     }
 }
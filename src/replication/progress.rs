@@ -0,0 +1,330 @@
+use super::report::{ErrorStage, ViewOutcome};
+use super::types::DEFAULT_DATABASE;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Progress of a `ReplicationManager::run`, reported to a `ProgressListener` as it
+/// happens. Collection events carry the database mapping and the source and target
+/// collection names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// A processor counted the documents it is about to write
+    CollectionStarted {
+        database: String,
+        collection: String,
+        target_collection: String,
+        total: u64,
+    },
+    /// A batch of documents was written into the target collection
+    BatchWritten {
+        database: String,
+        collection: String,
+        target_collection: String,
+        documents: u64,
+    },
+    /// An index was recreated on the target collection
+    IndexCopied {
+        database: String,
+        collection: String,
+        target_collection: String,
+        index: String,
+    },
+    /// A source view was created, skipped or failed on the target
+    ViewCopied {
+        database: String,
+        view: String,
+        outcome: ViewOutcome,
+    },
+    /// Something failed for a collection, the run goes on
+    Error {
+        database: String,
+        collection: String,
+        target_collection: String,
+        stage: ErrorStage,
+        message: String,
+    },
+    /// The run completed, see the returned `RunReport` for the details
+    Finished {
+        documents_written: u64,
+        elapsed: Duration,
+        success: bool,
+    },
+}
+
+/// Receives the progress events of a run, see `ReplicationManagerBuilder::progress`.
+///
+/// Events are sent from the tasks as they write, so listeners should return quickly.
+/// Closures and unbounded tokio channel senders are listeners.
+pub trait ProgressListener: Send + Sync {
+    fn on_event(&self, event: &ProgressEvent);
+}
+
+impl<F> ProgressListener for F
+where
+    F: Fn(&ProgressEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &ProgressEvent) {
+        self(event)
+    }
+}
+
+/// Events are dropped once the receiver is closed
+impl ProgressListener for mpsc::UnboundedSender<ProgressEvent> {
+    fn on_event(&self, event: &ProgressEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// Listener used when none is configured: progress bars on a terminal when the
+/// `progress-bars` feature is enabled, plain lines otherwise
+pub(crate) fn default_listener() -> Arc<dyn ProgressListener> {
+    #[cfg(feature = "progress-bars")]
+    if std::io::IsTerminal::is_terminal(&std::io::stderr()) {
+        return Arc::new(ProgressBars::new());
+    }
+    Arc::new(PlainProgress::new())
+}
+
+/// Handle on the run's listener held by the collection stats
+#[derive(Clone)]
+pub(crate) struct Progress(Arc<dyn ProgressListener>);
+
+impl Progress {
+    pub(crate) fn new(listener: Arc<dyn ProgressListener>) -> Self {
+        Self(listener)
+    }
+
+    pub(crate) fn emit(&self, event: ProgressEvent) {
+        self.0.on_event(&event);
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self(Arc::new(|_: &ProgressEvent| {}))
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Name a collection is displayed with, prefixed by its database mapping unless it
+/// is the default one
+fn label(database: &str, collection: &str) -> String {
+    if database == DEFAULT_DATABASE {
+        collection.to_string()
+    } else {
+        format!("{database}.{collection}")
+    }
+}
+
+type CollectionKey = (String, String, String);
+
+fn key(database: &str, collection: &str, target_collection: &str) -> CollectionKey {
+    (
+        database.to_string(),
+        collection.to_string(),
+        target_collection.to_string(),
+    )
+}
+
+/// Writes progress to stderr as plain lines, for CI logs and other non-TTY output.
+/// Collections are reported every 10% of their documents rather than every batch.
+#[derive(Debug, Default)]
+pub struct PlainProgress {
+    /// Documents written and total of every started collection
+    collections: Mutex<HashMap<CollectionKey, (u64, u64)>>,
+}
+
+impl PlainProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn line(&self, line: fmt::Arguments<'_>) {
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+}
+
+impl ProgressListener for PlainProgress {
+    fn on_event(&self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::CollectionStarted {
+                database,
+                collection,
+                target_collection,
+                total,
+            } => {
+                self.collections
+                    .lock()
+                    .expect("Plain progress poisoned")
+                    .entry(key(database, collection, target_collection))
+                    .or_default()
+                    .1 += total;
+                self.line(format_args!(
+                    "{}: {total} documents to write",
+                    label(database, collection)
+                ));
+            }
+            ProgressEvent::BatchWritten {
+                database,
+                collection,
+                target_collection,
+                documents,
+            } => {
+                let (written, total) = {
+                    let mut collections = self.collections.lock().expect("Plain progress poisoned");
+                    let entry = collections
+                        .entry(key(database, collection, target_collection))
+                        .or_default();
+                    let previous_step = percent(entry.0, entry.1) / 10;
+                    entry.0 += documents;
+                    if percent(entry.0, entry.1) / 10 == previous_step {
+                        return;
+                    }
+                    *entry
+                };
+                self.line(format_args!(
+                    "{}: {written}/{total} documents ({}%)",
+                    label(database, collection),
+                    percent(written, total)
+                ));
+            }
+            ProgressEvent::IndexCopied {
+                database,
+                collection,
+                index,
+                ..
+            } => self.line(format_args!(
+                "{}: index `{index}` copied",
+                label(database, collection)
+            )),
+            ProgressEvent::ViewCopied {
+                database,
+                view,
+                outcome,
+            } => {
+                let view = label(database, view);
+                match outcome {
+                    ViewOutcome::Created => self.line(format_args!("{view}: view created")),
+                    ViewOutcome::Skipped(reason) => {
+                        self.line(format_args!("{view}: view skipped, {reason}"))
+                    }
+                    ViewOutcome::Failed(error) => {
+                        self.line(format_args!("{view}: view failed, {error}"))
+                    }
+                }
+            }
+            ProgressEvent::Error {
+                database,
+                collection,
+                stage,
+                message,
+                ..
+            } => self.line(format_args!(
+                "{}: {stage} error, {message}",
+                label(database, collection)
+            )),
+            ProgressEvent::Finished {
+                documents_written,
+                elapsed,
+                success,
+            } => self.line(format_args!(
+                "Finished in {:.1}s, {documents_written} documents written{}",
+                elapsed.as_secs_f64(),
+                if *success { "" } else { ", with errors" }
+            )),
+        }
+    }
+}
+
+fn percent(written: u64, total: u64) -> u64 {
+    (written * 100).checked_div(total).unwrap_or(100).min(100)
+}
+
+/// Renders a progress bar per collection on stderr
+#[cfg(feature = "progress-bars")]
+pub struct ProgressBars {
+    multi_progress: indicatif::MultiProgress,
+    style: indicatif::ProgressStyle,
+    bars: Mutex<HashMap<CollectionKey, indicatif::ProgressBar>>,
+}
+
+#[cfg(feature = "progress-bars")]
+impl ProgressBars {
+    pub fn new() -> Self {
+        let style = indicatif::ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+        )
+        .expect("Expected to set progress bar styling")
+        .progress_chars("█▓▒░");
+
+        Self {
+            multi_progress: indicatif::MultiProgress::new(),
+            style,
+            bars: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[cfg(feature = "progress-bars")]
+impl Default for ProgressBars {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "progress-bars")]
+impl ProgressListener for ProgressBars {
+    fn on_event(&self, event: &ProgressEvent) {
+        let mut bars = self.bars.lock().expect("Progress bars poisoned");
+        match event {
+            ProgressEvent::CollectionStarted {
+                database,
+                collection,
+                target_collection,
+                total,
+            } => {
+                let bar = bars
+                    .entry(key(database, collection, target_collection))
+                    .or_insert_with(|| {
+                        let bar = self
+                            .multi_progress
+                            .add(indicatif::ProgressBar::new(0).with_style(self.style.clone()));
+                        bar.set_message(label(database, collection));
+                        bar
+                    });
+                bar.inc_length(*total);
+                if bar.position() >= bar.length().unwrap_or_default() {
+                    bar.finish_and_clear();
+                }
+            }
+            ProgressEvent::BatchWritten {
+                database,
+                collection,
+                target_collection,
+                documents,
+            } => {
+                if let Some(bar) = bars.get(&key(database, collection, target_collection)) {
+                    bar.inc(*documents);
+                    if bar.position() >= bar.length().unwrap_or_default() {
+                        bar.finish_and_clear();
+                    }
+                }
+            }
+            ProgressEvent::Finished { .. } => {
+                for (_, bar) in bars.drain() {
+                    bar.finish_and_clear();
+                }
+            }
+            _ => {}
+        }
+    }
+}
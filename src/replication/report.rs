@@ -0,0 +1,457 @@
+#[cfg(feature = "metrics")]
+use super::metrics::CollectionMetrics;
+use super::progress::{Progress, ProgressEvent};
+use bson::Bson;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Summary of a single `ReplicationManager::run`, covering every database mapping
+/// and processor in the manager.
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    pub collections: Vec<CollectionReport>,
+    pub views: Vec<ViewReport>,
+    /// One per processor when verification is enabled
+    pub verifications: Vec<VerificationReport>,
+    /// One per processor with sensitive paths when leak detection is enabled
+    pub leaks: Vec<LeakReport>,
+    pub elapsed: Duration,
+}
+
+impl RunReport {
+    /// Total number of documents written across all collections
+    pub fn documents_written(&self) -> u64 {
+        self.collections.iter().map(|c| c.documents_written).sum()
+    }
+
+    /// Returns true when every collection was fully written without errors, no
+    /// view failed to be created and verification found no discrepancy or leak. Views skipped
+    /// because of missing dependencies do not count as failures.
+    pub fn is_success(&self) -> bool {
+        self.collections.iter().all(CollectionReport::is_success)
+            && !self
+                .views
+                .iter()
+                .any(|view| matches!(view.outcome, ViewOutcome::Failed(_)))
+            && self
+                .verifications
+                .iter()
+                .all(VerificationReport::is_success)
+            && self.leaks.iter().all(LeakReport::is_success)
+    }
+}
+
+/// Comparison of a processor's source documents with its target collection once the
+/// run completed
+#[derive(Debug, Clone, Default)]
+pub struct VerificationReport {
+    /// Name of the database mapping the processor was bound to
+    pub database: String,
+    pub source_collection: String,
+    pub target_collection: String,
+    /// Source documents matching the processor's query
+    pub source_count: u64,
+    /// Documents of the target collection, `None` when other processors write into it
+    /// as well and the counts can't be compared
+    pub target_count: Option<u64>,
+    /// Source documents without a target document of the same `_id`
+    pub missing: u64,
+    /// Up to `MAX_REPORTED_IDS` `_id`s of missing documents
+    pub missing_ids: Vec<Bson>,
+    /// Documents whose content differs from the source, only compared with the clone
+    /// strategy
+    pub mismatched: u64,
+    /// Up to `MAX_REPORTED_IDS` `_id`s of mismatched documents
+    pub mismatched_ids: Vec<Bson>,
+    /// Why the collection was not verified, e.g. a target which can't be read back
+    pub skipped: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Number of discrepant `_id`s kept in a `VerificationReport` or `LeakReport`
+pub const MAX_REPORTED_IDS: usize = 100;
+
+impl VerificationReport {
+    pub(crate) fn new(
+        database: impl Into<String>,
+        source_collection: impl Into<String>,
+        target_collection: impl Into<String>,
+    ) -> Self {
+        Self {
+            database: database.into(),
+            source_collection: source_collection.into(),
+            target_collection: target_collection.into(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn skipped(mut self, reason: impl Into<String>) -> Self {
+        self.skipped = Some(reason.into());
+        self
+    }
+
+    pub(crate) fn add_missing(&mut self, id: Bson) {
+        self.missing += 1;
+        if self.missing_ids.len() < MAX_REPORTED_IDS {
+            self.missing_ids.push(id);
+        }
+    }
+
+    pub(crate) fn add_mismatched(&mut self, id: Bson) {
+        self.mismatched += 1;
+        if self.mismatched_ids.len() < MAX_REPORTED_IDS {
+            self.mismatched_ids.push(id);
+        }
+    }
+
+    /// Returns true when verification ran and every source document was found on the
+    /// target unchanged. Skipped collections count as successful.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+            && self.missing == 0
+            && self.mismatched == 0
+            && self
+                .target_count
+                .is_none_or(|target_count| target_count == self.source_count)
+    }
+}
+
+/// Source values found on the target at the sensitive paths of a processor, i.e.
+/// values its masking did not replace
+#[derive(Debug, Clone, Default)]
+pub struct LeakReport {
+    /// Name of the database mapping the processor was bound to
+    pub database: String,
+    pub source_collection: String,
+    pub target_collection: String,
+    /// Paths whose values were compared
+    pub paths: Vec<String>,
+    /// Values read from the source at those paths
+    pub source_values: u64,
+    /// Documents of the target collection scanned
+    pub documents_scanned: u64,
+    /// Values of the target equal to a source value at the same path
+    pub leaks: u64,
+    /// Up to `MAX_REPORTED_IDS` leaked values, without the value itself
+    pub leaked: Vec<Leak>,
+    /// Why the target was not scanned, e.g. a target which can't be read back
+    pub skipped: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A target document holding a source value at a sensitive path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leak {
+    pub path: String,
+    pub id: Bson,
+}
+
+impl LeakReport {
+    pub(crate) fn new(
+        database: impl Into<String>,
+        source_collection: impl Into<String>,
+        target_collection: impl Into<String>,
+        paths: Vec<String>,
+    ) -> Self {
+        Self {
+            database: database.into(),
+            source_collection: source_collection.into(),
+            target_collection: target_collection.into(),
+            paths,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn skipped(mut self, reason: impl Into<String>) -> Self {
+        self.skipped = Some(reason.into());
+        self
+    }
+
+    /// Counts `count` leaks of a value, keeping the first of `leaked`
+    pub(crate) fn add_leaks(&mut self, count: u64, leaked: impl IntoIterator<Item = Leak>) {
+        self.leaks += count;
+        let available = MAX_REPORTED_IDS.saturating_sub(self.leaked.len());
+        self.leaked.extend(leaked.into_iter().take(available));
+    }
+
+    /// Returns true when the scan found no source value on the target. Skipped
+    /// collections count as successful.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.leaks == 0
+    }
+}
+
+/// Outcome of recreating a source view on the target
+#[derive(Debug, Clone)]
+pub struct ViewReport {
+    /// Name of the database mapping the view belongs to
+    pub database: String,
+    pub name: String,
+    pub outcome: ViewOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViewOutcome {
+    Created,
+    /// The view was not created, e.g. because a dependency is missing on the target
+    Skipped(String),
+    Failed(String),
+}
+
+/// Outcome of replicating one source collection into its target collection
+#[derive(Debug, Clone, Default)]
+pub struct CollectionReport {
+    /// Name of the database mapping the processor was bound to
+    pub database: String,
+    pub source_collection: String,
+    pub target_collection: String,
+    pub documents_total: u64,
+    pub documents_written: u64,
+    pub documents_failed: u64,
+    pub indexes_copied: u64,
+    pub index_failures: Vec<IndexFailure>,
+    pub errors: Vec<String>,
+}
+
+/// An index which could not be recreated on the target collection
+#[derive(Debug, Clone)]
+pub struct IndexFailure {
+    pub name: String,
+    pub error: String,
+}
+
+impl CollectionReport {
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+            && self.index_failures.is_empty()
+            && self.documents_failed == 0
+            && self.documents_written == self.documents_total
+    }
+}
+
+/// Phase of the run an error occurred in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorStage {
+    /// Creating the target collection or applying its validator
+    Schema,
+    /// Counting the source documents
+    Count,
+    /// Selecting a sample of the source documents
+    Sample,
+    /// Validating or partitioning an aggregation pipeline
+    Pipeline,
+    /// Reading from the source
+    Read,
+    /// Writing into the target
+    Write,
+    /// Recreating indexes on the target
+    Index,
+}
+
+impl ErrorStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorStage::Schema => "schema",
+            ErrorStage::Count => "count",
+            ErrorStage::Sample => "sample",
+            ErrorStage::Pipeline => "pipeline",
+            ErrorStage::Read => "read",
+            ErrorStage::Write => "write",
+            ErrorStage::Index => "index",
+        }
+    }
+}
+
+impl fmt::Display for ErrorStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Counters shared between a processor and the tasks it spawns, collected into a
+/// `CollectionReport` once the run has completed. Changes are reported to the run's
+/// progress listener as they happen.
+#[derive(Debug, Default)]
+pub(crate) struct CollectionStats {
+    database: String,
+    source_collection: String,
+    target_collection: String,
+    documents_total: AtomicU64,
+    documents_written: AtomicU64,
+    documents_failed: AtomicU64,
+    indexes_copied: AtomicU64,
+    index_failures: Mutex<Vec<IndexFailure>>,
+    errors: Mutex<Vec<String>>,
+    /// `_id`s the processor was restricted to by a subset or sample, kept to verify
+    /// the run
+    ids: Mutex<Option<Arc<Vec<Bson>>>>,
+    progress: Progress,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<CollectionMetrics>>,
+}
+
+impl CollectionStats {
+    pub(crate) fn new(
+        database: impl Into<String>,
+        source_collection: impl Into<String>,
+        target_collection: impl Into<String>,
+        progress: Progress,
+    ) -> Self {
+        Self {
+            database: database.into(),
+            source_collection: source_collection.into(),
+            target_collection: target_collection.into(),
+            progress,
+            ..Default::default()
+        }
+    }
+
+    /// Feeds the counters of the collection into a metrics registry as well
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics(mut self, metrics: Arc<CollectionMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub(crate) fn set_ids(&self, ids: Option<Arc<Vec<Bson>>>) {
+        *self.ids.lock().expect("Collection stats ids lock poisoned") = ids;
+    }
+
+    pub(crate) fn ids(&self) -> Option<Arc<Vec<Bson>>> {
+        self.ids
+            .lock()
+            .expect("Collection stats ids lock poisoned")
+            .clone()
+    }
+
+    pub(crate) fn set_total(&self, total: u64) {
+        self.documents_total.store(total, Ordering::Relaxed);
+        self.progress.emit(ProgressEvent::CollectionStarted {
+            database: self.database.clone(),
+            collection: self.source_collection.clone(),
+            target_collection: self.target_collection.clone(),
+            total,
+        });
+    }
+
+    /// A document of `size` bytes was read from the source
+    pub(crate) fn add_read(&self, _size: usize) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.add_read(_size as u64);
+        }
+    }
+
+    pub(crate) fn add_masked(&self, _count: u64) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.add_masked(_count);
+        }
+    }
+
+    /// A batch of `size` bytes was written into the target in `_elapsed`
+    pub(crate) fn add_written_batch(&self, count: u64, _size: u64, _elapsed: Duration) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.add_written_batch(_size, _elapsed);
+        }
+        self.add_written(count);
+    }
+
+    pub(crate) fn add_written(&self, count: u64) {
+        self.documents_written.fetch_add(count, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.add_written(count);
+        }
+        self.progress.emit(ProgressEvent::BatchWritten {
+            database: self.database.clone(),
+            collection: self.source_collection.clone(),
+            target_collection: self.target_collection.clone(),
+            documents: count,
+        });
+    }
+
+    pub(crate) fn add_failed(&self, count: u64) {
+        self.documents_failed.fetch_add(count, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.add_failed(count);
+        }
+    }
+
+    pub(crate) fn add_indexes_copied(&self, names: &[String]) {
+        self.indexes_copied
+            .fetch_add(names.len() as u64, Ordering::Relaxed);
+        for name in names.iter() {
+            self.progress.emit(ProgressEvent::IndexCopied {
+                database: self.database.clone(),
+                collection: self.source_collection.clone(),
+                target_collection: self.target_collection.clone(),
+                index: name.clone(),
+            });
+        }
+    }
+
+    pub(crate) fn add_index_failure(&self, name: impl Into<String>, error: impl Into<String>) {
+        let failure = IndexFailure {
+            name: name.into(),
+            error: error.into(),
+        };
+        self.emit_error(
+            ErrorStage::Index,
+            format!("Failed to copy index `{}`: {}", failure.name, failure.error),
+        );
+        self.index_failures
+            .lock()
+            .expect("Collection stats index lock poisoned")
+            .push(failure);
+    }
+
+    pub(crate) fn add_error(&self, stage: ErrorStage, error: impl Into<String>) {
+        let error = error.into();
+        self.emit_error(stage, error.clone());
+        self.errors
+            .lock()
+            .expect("Collection stats error lock poisoned")
+            .push(error);
+    }
+
+    fn emit_error(&self, stage: ErrorStage, message: String) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.add_error(stage);
+        }
+        self.progress.emit(ProgressEvent::Error {
+            database: self.database.clone(),
+            collection: self.source_collection.clone(),
+            target_collection: self.target_collection.clone(),
+            stage,
+            message,
+        });
+    }
+
+    pub(crate) fn report(&self) -> CollectionReport {
+        CollectionReport {
+            database: self.database.clone(),
+            source_collection: self.source_collection.clone(),
+            target_collection: self.target_collection.clone(),
+            documents_total: self.documents_total.load(Ordering::Relaxed),
+            documents_written: self.documents_written.load(Ordering::Relaxed),
+            documents_failed: self.documents_failed.load(Ordering::Relaxed),
+            indexes_copied: self.indexes_copied.load(Ordering::Relaxed),
+            index_failures: self
+                .index_failures
+                .lock()
+                .expect("Collection stats index lock poisoned")
+                .clone(),
+            errors: self
+                .errors
+                .lock()
+                .expect("Collection stats error lock poisoned")
+                .clone(),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::types::DatabasePair;
+use crate::paths::bson_key;
+use crate::{TuxedoError, TuxedoResult};
+use bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use std::collections::{BinaryHeap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of a random sample taken from a collection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// At most this many documents
+    Count(u64),
+    /// This percentage (0-100) of the documents matching the query
+    Percent(f64),
+}
+
+/// Selects the `_id`s of a random sample of the documents matching `query`.
+///
+/// Without a seed the server side `$sample` stage is used, its duplicates are dropped
+/// so the sample may hold fewer documents than asked for. With a seed every `_id` is
+/// ranked by a seeded hash and the lowest ranked ones are kept, so repeated runs
+/// select the same subset as long as the documents don't change. When the processor
+/// is already restricted to a set of `ids` (subsetting) the sample is taken from those.
+pub(crate) async fn select_sample_ids(
+    dbs: &DatabasePair,
+    collection_name: &str,
+    query: &Document,
+    ids: Option<&[Bson]>,
+    sample: Sample,
+    seed: Option<u64>,
+) -> TuxedoResult<Vec<Bson>> {
+    if let Some(ids) = ids {
+        let sample_size = sample_size(sample, ids.len() as u64)?;
+        let seed = seed.unwrap_or_else(random_seed);
+        return Ok(lowest_ranked(ids.iter().cloned(), sample_size, seed));
+    }
+
+    let total_documents = match sample {
+        Sample::Count(_) => 0,
+        Sample::Percent(_) => {
+            dbs.read_total_documents(collection_name, query.clone())
+                .await? as u64
+        }
+    };
+    let sample_size = sample_size(sample, total_documents)?;
+
+    match seed {
+        Some(seed) => {
+            let read_options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+            let mut cursor = dbs
+                .read_documents(collection_name, query.clone(), Some(read_options))
+                .await?;
+
+            let mut ids = Vec::new();
+            while cursor.advance().await? {
+                let document: Document = cursor.deserialize_current()?;
+                if let Some(id) = document.get("_id") {
+                    ids.push(id.clone());
+                }
+            }
+
+            Ok(lowest_ranked(ids.into_iter(), sample_size, seed))
+        }
+        None => {
+            let pipeline = vec![
+                doc! { "$match": query.clone() },
+                doc! { "$sample": { "size": sample_size as i64 } },
+                doc! { "$project": { "_id": 1 } },
+            ];
+            let mut cursor = dbs.aggregate_documents(collection_name, pipeline).await?;
+
+            // `$sample` may return a document more than once, the sample is then smaller
+            let mut seen = HashSet::new();
+            let mut ids = Vec::new();
+            while cursor.advance().await? {
+                let document: Document = cursor.deserialize_current()?;
+                if let Some(id) = document.get("_id") {
+                    if seen.insert(bson_key(id)) {
+                        ids.push(id.clone());
+                    }
+                }
+            }
+            Ok(ids)
+        }
+    }
+}
+
+fn sample_size(sample: Sample, total_documents: u64) -> TuxedoResult<u64> {
+    match sample {
+        Sample::Count(count) => Ok(count),
+        Sample::Percent(percent) if (0.0..=100.0).contains(&percent) => {
+            Ok(((total_documents as f64) * percent / 100.0).ceil() as u64)
+        }
+        Sample::Percent(percent) => Err(TuxedoError::ConfigError(format!(
+            "Sample percentage must be between 0 and 100, got {percent}"
+        ))),
+    }
+}
+
+/// Keeps the `count` ids with the lowest seeded hash, independent of input order
+fn lowest_ranked(ids: impl Iterator<Item = Bson>, count: u64, seed: u64) -> Vec<Bson> {
+    let count = count as usize;
+    if count == 0 {
+        return Vec::new();
+    }
+
+    // Max-heap on the rank so the highest ranked id is evicted first
+    let mut heap: BinaryHeap<(u64, Vec<u8>)> = BinaryHeap::with_capacity(count + 1);
+    for id in ids {
+        let key = bson_key(&id);
+        let rank = seeded_hash(seed, &key);
+        if heap.len() < count {
+            heap.push((rank, key));
+        } else if heap.peek().is_some_and(|(highest, _)| rank < *highest) {
+            heap.pop();
+            heap.push((rank, key));
+        }
+    }
+
+    let mut ranked = heap.into_vec();
+    ranked.sort();
+    ranked
+        .into_iter()
+        .filter_map(|(_, key)| {
+            bson::from_slice::<Document>(&key)
+                .ok()
+                .and_then(|mut document| document.remove("v"))
+        })
+        .collect()
+}
+
+/// FNV-1a over the seed and the value, finished with the splitmix64 mixer. Stable
+/// across platforms and Rust versions, unlike `DefaultHasher`.
+pub(crate) fn seeded_hash(seed: u64, bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in seed.to_le_bytes().iter().chain(bytes.iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+pub(crate) fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
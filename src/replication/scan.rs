@@ -0,0 +1,275 @@
+use super::infer::{FieldSchema, SchemaInference};
+use super::processor::Processor;
+use super::types::{DatabasePair, ReplicationStrategy};
+use crate::paths::values_at_path;
+use crate::pii::{redact, PiiDetector, PiiKind};
+use crate::TuxedoResult;
+use bson::{doc, Bson, Document};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Documents sampled per collection unless configured otherwise
+const DEFAULT_SAMPLE_SIZE: u64 = 1_000;
+
+/// Redacted examples kept per finding unless configured otherwise
+const DEFAULT_EXAMPLES: usize = 3;
+
+/// Configures `ReplicationManager::scan`
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    sample_size: u64,
+    examples: usize,
+    names: Vec<String>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            sample_size: DEFAULT_SAMPLE_SIZE,
+            examples: DEFAULT_EXAMPLES,
+            names: Vec::new(),
+        }
+    }
+}
+
+impl ScanConfig {
+    pub fn builder() -> ScanConfigBuilder {
+        ScanConfigBuilder::new()
+    }
+
+    pub(crate) fn detector(&self) -> PiiDetector {
+        PiiDetector::new(&self.names)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScanConfigBuilder {
+    config: ScanConfig,
+}
+
+impl ScanConfigBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of documents randomly sampled from every collection, 1000 by default
+    pub fn sample_size(mut self, size: u64) -> Self {
+        self.config.sample_size = size;
+        self
+    }
+
+    /// Number of redacted example values kept per finding, 3 by default
+    pub fn examples(mut self, count: usize) -> Self {
+        self.config.examples = count;
+        self
+    }
+
+    /// Adds names to the built in dictionary of first and last names, e.g. the most
+    /// common names of a locale
+    pub fn names(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config.names.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn build(self) -> ScanConfig {
+        self.config
+    }
+}
+
+/// Personal information found in a sample of every source collection, see
+/// `ReplicationManager::scan`
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    pub collections: Vec<CollectionScan>,
+    pub elapsed: Duration,
+}
+
+impl ScanReport {
+    /// Findings whose values are copied unmasked or belong to a collection without
+    /// processor
+    pub fn uncovered(&self) -> impl Iterator<Item = (&CollectionScan, &PiiFinding)> {
+        self.collections.iter().flat_map(|collection| {
+            collection
+                .findings
+                .iter()
+                .filter(|finding| !finding.covered)
+                .map(move |finding| (collection, finding))
+        })
+    }
+}
+
+/// Findings of a single source collection
+#[derive(Debug, Clone, Default)]
+pub struct CollectionScan {
+    /// Name of the database mapping the collection belongs to
+    pub database: String,
+    pub collection: String,
+    /// Whether a processor copies the collection
+    pub processed: bool,
+    pub documents_sampled: u64,
+    /// Ordered by path, then kind
+    pub findings: Vec<PiiFinding>,
+    /// Fields of the sampled documents ordered by path
+    pub fields: Vec<FieldSchema>,
+    pub error: Option<String>,
+}
+
+/// Values of a path detected as a kind of personal information
+#[derive(Debug, Clone)]
+pub struct PiiFinding {
+    /// Dotted path of the values, array elements share the path of their array
+    pub path: String,
+    pub kind: PiiKind,
+    /// String values sampled at the path
+    pub values: u64,
+    /// Values detected as `kind`
+    pub hits: u64,
+    /// Redacted values, the first letter of every word is kept
+    pub examples: Vec<String>,
+    /// Whether the processors writing the sampled documents replace every detected
+    /// value. Documents no processor writes are not copied and don't count.
+    pub covered: bool,
+}
+
+impl PiiFinding {
+    /// Share of the values at the path detected as `kind`
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / self.values.max(1) as f64
+    }
+}
+
+#[derive(Default)]
+struct Hits {
+    hits: u64,
+    unmasked: u64,
+    examples: Vec<String>,
+}
+
+/// Samples a source collection, infers its fields and runs the detector on every
+/// string value. Each sampled document is masked by the `processors` of the
+/// collection to find out whether the detected values are replaced.
+pub(crate) async fn scan_collection(
+    dbs: &DatabasePair,
+    database: &str,
+    collection: &str,
+    processors: &[&dyn Processor],
+    strategy: &ReplicationStrategy,
+    config: &ScanConfig,
+    detector: &PiiDetector,
+) -> CollectionScan {
+    let mut scan = CollectionScan {
+        database: database.to_string(),
+        collection: collection.to_string(),
+        processed: !processors.is_empty(),
+        ..Default::default()
+    };
+
+    if let Err(e) = sample(dbs, processors, strategy, config, detector, &mut scan).await {
+        scan.error = Some(format!("Could not sample documents: {e}"));
+    }
+    scan
+}
+
+async fn sample(
+    dbs: &DatabasePair,
+    processors: &[&dyn Processor],
+    strategy: &ReplicationStrategy,
+    config: &ScanConfig,
+    detector: &PiiDetector,
+    scan: &mut CollectionScan,
+) -> TuxedoResult<()> {
+    let mut values: BTreeMap<String, u64> = BTreeMap::new();
+    let mut hits: BTreeMap<(String, PiiKind), Hits> = BTreeMap::new();
+    let mut inference = SchemaInference::default();
+
+    let mut cursor = dbs
+        .aggregate_documents(
+            &scan.collection,
+            vec![doc! { "$sample": { "size": config.sample_size as i64 } }],
+        )
+        .await?;
+    while cursor.advance().await? {
+        let document: Document = cursor.deserialize_current()?;
+        scan.documents_sampled += 1;
+        inference.add_document(&document);
+
+        // Only masked when something was detected
+        let mut written: Option<Vec<Document>> = None;
+        for (path, value) in string_values(&document) {
+            *values.entry(path.clone()).or_default() += 1;
+
+            for kind in detector.detect(value) {
+                let written = written.get_or_insert_with(|| {
+                    processors
+                        .iter()
+                        .filter_map(|processor| processor.mask_document(&document, strategy))
+                        .collect()
+                });
+                let unmasked = written.iter().any(|masked| {
+                    values_at_path(masked, &path)
+                        .into_iter()
+                        .any(|masked| matches!(masked, Bson::String(masked) if masked == value))
+                });
+
+                let entry = hits.entry((path.clone(), kind)).or_default();
+                entry.hits += 1;
+                if unmasked {
+                    entry.unmasked += 1;
+                }
+                let example = redact(value);
+                if entry.examples.len() < config.examples && !entry.examples.contains(&example) {
+                    entry.examples.push(example);
+                }
+            }
+        }
+    }
+
+    scan.findings = hits
+        .into_iter()
+        .map(|((path, kind), hits)| PiiFinding {
+            values: values.get(&path).copied().unwrap_or_default(),
+            path,
+            kind,
+            hits: hits.hits,
+            examples: hits.examples,
+            covered: scan.processed && hits.unmasked == 0,
+        })
+        .collect();
+    scan.fields = inference.fields();
+    Ok(())
+}
+
+/// Every string of a document with its dotted path
+fn string_values(document: &Document) -> Vec<(String, &str)> {
+    let mut values = Vec::new();
+    collect_from_document(document, "", &mut values);
+    values
+}
+
+fn collect_from_document<'a>(
+    document: &'a Document,
+    prefix: &str,
+    values: &mut Vec<(String, &'a str)>,
+) {
+    for (key, value) in document.iter() {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        collect_from_value(value, path, values);
+    }
+}
+
+fn collect_from_value<'a>(value: &'a Bson, path: String, values: &mut Vec<(String, &'a str)>) {
+    match value {
+        Bson::String(value) => values.push((path, value)),
+        Bson::Document(document) => collect_from_document(document, &path, values),
+        Bson::Array(elements) => {
+            for element in elements {
+                collect_from_value(element, path.clone(), values);
+            }
+        }
+        _ => {}
+    }
+}
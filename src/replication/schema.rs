@@ -0,0 +1,98 @@
+use super::report::CollectionStats;
+use super::types::DatabasePair;
+use crate::TuxedoResult;
+use bson::Document;
+use mongodb::options::{CreateCollectionOptions, ValidationAction, ValidationLevel};
+use mongodb::results::CollectionType;
+use std::sync::Arc;
+use tracing::info;
+
+/// Validator of a source collection, applied to the target through `collMod` once
+/// the data has been loaded when validators are deferred.
+#[derive(Debug, Clone)]
+pub struct CollectionValidator {
+    pub validator: Document,
+    pub validation_level: Option<ValidationLevel>,
+    pub validation_action: Option<ValidationAction>,
+}
+
+/// A target collection created during the schema phase whose validator still has
+/// to be applied after the load.
+#[derive(Debug)]
+pub(crate) struct DeferredValidator {
+    pub(crate) dbs: Arc<DatabasePair>,
+    pub(crate) collection_name: String,
+    pub(crate) validator: CollectionValidator,
+    pub(crate) stats: Vec<Arc<CollectionStats>>,
+}
+
+/// Creates `target_collection_name` with the options of `source_collection_name`
+/// (validator, collation, capped, clustered index, time-series, change stream
+/// pre/post images, ...) so they are not lost to the implicit creation on the first
+/// insert. Returns the validator when it has been held back until after the load.
+pub(crate) async fn create_target_collection(
+    dbs: &DatabasePair,
+    source_collection_name: &str,
+    target_collection_name: &str,
+    defer_validators: bool,
+) -> TuxedoResult<Option<CollectionValidator>> {
+    let Some(specification) = dbs
+        .get_source_collection_specification(source_collection_name)
+        .await?
+    else {
+        info!(
+            collection = source_collection_name,
+            target_collection = target_collection_name,
+            "Source collection not found, target will be created implicitly"
+        );
+        return Ok(None);
+    };
+
+    // Views are recreated separately once all collections have been copied
+    if matches!(specification.collection_type, CollectionType::View) {
+        return Ok(None);
+    }
+
+    let (options, deferred_validator) =
+        prepare_collection_options(specification.options, defer_validators);
+
+    dbs.create_target_collection(target_collection_name, options)
+        .await?;
+
+    Ok(deferred_validator)
+}
+
+/// Strips the options that should not be carried over from the source specification
+/// and splits out the validator when it is deferred.
+fn prepare_collection_options(
+    mut options: CreateCollectionOptions,
+    defer_validators: bool,
+) -> (CreateCollectionOptions, Option<CollectionValidator>) {
+    // Only relevant for views, which are handled separately
+    options.view_on = None;
+    options.pipeline = None;
+
+    // The server reports the bucketing parameters implied by the granularity,
+    // but refuses to accept both when creating the collection
+    if let Some(timeseries) = options.timeseries.as_mut() {
+        if timeseries.granularity.is_some() {
+            timeseries.bucket_max_span = None;
+            timeseries.bucket_rounding = None;
+        }
+    }
+
+    if !defer_validators {
+        return (options, None);
+    }
+
+    let deferred_validator = options
+        .validator
+        .take()
+        .map(|validator| CollectionValidator {
+            validator,
+            validation_level: options.validation_level.take(),
+            validation_action: options.validation_action.take(),
+        });
+
+    (options, deferred_validator)
+}
@@ -0,0 +1,240 @@
+use super::indexes::ServerVersion;
+use super::schema::CollectionValidator;
+use super::source::DocumentCursor;
+use crate::TuxedoResult;
+use async_trait::async_trait;
+use bson::{doc, Document, RawDocumentBuf};
+use futures_util::{StreamExt, TryStreamExt};
+use mongodb::options::{CreateCollectionOptions, FindOptions, InsertManyOptions};
+use mongodb::{Database, IndexModel};
+use std::fmt::Debug;
+use tracing::warn;
+
+/// Destination the replicated data is written to. The target database is the
+/// default, other sinks write the masked data to files for an offline hand-off.
+///
+/// Implement it to replicate into anything else, see
+/// `ReplicationManagerBuilder::target`.
+#[async_trait]
+pub trait Sink: Send + Sync + Debug {
+    /// Writes a batch of (masked) documents into a collection
+    async fn write(
+        &self,
+        collection_name: &str,
+        documents: &[RawDocumentBuf],
+        options: Option<InsertManyOptions>,
+    ) -> TuxedoResult<()>;
+
+    /// Explicitly creates a collection with the given options
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        options: CreateCollectionOptions,
+    ) -> TuxedoResult<()>;
+
+    /// Applies a validator to an existing collection
+    async fn apply_validator(
+        &self,
+        collection_name: &str,
+        validator: &CollectionValidator,
+    ) -> TuxedoResult<()>;
+
+    async fn create_indexes(
+        &self,
+        collection_name: &str,
+        indexes: Vec<IndexModel>,
+    ) -> TuxedoResult<()>;
+
+    /// Creates a view, `pipeline` already references the target collections
+    async fn create_view(
+        &self,
+        view_name: &str,
+        view_on: &str,
+        pipeline: Vec<Document>,
+    ) -> TuxedoResult<()>;
+
+    async fn drop_collection(&self, collection_name: &str) -> TuxedoResult<()>;
+
+    /// Collections and views currently present in the sink
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>>;
+
+    /// Server version of the sink, `None` when it is not a server or is unknown
+    async fn server_version(&self) -> Option<ServerVersion>;
+
+    /// Checks the sink can be written to, called once when the manager is built
+    async fn test_connection(&self) -> TuxedoResult<()>;
+
+    /// Called once every document, index and view has been written
+    async fn finish(&self) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    /// Reads back the documents of a collection matching `query` to verify the run,
+    /// honouring the sort and projection of `options`. `None` when the sink can't be
+    /// read back.
+    async fn find(
+        &self,
+        _collection_name: &str,
+        _query: Document,
+        _options: Option<FindOptions>,
+    ) -> TuxedoResult<Option<DocumentCursor>> {
+        Ok(None)
+    }
+
+    /// Counts the documents of a collection matching `query` to verify the run.
+    /// `None` when the sink can't be read back.
+    async fn count_documents(
+        &self,
+        _collection_name: &str,
+        _query: Document,
+    ) -> TuxedoResult<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// Writes into a MongoDB database
+#[derive(Debug)]
+pub struct MongoSink {
+    database: Database,
+}
+
+impl MongoSink {
+    pub fn new(database: Database) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl Sink for MongoSink {
+    async fn write(
+        &self,
+        collection_name: &str,
+        documents: &[RawDocumentBuf],
+        options: Option<InsertManyOptions>,
+    ) -> TuxedoResult<()> {
+        self.database
+            .collection::<RawDocumentBuf>(collection_name)
+            .insert_many(documents)
+            .with_options(options)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        options: CreateCollectionOptions,
+    ) -> TuxedoResult<()> {
+        self.database
+            .create_collection(collection_name)
+            .with_options(options)
+            .await?;
+        Ok(())
+    }
+
+    /// Uses `collMod` so the validator only applies to future writes
+    async fn apply_validator(
+        &self,
+        collection_name: &str,
+        validator: &CollectionValidator,
+    ) -> TuxedoResult<()> {
+        let mut command = doc! {
+            "collMod": collection_name,
+            "validator": validator.validator.clone(),
+        };
+        if let Some(validation_level) = validator.validation_level.as_ref() {
+            command.insert("validationLevel", bson::to_bson(validation_level)?);
+        }
+        if let Some(validation_action) = validator.validation_action.as_ref() {
+            command.insert("validationAction", bson::to_bson(validation_action)?);
+        }
+
+        self.database.run_command(command).await?;
+        Ok(())
+    }
+
+    async fn create_indexes(
+        &self,
+        collection_name: &str,
+        indexes: Vec<IndexModel>,
+    ) -> TuxedoResult<()> {
+        self.database
+            .collection::<Document>(collection_name)
+            .create_indexes(indexes)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_view(
+        &self,
+        view_name: &str,
+        view_on: &str,
+        pipeline: Vec<Document>,
+    ) -> TuxedoResult<()> {
+        let create_view_command = doc! {
+            "create": view_name,
+            "viewOn": view_on,
+            "pipeline": pipeline,
+        };
+
+        self.database.run_command(create_view_command).await?;
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> TuxedoResult<()> {
+        self.database
+            .collection::<Document>(collection_name)
+            .drop()
+            .await?;
+        Ok(())
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        Ok(self.database.list_collection_names().await?)
+    }
+
+    async fn server_version(&self) -> Option<ServerVersion> {
+        match self.database.run_command(doc! { "buildInfo": 1 }).await {
+            Ok(build_info) => ServerVersion::from_build_info(&build_info),
+            Err(e) => {
+                warn!(error = %e, "Could not determine target server version");
+                None
+            }
+        }
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        self.database.list_collection_names().await?;
+        Ok(())
+    }
+
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<Option<DocumentCursor>> {
+        let cursor = self
+            .database
+            .collection::<RawDocumentBuf>(collection_name)
+            .find(query)
+            .with_options(options)
+            .await?;
+        Ok(Some(DocumentCursor::new(
+            cursor.map_err(Into::into).boxed(),
+        )))
+    }
+
+    async fn count_documents(
+        &self,
+        collection_name: &str,
+        query: Document,
+    ) -> TuxedoResult<Option<u64>> {
+        Ok(Some(
+            self.database
+                .collection::<RawDocumentBuf>(collection_name)
+                .count_documents(query)
+                .await?,
+        ))
+    }
+}
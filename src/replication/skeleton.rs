@@ -0,0 +1,586 @@
+use super::infer::FieldSchema;
+use super::scan::{CollectionScan, ScanReport};
+use super::types::DEFAULT_DATABASE;
+use crate::pii::PiiKind;
+use crate::MaskAction;
+use bson::Bson;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Hit rate from which a suggested rule is enabled, weaker suggestions are written
+/// commented out
+const CONFIDENT_HIT_RATE: f64 = 0.5;
+
+const POLICY_HEADER: &str = "\
+# Masking policy generated from a sample of the source, load it with `Policy::load`.
+# Rules detected in most sampled values are enabled, weaker suggestions are
+# commented out. Every field is listed with its BSON types, review them all.
+#
+# Actions: \"name\", \"first_name\", \"last_name\", \"full_name\", \"email\",
+# \"phone_number\", \"address\", \"postal_code\", \"comments\", \"null\", \"remove\",
+# { numeric_string = <length> } and { fixed = <value> }";
+
+const STRUCTS_HEADER: &str = "\
+// Models generated from a sample of the source. Unions of types are `bson::Bson`,
+// fields missing or null in some documents are `Option`s and fields absent from
+// the sample are dropped when deserializing, review them all.
+
+use serde::{Deserialize, Serialize};
+use tuxedo::Mask;";
+
+impl ScanReport {
+    /// Masking policy with a `[[collections]]` entry per sampled collection, listing
+    /// every inferred field with the rule suggested by the PII detector or the field
+    /// name. Parses with `Policy::from_toml`.
+    pub fn policy_skeleton(&self) -> String {
+        let mut lines = vec![POLICY_HEADER.to_string()];
+        for scan in self.collections.iter() {
+            lines.push(String::new());
+            lines.push("[[collections]]".to_string());
+            lines.push(format!("name = {}", toml_string(&scan.collection)));
+            if scan.database != DEFAULT_DATABASE {
+                lines.push(format!("database = {}", toml_string(&scan.database)));
+            }
+            lines.push(sample_comment("# ", scan));
+            lines.push(String::new());
+            lines.push("[collections.rules]".to_string());
+
+            for field in scan.fields.iter() {
+                let mut description = format!("# {}: {}", field.path, field.type_description());
+                if field.is_optional() {
+                    description.push_str(", optional");
+                }
+                for finding in scan.findings.iter().filter(|f| f.path == field.path) {
+                    description.push_str(&format!(
+                        ", {} in {:.0}% of values",
+                        finding.kind,
+                        finding.hit_rate() * 100.0
+                    ));
+                }
+                lines.push(description);
+
+                if let Some(suggestion) = suggest(scan, field) {
+                    lines.push(format!(
+                        "{}{} = {}",
+                        if suggestion.confident { "" } else { "# " },
+                        toml_key(&field.path),
+                        action_toml(&suggestion.action)
+                    ));
+                }
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Rust models of the sampled collections with their serde attributes, nested
+    /// documents becoming their own structs, and a `Mask` implementation replacing
+    /// the string fields a rule is suggested for
+    pub fn rust_structs(&self) -> String {
+        let mut lines = vec![STRUCTS_HEADER.to_string()];
+        let mut struct_names = HashSet::new();
+        for scan in self.collections.iter() {
+            lines.push(String::new());
+            if scan.fields.is_empty() {
+                lines.push(format!("// `{}`: no documents sampled", scan.collection));
+                continue;
+            }
+            Models::new(scan, &mut struct_names).write(&mut lines);
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+fn sample_comment(prefix: &str, scan: &CollectionScan) -> String {
+    match scan.error.as_ref() {
+        Some(error) => format!("{prefix}{error}"),
+        None if scan.documents_sampled == 1 => format!("{prefix}1 document sampled"),
+        None => format!("{prefix}{} documents sampled", scan.documents_sampled),
+    }
+}
+
+/// Masking suggested for a field
+struct Suggestion {
+    action: MaskAction,
+    reason: String,
+    confident: bool,
+}
+
+/// Prefers the most frequent detection, then the field name. Free text fields and
+/// values detected only now and then are suggested to become comments.
+fn suggest(scan: &CollectionScan, field: &FieldSchema) -> Option<Suggestion> {
+    let holds_strings =
+        field.value_types().contains(&"string") || field.element_value_types().contains(&"string");
+    if !holds_strings {
+        return None;
+    }
+
+    let finding = scan
+        .findings
+        .iter()
+        .filter(|finding| finding.path == field.path)
+        // Ties go to the most specific kind, names are often part of other values
+        .max_by(|a, b| {
+            a.hit_rate()
+                .total_cmp(&b.hit_rate())
+                .then(b.kind.cmp(&a.kind))
+        });
+    let named = action_for_name(&field.path);
+
+    match (finding, named) {
+        (Some(finding), Some(MaskAction::Comments)) if finding.hit_rate() >= CONFIDENT_HIT_RATE => {
+            Some(Suggestion {
+                action: MaskAction::Comments,
+                reason: finding_reason(finding.kind, finding.hit_rate()),
+                confident: true,
+            })
+        }
+        (Some(finding), named) if finding.hit_rate() >= CONFIDENT_HIT_RATE => Some(Suggestion {
+            action: match finding.kind {
+                PiiKind::Email => MaskAction::Email,
+                PiiKind::PhoneNumber => MaskAction::PhoneNumber,
+                PiiKind::PostalCode => MaskAction::PostalCode,
+                PiiKind::CreditCard => MaskAction::NumericString(16),
+                PiiKind::NationalId => MaskAction::NumericString(9),
+                PiiKind::IpAddress => MaskAction::Fixed(Bson::String("0.0.0.0".to_string())),
+                PiiKind::Name => match named {
+                    Some(
+                        action @ (MaskAction::FirstName
+                        | MaskAction::LastName
+                        | MaskAction::FullName),
+                    ) => action,
+                    _ => MaskAction::Name,
+                },
+            },
+            reason: finding_reason(finding.kind, finding.hit_rate()),
+            confident: true,
+        }),
+        (_, Some(action)) => Some(Suggestion {
+            action,
+            reason: "suggested by the field name".to_string(),
+            confident: false,
+        }),
+        (Some(finding), None) => Some(Suggestion {
+            action: match finding.kind {
+                PiiKind::PhoneNumber => MaskAction::PhoneNumber,
+                PiiKind::PostalCode => MaskAction::PostalCode,
+                PiiKind::NationalId => MaskAction::NumericString(9),
+                _ => MaskAction::Comments,
+            },
+            reason: finding_reason(finding.kind, finding.hit_rate()),
+            confident: false,
+        }),
+        (None, None) => None,
+    }
+}
+
+fn finding_reason(kind: PiiKind, hit_rate: f64) -> String {
+    format!("{kind} in {:.0}% of values", hit_rate * 100.0)
+}
+
+/// Action suggested by the last segment of a path, ignoring case and separators
+fn action_for_name(path: &str) -> Option<MaskAction> {
+    let name: String = path
+        .rsplit('.')
+        .next()
+        .unwrap_or(path)
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    Some(match name.as_str() {
+        name if name.contains("email") => MaskAction::Email,
+        "ip" | "ipaddress" | "ipaddr" => MaskAction::Fixed(Bson::String("0.0.0.0".to_string())),
+        name if name.contains("phone") || name.contains("mobile") => MaskAction::PhoneNumber,
+        name if name.contains("firstname") || name.contains("givenname") => MaskAction::FirstName,
+        name if name.contains("lastname")
+            || name.contains("surname")
+            || name.contains("familyname") =>
+        {
+            MaskAction::LastName
+        }
+        "fullname" => MaskAction::FullName,
+        "name" | "displayname" | "contactname" => MaskAction::Name,
+        name if name.contains("street") || name.contains("address") => MaskAction::Address,
+        name if name.contains("postal") || name.contains("zip") => MaskAction::PostalCode,
+        name if name.contains("comment") || name.contains("note") => MaskAction::Comments,
+        "ssn" | "sin" => MaskAction::NumericString(9),
+        _ => return None,
+    })
+}
+
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// Dotted paths are quoted so they stay a single key
+fn toml_key(path: &str) -> String {
+    if !path.is_empty()
+        && path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        path.to_string()
+    } else {
+        toml_string(path)
+    }
+}
+
+fn action_toml(action: &MaskAction) -> String {
+    toml::Value::try_from(action)
+        .map(|value| value.to_string())
+        .unwrap_or_else(|_| format!("{action:?}"))
+}
+
+/// How a string field is held by its struct
+enum Holder {
+    Value,
+    /// `Option<String>` or `Vec<String>`
+    Container,
+    /// `Option<Vec<String>>`
+    OptionalVec,
+}
+
+/// Structs generated for a collection, the root one named after the collection and
+/// one per embedded document path
+struct Models<'a> {
+    scan: &'a CollectionScan,
+    fields: HashMap<&'a str, &'a FieldSchema>,
+    /// Fields by parent path, the top level ones under the empty path
+    children: BTreeMap<&'a str, Vec<&'a FieldSchema>>,
+    /// Rust identifier of every field
+    idents: HashMap<&'a str, String>,
+    /// Struct name of the root and of every embedded document path
+    structs: BTreeMap<&'a str, String>,
+}
+
+impl<'a> Models<'a> {
+    fn new(scan: &'a CollectionScan, struct_names: &mut HashSet<String>) -> Self {
+        let mut models = Self {
+            scan,
+            fields: HashMap::new(),
+            children: BTreeMap::new(),
+            idents: HashMap::new(),
+            structs: BTreeMap::new(),
+        };
+        models
+            .structs
+            .insert("", unique_name(pascal_case(&scan.collection), struct_names));
+
+        // Paths are ordered, parents come before their fields
+        for field in scan.fields.iter() {
+            let (parent, name) = split_path(&field.path);
+            models.fields.insert(&field.path, field);
+            let Some(parent_struct) = models.structs.get(parent).cloned() else {
+                continue;
+            };
+
+            let siblings = models.children.entry(parent).or_default();
+            let mut ident = field_ident(name);
+            if siblings
+                .iter()
+                .any(|sibling| models.idents[sibling.path.as_str()] == ident)
+            {
+                let mut suffix = 2;
+                while siblings.iter().any(|sibling| {
+                    models.idents[sibling.path.as_str()] == format!("{ident}_{suffix}")
+                }) {
+                    suffix += 1;
+                }
+                ident = format!("{ident}_{suffix}");
+            }
+            siblings.push(field);
+            models.idents.insert(&field.path, ident);
+
+            if is_embedded_document(field) {
+                let name = format!("{parent_struct}{}", pascal_case(name));
+                models
+                    .structs
+                    .insert(&field.path, unique_name(name, struct_names));
+            }
+        }
+        models
+    }
+
+    fn write(&self, lines: &mut Vec<String>) {
+        for (path, name) in self.structs.iter() {
+            if path.is_empty() {
+                lines.push(format!(
+                    "/// `{}`{}",
+                    self.scan.collection,
+                    sample_comment(", ", self.scan)
+                ));
+            } else {
+                lines.push(String::new());
+                lines.push(format!("/// `{path}` of `{}`", self.scan.collection));
+            }
+            lines.push("#[derive(Debug, Clone, Serialize, Deserialize)]".to_string());
+            lines.push(format!("pub struct {name} {{"));
+            for field in self.children.get(path).into_iter().flatten() {
+                self.write_field(field, lines);
+            }
+            lines.push("}".to_string());
+
+            if path.is_empty() {
+                lines.push(String::new());
+                self.write_mask(name, lines);
+            }
+        }
+    }
+
+    fn write_field(&self, field: &FieldSchema, lines: &mut Vec<String>) {
+        let (_, name) = split_path(&field.path);
+        let ident = &self.idents[field.path.as_str()];
+        let (rust_type, exact) = self.rust_type(field);
+
+        if !exact {
+            lines.push(format!("    /// Sampled as `{}`", field.type_description()));
+        }
+        let mut attributes = Vec::new();
+        if ident.trim_start_matches("r#") != name {
+            attributes.push(format!("rename = {name:?}"));
+        }
+        let rust_type = if field.is_optional() {
+            attributes.push("default, skip_serializing_if = \"Option::is_none\"".to_string());
+            format!("Option<{rust_type}>")
+        } else {
+            rust_type
+        };
+        if !attributes.is_empty() {
+            lines.push(format!("    #[serde({})]", attributes.join(", ")));
+        }
+        lines.push(format!("    pub {ident}: {rust_type},"));
+    }
+
+    /// Type of a field without its `Option`, and whether it holds every sampled value
+    /// without conversion
+    fn rust_type(&self, field: &FieldSchema) -> (String, bool) {
+        match field.value_types().as_slice() {
+            ["array"] => {
+                let element_types = field.element_value_types();
+                let (element, exact) = match element_types.as_slice() {
+                    [] => ("bson::Bson".to_string(), true),
+                    ["array"] => ("bson::Bson".to_string(), false),
+                    types => self.scalar_type(field, types),
+                };
+                let nullable = field.element_types.contains_key("null")
+                    || field.element_types.contains_key("undefined");
+                if nullable {
+                    (format!("Vec<Option<{element}>>"), exact)
+                } else {
+                    (format!("Vec<{element}>"), exact)
+                }
+            }
+            [] => ("bson::Bson".to_string(), true),
+            types => self.scalar_type(field, types),
+        }
+    }
+
+    fn scalar_type(&self, field: &FieldSchema, types: &[&str]) -> (String, bool) {
+        let rust_type = match types {
+            ["object"] => return (self.structs[field.path.as_str()].clone(), true),
+            ["string"] => "String",
+            ["int"] => "i32",
+            ["long"] => "i64",
+            ["double"] => "f64",
+            ["decimal"] => "bson::Decimal128",
+            ["bool"] => "bool",
+            ["date"] => "bson::DateTime",
+            ["objectId"] => "bson::oid::ObjectId",
+            ["binData"] => "bson::Binary",
+            ["timestamp"] => "bson::Timestamp",
+            ["regex"] => "bson::Regex",
+            [_] => "bson::Bson",
+            types if types.iter().all(|t| matches!(*t, "int" | "long")) => {
+                return ("i64".to_string(), false)
+            }
+            types
+                if types
+                    .iter()
+                    .all(|t| matches!(*t, "int" | "long" | "double")) =>
+            {
+                return ("f64".to_string(), false)
+            }
+            _ => return ("bson::Bson".to_string(), false),
+        };
+        (rust_type.to_string(), true)
+    }
+
+    fn write_mask(&self, name: &str, lines: &mut Vec<String>) {
+        let mut body = Vec::new();
+        for field in self.scan.fields.iter() {
+            let Some(suggestion) = suggest(self.scan, field) else {
+                continue;
+            };
+            body.push(format!("// {}: {}", field.path, suggestion.reason));
+
+            let code = match (self.access(field), fake_expression(&suggestion.action)) {
+                (Some((access, holder)), Some(fake)) => match holder {
+                    Holder::Value => vec![format!("self.{access} = {fake};")],
+                    Holder::Container => vec![
+                        format!("for value in self.{access}.iter_mut() {{"),
+                        format!("    *value = {fake};"),
+                        "}".to_string(),
+                    ],
+                    Holder::OptionalVec => vec![
+                        format!("for value in self.{access}.iter_mut().flatten() {{"),
+                        format!("    *value = {fake};"),
+                        "}".to_string(),
+                    ],
+                },
+                _ => {
+                    body.push(format!(
+                        "// Not a plain string field, mask it with a `{}` rule",
+                        action_toml(&suggestion.action)
+                    ));
+                    continue;
+                }
+            };
+            body.extend(code.into_iter().map(|line| {
+                if suggestion.confident {
+                    line
+                } else {
+                    format!("// {line}")
+                }
+            }));
+        }
+
+        lines.push(format!("impl Mask for {name} {{"));
+        lines.push("    fn mask(&mut self) {".to_string());
+        if body.is_empty() {
+            body.push("// No personal information detected, review the fields".to_string());
+        }
+        lines.extend(body.into_iter().map(|line| format!("        {line}")));
+        lines.push("    }".to_string());
+        lines.push("}".to_string());
+    }
+
+    /// Field access from the root struct when every parent is a required embedded
+    /// document and the field holds strings
+    fn access(&self, field: &FieldSchema) -> Option<(String, Holder)> {
+        let holder = match (field.value_types().as_slice(), field.is_optional()) {
+            (["string"], false) => Holder::Value,
+            (["string"], true) => Holder::Container,
+            (["array"], optional)
+                if field.element_types.keys().all(|t| t == "string")
+                    && !field.element_types.is_empty() =>
+            {
+                if optional {
+                    Holder::OptionalVec
+                } else {
+                    Holder::Container
+                }
+            }
+            _ => return None,
+        };
+
+        let mut access = vec![self.idents.get(field.path.as_str())?.clone()];
+        let mut path = field.path.as_str();
+        while let Some((parent, _)) = path.rsplit_once('.') {
+            let parent_field = self.fields.get(parent)?;
+            if parent_field.is_optional() || parent_field.value_types() != ["object"] {
+                return None;
+            }
+            access.push(self.idents.get(parent)?.clone());
+            path = parent;
+        }
+        access.reverse();
+        Some((access.join("."), holder))
+    }
+}
+
+/// `Mask` call producing the value of an action, none for actions changing the type
+fn fake_expression(action: &MaskAction) -> Option<String> {
+    Some(match action {
+        MaskAction::Name => "Self::fake_name()".to_string(),
+        MaskAction::FirstName => "Self::fake_first_name()".to_string(),
+        MaskAction::LastName => "Self::fake_last_name()".to_string(),
+        MaskAction::FullName => "Self::fake_full_name()".to_string(),
+        MaskAction::Email => "Self::fake_email()".to_string(),
+        MaskAction::PhoneNumber => "Self::fake_phone_number()".to_string(),
+        MaskAction::Address => "Self::fake_address()".to_string(),
+        MaskAction::PostalCode => "Self::fake_postal_code()".to_string(),
+        MaskAction::Comments => "Self::fake_comments()".to_string(),
+        MaskAction::NumericString(length) => format!("Self::fake_numeric_string({length})"),
+        MaskAction::Fixed(Bson::String(value)) => format!("{value:?}.to_string()"),
+        MaskAction::Fixed(_) | MaskAction::Null | MaskAction::Remove => return None,
+    })
+}
+
+fn is_embedded_document(field: &FieldSchema) -> bool {
+    match field.value_types().as_slice() {
+        ["object"] => true,
+        ["array"] => field.element_value_types() == ["object"],
+        _ => false,
+    }
+}
+
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('.').unwrap_or(("", path))
+}
+
+fn unique_name(name: String, names: &mut HashSet<String>) -> String {
+    let mut unique = name.clone();
+    let mut suffix = 2;
+    while !names.insert(unique.clone()) {
+        unique = format!("{name}{suffix}");
+        suffix += 1;
+    }
+    unique
+}
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Snake case identifier of a field name, `_id` becoming `id`
+fn field_ident(name: &str) -> String {
+    let mut ident = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            let boundary = previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit());
+            if c.is_ascii_uppercase() && boundary {
+                ident.push('_');
+            }
+            ident.push(c.to_ascii_lowercase());
+        } else if !ident.is_empty() && !ident.ends_with('_') {
+            ident.push('_');
+        }
+        previous = Some(c);
+    }
+
+    let ident = ident.trim_end_matches('_');
+    match ident {
+        "" => "field".to_string(),
+        "self" | "super" | "crate" => format!("{ident}_"),
+        ident if KEYWORDS.contains(&ident) => format!("r#{ident}"),
+        ident if ident.starts_with(|c: char| c.is_ascii_digit()) => format!("_{ident}"),
+        ident => ident.to_string(),
+    }
+}
+
+/// Pascal case type name, separators dropped and the following letter capitalized
+fn pascal_case(name: &str) -> String {
+    let mut pascal: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect();
+
+    if pascal.is_empty() || pascal.starts_with(|c: char| c.is_ascii_digit()) || pascal == "Self" {
+        pascal.insert_str(0, "Model");
+    }
+    pascal
+}
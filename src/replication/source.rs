@@ -0,0 +1,218 @@
+use crate::{TuxedoError, TuxedoResult};
+use async_trait::async_trait;
+use bson::{doc, Document, RawDocumentBuf};
+use futures_util::stream::BoxStream;
+use futures_util::{StreamExt, TryStreamExt};
+use mongodb::options::{AggregateOptions, FindOptions};
+use mongodb::results::CollectionSpecification;
+use mongodb::{Database, IndexModel};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+
+/// Origin of the replicated data. The source database is the default, other sources
+/// read snapshots from files without restoring the unmasked data anywhere.
+///
+/// Implement it to replicate from anything else, see
+/// `ReplicationManagerBuilder::source`.
+#[async_trait]
+pub trait Source: Send + Sync + Debug {
+    /// Documents of a collection matching `query`, honouring the sort, skip and
+    /// limit of `options`
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<DocumentCursor>;
+
+    /// Runs an aggregation pipeline on a collection
+    async fn aggregate(
+        &self,
+        collection_name: &str,
+        pipeline: Vec<Document>,
+        options: Option<AggregateOptions>,
+    ) -> TuxedoResult<DocumentCursor>;
+
+    async fn count_documents(&self, collection_name: &str, query: Document) -> TuxedoResult<u64>;
+
+    /// Average size in bytes of the documents of a collection
+    async fn average_document_size(&self, collection_name: &str) -> TuxedoResult<u64>;
+
+    /// The `list_collections` specification of a single collection
+    async fn collection_specification(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<Option<CollectionSpecification>>;
+
+    async fn list_indexes(&self, collection_name: &str) -> TuxedoResult<Vec<IndexModel>>;
+
+    /// Specifications of every view
+    async fn list_views(&self) -> TuxedoResult<Vec<CollectionSpecification>>;
+
+    /// Names of every collection, time-series included and views excluded. Sources
+    /// which can't list their collections return none, a scan then only covers the
+    /// processors' collections.
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Checks the source can be read, called once when the manager is built
+    async fn test_connection(&self) -> TuxedoResult<()>;
+
+    /// Whether reading a slice of a collection with skip/limit is cheap. Sources which
+    /// have to scan from the start for every slice are read by a single task instead.
+    fn supports_partitioned_reads(&self) -> bool {
+        true
+    }
+}
+
+/// Documents read from a `Source`, consumed like a driver cursor
+pub struct DocumentCursor {
+    stream: BoxStream<'static, TuxedoResult<RawDocumentBuf>>,
+    current: Option<RawDocumentBuf>,
+}
+
+impl DocumentCursor {
+    pub fn new(stream: BoxStream<'static, TuxedoResult<RawDocumentBuf>>) -> Self {
+        Self {
+            stream,
+            current: None,
+        }
+    }
+
+    /// Moves to the next document, returning `false` once the cursor is exhausted
+    pub async fn advance(&mut self) -> TuxedoResult<bool> {
+        self.current = self.stream.next().await.transpose()?;
+        Ok(self.current.is_some())
+    }
+
+    /// Size in bytes of the current document
+    pub(crate) fn current_size(&self) -> usize {
+        self.current
+            .as_ref()
+            .map_or(0, |current| current.as_bytes().len())
+    }
+
+    pub fn deserialize_current<T: DeserializeOwned>(&self) -> TuxedoResult<T> {
+        let current = self
+            .current
+            .as_ref()
+            .ok_or_else(|| TuxedoError::Generic("Cursor has no current document".into()))?;
+        Ok(bson::from_slice(current.as_bytes())?)
+    }
+}
+
+/// Reads from a MongoDB database
+#[derive(Debug)]
+pub struct MongoSource {
+    database: Database,
+}
+
+impl MongoSource {
+    pub fn new(database: Database) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl Source for MongoSource {
+    async fn find(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let cursor = self
+            .database
+            .collection::<RawDocumentBuf>(collection_name)
+            .find(query)
+            .with_options(options)
+            .await?;
+        Ok(DocumentCursor::new(cursor.map_err(Into::into).boxed()))
+    }
+
+    async fn aggregate(
+        &self,
+        collection_name: &str,
+        pipeline: Vec<Document>,
+        options: Option<AggregateOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        let cursor = self
+            .database
+            .collection::<Document>(collection_name)
+            .aggregate(pipeline)
+            .with_type::<RawDocumentBuf>()
+            .with_options(options)
+            .await?;
+        Ok(DocumentCursor::new(cursor.map_err(Into::into).boxed()))
+    }
+
+    async fn count_documents(&self, collection_name: &str, query: Document) -> TuxedoResult<u64> {
+        Ok(self
+            .database
+            .collection::<RawDocumentBuf>(collection_name)
+            .count_documents(query)
+            .await?)
+    }
+
+    async fn average_document_size(&self, collection_name: &str) -> TuxedoResult<u64> {
+        let stats = self
+            .database
+            .run_command(doc! { "collStats": collection_name })
+            .await?;
+
+        let avg_doc_size = stats.get_f64("avgObjSize").unwrap_or(1024.0);
+        Ok(avg_doc_size as u64)
+    }
+
+    async fn collection_specification(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<Option<CollectionSpecification>> {
+        let mut cursor = self
+            .database
+            .list_collections()
+            .filter(doc! { "name": collection_name })
+            .await?;
+
+        Ok(cursor.try_next().await?)
+    }
+
+    async fn list_indexes(&self, collection_name: &str) -> TuxedoResult<Vec<IndexModel>> {
+        let cursor = self
+            .database
+            .collection::<Document>(collection_name)
+            .list_indexes()
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_views(&self) -> TuxedoResult<Vec<CollectionSpecification>> {
+        let cursor = self
+            .database
+            .list_collections()
+            .filter(doc! { "type": "view" })
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        let mut names = self
+            .database
+            .list_collection_names()
+            .filter(doc! { "type": { "$in": ["collection", "timeseries"] } })
+            .await?;
+        names.retain(|name| !name.starts_with("system."));
+        names.sort();
+        Ok(names)
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        if self.database.list_collection_names().await?.is_empty() {
+            return Err(TuxedoError::DatabaseMissing(
+                self.database.name().to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use super::indexes::ServerVersion;
+use super::schema::CollectionValidator;
+use super::sink::Sink;
+use crate::{TuxedoError, TuxedoResult};
+use async_trait::async_trait;
+use bson::{Bson, Document, RawDocumentBuf};
+use mongodb::options::{CreateCollectionOptions, InsertManyOptions};
+use mongodb::IndexModel;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Column every table is created with, so collections without documents still get
+/// a table
+const ID_COLUMN: &str = "_id";
+
+/// Columns of every table, keyed by their lowercased name as SQLite compares column
+/// names case-insensitively
+type Tables = Mutex<BTreeMap<String, BTreeMap<String, String>>>;
+
+/// Writes every collection into a table of a single SQLite file, for analytics
+/// extracts which do not need a MongoDB instance.
+///
+/// Nested documents are flattened into dotted columns (`address.city`), arrays are
+/// stored as Extended JSON text. Columns are added as new fields show up, typed
+/// after the first non-null value seen. Fields only differing by case share a
+/// column. SQLite does not enforce column types, values
+/// of another type are stored as they are. Every batch is written in one transaction,
+/// so transactions are sized by `write_batch_size`. Indexes, validators and views
+/// are not written.
+#[derive(Debug)]
+pub(crate) struct SqliteSink {
+    connection: Arc<Mutex<Connection>>,
+    /// Prepended to the table names when several databases share the file
+    table_prefix: Option<String>,
+    /// Columns of every table created so far
+    tables: Arc<Tables>,
+    /// Tables of created collections, made once the run finishes if nothing was
+    /// written into them so their columns can be typed by the first batch
+    created: Mutex<BTreeSet<String>>,
+}
+
+impl SqliteSink {
+    /// Opens (or creates) the SQLite file shared by the sinks of every database
+    pub(crate) fn open_connection(path: &Path) -> TuxedoResult<Arc<Mutex<Connection>>> {
+        let connection = Connection::open(path).map_err(|e| {
+            TuxedoError::ConfigError(format!(
+                "Could not open SQLite file `{}`: {e}",
+                path.display()
+            ))
+        })?;
+        Ok(Arc::new(Mutex::new(connection)))
+    }
+
+    pub(crate) fn new(connection: Arc<Mutex<Connection>>, table_prefix: Option<String>) -> Self {
+        Self {
+            connection,
+            table_prefix,
+            tables: Arc::new(Mutex::new(BTreeMap::new())),
+            created: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    fn table_name(&self, collection_name: &str) -> String {
+        match self.table_prefix.as_ref() {
+            Some(prefix) => format!("{prefix}.{collection_name}"),
+            None => collection_name.to_string(),
+        }
+    }
+
+    /// Runs rusqlite calls, which are synchronous, on a blocking thread
+    async fn with_connection<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> TuxedoResult<R> + Send + 'static,
+    ) -> TuxedoResult<R> {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().expect("SQLite connection poisoned");
+            f(&mut connection)
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    async fn write(
+        &self,
+        collection_name: &str,
+        documents: &[RawDocumentBuf],
+        _options: Option<InsertManyOptions>,
+    ) -> TuxedoResult<()> {
+        let mut rows = Vec::with_capacity(documents.len());
+        for document in documents.iter() {
+            let document: Document = bson::from_slice(document.as_bytes())?;
+            let mut row = BTreeMap::new();
+            flatten(&document, None, &mut row);
+            rows.push(row);
+        }
+
+        // Columns are typed after the first non-null value of the batch
+        let mut columns: BTreeMap<String, &'static str> = BTreeMap::new();
+        for row in rows.iter() {
+            for (column, value) in row.iter() {
+                if let Some(column_type) = column_type(value) {
+                    columns.entry(column.clone()).or_insert(column_type);
+                }
+            }
+        }
+
+        let table = self.table_name(collection_name);
+        let tables = Arc::clone(&self.tables);
+        self.with_connection(move |connection| {
+            let table_columns = ensure_columns(&tables, connection, &table, &columns)?;
+
+            let transaction = connection.transaction()?;
+            for row in rows.into_iter() {
+                // Fields sharing a column keep the first value
+                let mut values: BTreeMap<&str, Value> = BTreeMap::new();
+                for (column, value) in row.into_iter() {
+                    if value == Value::Null {
+                        continue;
+                    }
+                    if let Some(table_column) = table_columns.get(&column) {
+                        values.entry(table_column.as_str()).or_insert(value);
+                    }
+                }
+                let row: Vec<(&str, Value)> = values.into_iter().collect();
+                if row.is_empty() {
+                    transaction
+                        .execute(&format!("INSERT INTO {} DEFAULT VALUES", quote(&table)), [])?;
+                    continue;
+                }
+
+                let names: Vec<String> = row.iter().map(|(column, _)| quote(column)).collect();
+                let placeholders = vec!["?"; row.len()].join(", ");
+                transaction.execute(
+                    &format!(
+                        "INSERT INTO {} ({}) VALUES ({placeholders})",
+                        quote(&table),
+                        names.join(", ")
+                    ),
+                    params_from_iter(row.into_iter().map(|(_, value)| value)),
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        _options: CreateCollectionOptions,
+    ) -> TuxedoResult<()> {
+        self.created
+            .lock()
+            .expect("SQLite tables poisoned")
+            .insert(self.table_name(collection_name));
+        Ok(())
+    }
+
+    async fn apply_validator(
+        &self,
+        _collection_name: &str,
+        _validator: &CollectionValidator,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn create_indexes(
+        &self,
+        _collection_name: &str,
+        _indexes: Vec<IndexModel>,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn create_view(
+        &self,
+        _view_name: &str,
+        _view_on: &str,
+        _pipeline: Vec<Document>,
+    ) -> TuxedoResult<()> {
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection_name: &str) -> TuxedoResult<()> {
+        let table = self.table_name(collection_name);
+        let statement = format!("DROP TABLE IF EXISTS {}", quote(&table));
+        self.with_connection(move |connection| Ok(connection.execute(&statement, [])?))
+            .await?;
+        self.tables
+            .lock()
+            .expect("SQLite tables poisoned")
+            .remove(&table);
+        self.created
+            .lock()
+            .expect("SQLite tables poisoned")
+            .remove(&table);
+        Ok(())
+    }
+
+    async fn list_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        let tables = self
+            .with_connection(|connection| {
+                let mut statement =
+                    connection.prepare("SELECT name FROM sqlite_master WHERE type = 'table'")?;
+                let tables = statement
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(tables)
+            })
+            .await?;
+
+        Ok(tables
+            .into_iter()
+            .filter_map(|table| match self.table_prefix.as_ref() {
+                Some(prefix) => table
+                    .strip_prefix(&format!("{prefix}."))
+                    .map(str::to_string),
+                None => Some(table),
+            })
+            .collect())
+    }
+
+    async fn server_version(&self) -> Option<ServerVersion> {
+        None
+    }
+
+    async fn test_connection(&self) -> TuxedoResult<()> {
+        self.with_connection(|connection| {
+            connection.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Creates the tables of collections without documents
+    async fn finish(&self) -> TuxedoResult<()> {
+        let created = std::mem::take(&mut *self.created.lock().expect("SQLite tables poisoned"));
+        let tables = Arc::clone(&self.tables);
+        self.with_connection(move |connection| {
+            for table in created.iter() {
+                ensure_columns(&tables, connection, table, &BTreeMap::new())?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Creates the table or adds the columns it is missing, typed after `columns`.
+/// Returns the table column of every field, fields only differing by case share
+/// the column of the first one seen.
+fn ensure_columns(
+    tables: &Tables,
+    connection: &Connection,
+    table: &str,
+    columns: &BTreeMap<String, &'static str>,
+) -> TuxedoResult<BTreeMap<String, String>> {
+    let mut tables = tables.lock().expect("SQLite tables poisoned");
+
+    let known = match tables.get_mut(table) {
+        Some(known) => known,
+        None => {
+            let mut known = existing_columns(connection, table)?;
+            if known.is_empty() {
+                let id_type = columns.get(ID_COLUMN).copied().unwrap_or_default();
+                connection.execute(
+                    &format!(
+                        "CREATE TABLE {} ({} {id_type})",
+                        quote(table),
+                        quote(ID_COLUMN)
+                    ),
+                    [],
+                )?;
+                known.insert(column_key(ID_COLUMN), ID_COLUMN.to_string());
+            }
+            tables.entry(table.to_string()).or_insert(known)
+        }
+    };
+
+    let mut table_columns = BTreeMap::new();
+    for (column, column_type) in columns.iter() {
+        let key = column_key(column);
+        if !known.contains_key(&key) {
+            connection.execute(
+                &format!(
+                    "ALTER TABLE {} ADD COLUMN {} {column_type}",
+                    quote(table),
+                    quote(column)
+                ),
+                [],
+            )?;
+            known.insert(key.clone(), column.clone());
+        }
+        table_columns.insert(column.clone(), known[&key].clone());
+    }
+    Ok(table_columns)
+}
+
+fn existing_columns(
+    connection: &Connection,
+    table: &str,
+) -> TuxedoResult<BTreeMap<String, String>> {
+    let mut statement = connection.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .map(|column| column.map(|column| (column_key(&column), column)))
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+    Ok(columns)
+}
+
+/// SQLite only folds the case of ASCII letters in identifiers
+fn column_key(column: &str) -> String {
+    column.to_ascii_lowercase()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Flattens a document into dotted columns
+fn flatten(document: &Document, prefix: Option<&str>, row: &mut BTreeMap<String, Value>) {
+    for (key, value) in document.iter() {
+        let column = match prefix {
+            Some(prefix) => format!("{prefix}.{key}"),
+            None => key.clone(),
+        };
+        match value {
+            Bson::Document(document) => flatten(document, Some(&column), row),
+            // A dotted key and a nested document can name the same column, the first
+            // non-null value is kept
+            value => match row.get(&column) {
+                Some(existing) if *existing != Value::Null => {}
+                _ => {
+                    row.insert(column, sql_value(value));
+                }
+            },
+        }
+    }
+}
+
+fn sql_value(value: &Bson) -> Value {
+    match value {
+        Bson::Null | Bson::Undefined => Value::Null,
+        Bson::Boolean(value) => Value::Integer(*value as i64),
+        Bson::Int32(value) => Value::Integer(*value as i64),
+        Bson::Int64(value) => Value::Integer(*value),
+        Bson::Double(value) => Value::Real(*value),
+        Bson::String(value) | Bson::Symbol(value) => Value::Text(value.clone()),
+        Bson::ObjectId(id) => Value::Text(id.to_hex()),
+        Bson::DateTime(date) => match date.try_to_rfc3339_string() {
+            Ok(date) => Value::Text(date),
+            Err(_) => Value::Integer(date.timestamp_millis()),
+        },
+        Bson::Decimal128(value) => Value::Text(value.to_string()),
+        Bson::Binary(binary) => Value::Blob(binary.bytes.clone()),
+        // Arrays and the remaining types are kept as Extended JSON
+        value => Value::Text(value.clone().into_relaxed_extjson().to_string()),
+    }
+}
+
+/// SQL type of a column holding `value`, `None` for null
+fn column_type(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Null => None,
+        Value::Integer(_) => Some("INTEGER"),
+        Value::Real(_) => Some("REAL"),
+        Value::Text(_) => Some("TEXT"),
+        Value::Blob(_) => Some("BLOB"),
+    }
+}
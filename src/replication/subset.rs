@@ -0,0 +1,341 @@
+use super::types::{DatabasePair, DEFAULT_DATABASE};
+use crate::paths::{bson_key, values_at_path};
+use crate::{TuxedoError, TuxedoResult};
+use bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tracing::info;
+
+/// Maximum number of values sent in a single `$in` query
+const IN_QUERY_CHUNK_SIZE: usize = 10_000;
+
+/// A foreign key from `from_collection.from_field` to `to_collection.to_field`,
+/// e.g. `orders.account_id -> accounts._id`
+#[derive(Debug, Clone)]
+pub struct Relationship {
+    pub from_collection: String,
+    pub from_field: String,
+    pub to_collection: String,
+    pub to_field: String,
+}
+
+/// Describes a referentially consistent subset of a database: a root selection
+/// and the relationships used to pull in related documents.
+#[derive(Debug, Clone)]
+pub struct SubsetConfig {
+    database: Option<String>,
+    root_collection: String,
+    root_query: Document,
+    root_limit: Option<u64>,
+    root_percent: Option<f64>,
+    relationships: Vec<Relationship>,
+}
+
+impl SubsetConfig {
+    pub fn builder(root_collection: impl Into<String>) -> SubsetConfigBuilder {
+        SubsetConfigBuilder::new(root_collection)
+    }
+
+    pub(crate) fn database_name(&self) -> &str {
+        self.database.as_deref().unwrap_or(DEFAULT_DATABASE)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubsetConfigBuilder {
+    config: SubsetConfig,
+}
+
+impl SubsetConfigBuilder {
+    pub fn new(root_collection: impl Into<String>) -> Self {
+        Self {
+            config: SubsetConfig {
+                database: None,
+                root_collection: root_collection.into(),
+                root_query: Document::new(),
+                root_limit: None,
+                root_percent: None,
+                relationships: Vec::new(),
+            },
+        }
+    }
+
+    /// Filter selecting the candidate root documents
+    pub fn query(mut self, query: impl Into<Document>) -> Self {
+        self.config.root_query = query.into();
+        self
+    }
+
+    /// Randomly selects at most `limit` root documents
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.config.root_limit = Some(limit);
+        self
+    }
+
+    /// Randomly selects `percent` (0-100) of the root documents matching the query
+    pub fn percent(mut self, percent: f64) -> Self {
+        self.config.root_percent = Some(percent);
+        self
+    }
+
+    /// Declares that `from_collection.from_field` references `to_collection.to_field`
+    pub fn relationship(
+        mut self,
+        from_collection: impl Into<String>,
+        from_field: impl Into<String>,
+        to_collection: impl Into<String>,
+        to_field: impl Into<String>,
+    ) -> Self {
+        self.config.relationships.push(Relationship {
+            from_collection: from_collection.into(),
+            from_field: from_field.into(),
+            to_collection: to_collection.into(),
+            to_field: to_field.into(),
+        });
+        self
+    }
+
+    /// Computes the subset within a database mapping registered with
+    /// `ReplicationManagerBuilder::add_database`
+    pub fn database(mut self, name: impl Into<String>) -> Self {
+        self.config.database = Some(name.into());
+        self
+    }
+
+    pub fn build(self) -> SubsetConfig {
+        self.config
+    }
+}
+
+/// `_id`s selected for a collection, deduplicated by their BSON representation
+#[derive(Debug, Default)]
+struct IdSet {
+    keys: HashSet<Vec<u8>>,
+    ids: Vec<Bson>,
+}
+
+impl IdSet {
+    /// Adds the ids, returning the ones which were not part of the set yet
+    fn extend(&mut self, ids: impl IntoIterator<Item = Bson>) -> Vec<Bson> {
+        let mut added = Vec::new();
+        for id in ids {
+            if self.keys.insert(bson_key(&id)) {
+                self.ids.push(id.clone());
+                added.push(id);
+            }
+        }
+        added
+    }
+}
+
+/// Computes the `_id`s of every collection taking part in the subset.
+///
+/// Starting from the root selection, documents referencing an included document
+/// (children) are followed downwards, and documents referenced by an included
+/// document (parents) are always pulled in so no reference dangles. Parents only
+/// pulled in to satisfy a reference do not pull in their own children, which keeps
+/// the subset from growing into the whole database.
+pub(crate) async fn compute_subset(
+    dbs: &DatabasePair,
+    config: &SubsetConfig,
+) -> TuxedoResult<HashMap<String, Vec<Bson>>> {
+    let root_ids = select_root_ids(dbs, config).await?;
+
+    // Every related collection is part of the subset, even when no document of it is
+    // reached, so its processor copies nothing instead of the whole collection
+    let mut included: HashMap<String, IdSet> = config
+        .relationships
+        .iter()
+        .flat_map(|relationship| {
+            [
+                relationship.from_collection.clone(),
+                relationship.to_collection.clone(),
+            ]
+        })
+        .map(|collection| (collection, IdSet::default()))
+        .collect();
+    // Documents whose children have been (or will be) followed
+    let mut expanded: HashMap<String, IdSet> = HashMap::new();
+    let mut queue: VecDeque<(String, Vec<Bson>, bool)> = VecDeque::new();
+
+    let added = included
+        .entry(config.root_collection.clone())
+        .or_default()
+        .extend(root_ids);
+    queue.push_back((config.root_collection.clone(), added, true));
+
+    while let Some((collection, ids, follow_children)) = queue.pop_front() {
+        if ids.is_empty() {
+            continue;
+        }
+
+        let ids = if follow_children {
+            expanded.entry(collection.clone()).or_default().extend(ids)
+        } else {
+            ids
+        };
+
+        for relationship in config.relationships.iter() {
+            // Parents referenced by the new documents
+            if relationship.from_collection == collection {
+                let references =
+                    field_values(dbs, &collection, &ids, &relationship.from_field).await?;
+                let parent_ids = ids_matching(
+                    dbs,
+                    &relationship.to_collection,
+                    &relationship.to_field,
+                    references,
+                )
+                .await?;
+                let added = included
+                    .entry(relationship.to_collection.clone())
+                    .or_default()
+                    .extend(parent_ids);
+                queue.push_back((relationship.to_collection.clone(), added, false));
+            }
+
+            // Children referencing the new documents
+            if follow_children && relationship.to_collection == collection {
+                let keys = field_values(dbs, &collection, &ids, &relationship.to_field).await?;
+                let child_ids = ids_matching(
+                    dbs,
+                    &relationship.from_collection,
+                    &relationship.from_field,
+                    keys,
+                )
+                .await?;
+
+                let child_set = included
+                    .entry(relationship.from_collection.clone())
+                    .or_default();
+                child_set.extend(child_ids.iter().cloned());
+                // Children already included as parents still need their own children followed
+                queue.push_back((relationship.from_collection.clone(), child_ids, true));
+            }
+        }
+    }
+
+    let subset: HashMap<String, Vec<Bson>> = included
+        .into_iter()
+        .map(|(collection, ids)| (collection, ids.ids))
+        .collect();
+
+    for (collection, ids) in subset.iter() {
+        info!(collection = %collection, documents = ids.len(), "Computed subset");
+    }
+
+    Ok(subset)
+}
+
+async fn select_root_ids(dbs: &DatabasePair, config: &SubsetConfig) -> TuxedoResult<Vec<Bson>> {
+    let sample_size = match (config.root_limit, config.root_percent) {
+        (Some(limit), _) => Some(limit),
+        (None, Some(percent)) => {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(TuxedoError::ConfigError(format!(
+                    "Subset percentage must be between 0 and 100, got {percent}"
+                )));
+            }
+            let total = dbs
+                .read_total_documents(&config.root_collection, config.root_query.clone())
+                .await?;
+            Some(((total as f64) * percent / 100.0).ceil() as u64)
+        }
+        (None, None) => None,
+    };
+
+    let mut pipeline = vec![doc! { "$match": config.root_query.clone() }];
+    if let Some(sample_size) = sample_size {
+        pipeline.push(doc! { "$sample": { "size": sample_size as i64 } });
+    }
+    pipeline.push(doc! { "$project": { "_id": 1 } });
+
+    let mut cursor = dbs
+        .aggregate_documents(&config.root_collection, pipeline)
+        .await?;
+
+    let mut ids = Vec::new();
+    while cursor.advance().await? {
+        let document: Document = cursor.deserialize_current()?;
+        if let Some(id) = document.get("_id") {
+            ids.push(id.clone());
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Values of `field` for the documents with the given `_id`s
+async fn field_values(
+    dbs: &DatabasePair,
+    collection_name: &str,
+    ids: &[Bson],
+    field: &str,
+) -> TuxedoResult<Vec<Bson>> {
+    if field == "_id" {
+        return Ok(ids.to_vec());
+    }
+
+    let mut values = Vec::new();
+    for chunk in ids.chunks(IN_QUERY_CHUNK_SIZE) {
+        let read_options = FindOptions::builder().projection(doc! { field: 1 }).build();
+        let mut cursor = dbs
+            .read_documents(
+                collection_name,
+                doc! { "_id": { "$in": chunk.to_vec() } },
+                Some(read_options),
+            )
+            .await?;
+
+        while cursor.advance().await? {
+            let document: Document = cursor.deserialize_current()?;
+            values.extend(values_at_path(&document, field).into_iter().cloned());
+        }
+    }
+
+    let mut unique = IdSet::default();
+    unique.extend(values.into_iter().filter(|v| !matches!(v, Bson::Null)));
+    Ok(unique.ids)
+}
+
+/// `_id`s of the documents whose `field` matches any of `values`. Always queried,
+/// even for `_id`, so references to missing documents are not included.
+async fn ids_matching(
+    dbs: &DatabasePair,
+    collection_name: &str,
+    field: &str,
+    values: Vec<Bson>,
+) -> TuxedoResult<Vec<Bson>> {
+    let mut ids = Vec::new();
+    for chunk in values.chunks(IN_QUERY_CHUNK_SIZE) {
+        ids.extend(
+            find_ids(
+                dbs,
+                collection_name,
+                doc! { field: { "$in": chunk.to_vec() } },
+            )
+            .await?,
+        );
+    }
+    Ok(ids)
+}
+
+async fn find_ids(
+    dbs: &DatabasePair,
+    collection_name: &str,
+    query: Document,
+) -> TuxedoResult<Vec<Bson>> {
+    let read_options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+    let mut cursor = dbs
+        .read_documents(collection_name, query, Some(read_options))
+        .await?;
+
+    let mut ids = Vec::new();
+    while cursor.advance().await? {
+        let document: Document = cursor.deserialize_current()?;
+        if let Some(id) = document.get("_id") {
+            ids.push(id.clone());
+        }
+    }
+    Ok(ids)
+}
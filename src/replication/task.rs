@@ -1,31 +1,31 @@
+use super::pipeline::aggregate_options;
+use super::processor::MaskingLambda;
+use super::report::{CollectionStats, ErrorStage};
 use super::types::{DatabasePair, ReplicationStrategy};
 use crate::Mask;
 use async_trait::async_trait;
 use bson::Document;
-use indicatif::ProgressBar;
 use mongodb::options::{FindOptions, InsertManyOptions};
 use serde::{de::DeserializeOwned, Serialize};
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, debug_span, error, warn, Instrument, Span};
 
 #[async_trait]
 pub(crate) trait Task: Send + Sync {
     async fn run(&self);
-    fn update_progress_bar(&self, progress_bar: &ProgressBar, num_records: usize) {
-        progress_bar.inc(num_records as u64);
-        if progress_bar.is_finished() {
-            progress_bar.finish_and_clear();
-            progress_bar.set_message("Complete");
-        }
-    }
+    /// Span the task runs in, created by its processor so it nests under the collection
+    fn span(&self) -> Span;
 }
 
 #[derive(Debug)]
 pub(crate) struct ModelTask<T: Mask + Serialize + DeserializeOwned + Send + Sync + 'static> {
     dbs: Arc<DatabasePair>,
     collection_name: String,
+    target_collection_name: String,
     config: TaskConfig,
-    progress_bar: Arc<ProgressBar>,
+    stats: Arc<CollectionStats>,
     strategy: ReplicationStrategy,
     _phantom_data: PhantomData<T>,
 }
@@ -33,34 +33,40 @@ pub(crate) struct ModelTask<T: Mask + Serialize + DeserializeOwned + Send + Sync
 pub(crate) struct ReplicatorTask<T: Send> {
     dbs: Arc<DatabasePair>,
     collection_name: String,
+    target_collection_name: String,
     config: TaskConfig,
-    masking_lambda: Option<Arc<dyn Fn(&mut Document) + Send + Sync>>,
-    progress_bar: Arc<ProgressBar>,
+    masking_lambda: Option<MaskingLambda>,
+    stats: Arc<CollectionStats>,
     _phantom_data: PhantomData<T>,
 }
 
 #[derive(Debug)]
 pub(crate) struct TaskConfig {
     pub(crate) query: Document,
+    /// Aggregation run instead of a `find` with `query`, already partitioned
+    pub(crate) pipeline: Option<Vec<Document>>,
     pub(crate) write_batch_size: u64,
     pub(crate) read_options: FindOptions,
     pub(crate) write_options: InsertManyOptions,
+    pub(crate) span: Span,
 }
 
 impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + 'static> ModelTask<T> {
     pub(crate) fn new(
         dbs: Arc<DatabasePair>,
         collection_name: impl Into<String>,
+        target_collection_name: impl Into<String>,
         config: TaskConfig,
         strategy: ReplicationStrategy,
-        progress_bar: Arc<ProgressBar>,
+        stats: Arc<CollectionStats>,
     ) -> Self {
         Self {
             dbs,
             collection_name: collection_name.into(),
+            target_collection_name: target_collection_name.into(),
             config,
             strategy,
-            progress_bar,
+            stats,
             _phantom_data: PhantomData,
         }
     }
@@ -70,16 +76,18 @@ impl<T: Send> ReplicatorTask<T> {
     pub(crate) fn new(
         dbs: Arc<DatabasePair>,
         collection_name: impl Into<String>,
+        target_collection_name: impl Into<String>,
         config: TaskConfig,
-        masking_lambda: Option<Arc<dyn Fn(&mut Document) + Send + Sync>>,
-        progress_bar: Arc<ProgressBar>,
+        masking_lambda: Option<MaskingLambda>,
+        stats: Arc<CollectionStats>,
     ) -> Self {
         Self {
             dbs,
             collection_name: collection_name.into(),
+            target_collection_name: target_collection_name.into(),
             config,
             masking_lambda,
-            progress_bar,
+            stats,
             _phantom_data: PhantomData,
         }
     }
@@ -87,25 +95,46 @@ impl<T: Send> ReplicatorTask<T> {
 
 #[async_trait]
 impl<T: Send + Sync> Task for ReplicatorTask<T> {
+    fn span(&self) -> Span {
+        self.config.span.clone()
+    }
+
     async fn run(&self) {
         // Get the cursor
-        let mut cursor = match self
-            .dbs
-            .read_documents(
-                &self.collection_name,
-                self.config.query.clone(),
-                self.config.read_options.clone().into(),
-            )
-            .await
-        {
+        let cursor = match self.config.pipeline.as_ref() {
+            Some(pipeline) => {
+                self.dbs
+                    .aggregate(
+                        &self.collection_name,
+                        pipeline.clone(),
+                        aggregate_options(&self.config.read_options).into(),
+                    )
+                    .await
+            }
+            None => {
+                self.dbs
+                    .read_documents(
+                        &self.collection_name,
+                        self.config.query.clone(),
+                        self.config.read_options.clone().into(),
+                    )
+                    .await
+            }
+        };
+        let mut cursor = match cursor {
             Ok(cursor) => cursor,
             Err(e) => {
-                println!(
-                    "Failed to retrieve cursor for collection: `{}` using Query: {:?} with read options: {:?}. Encountered error: {}",
-                    &self.collection_name,
-                    &self.config.query,
-                    &self.config.read_options,
-                    e
+                error!(
+                    collection = %self.collection_name,
+                    query = ?self.config.query,
+                    pipeline = ?self.config.pipeline,
+                    read_options = ?self.config.read_options,
+                    error = %e,
+                    "Failed to retrieve source cursor"
+                );
+                self.stats.add_error(
+                    ErrorStage::Read,
+                    format!("Failed to retrieve source cursor: {e}"),
                 );
                 return;
             }
@@ -120,22 +149,31 @@ impl<T: Send + Sync> Task for ReplicatorTask<T> {
             Ok(true) => true,   // Advanced successfully, okay to deserialize
             Ok(false) => false, // End of cursor
             Err(e) => {
-                println!(
-                    "Error advancing cursor for collection: `{}`. Stopping task. Error: {}",
-                    &self.collection_name, e
+                error!(
+                    collection = %self.collection_name,
+                    error = %e,
+                    "Error advancing cursor, stopping task"
+                );
+                self.stats.add_error(
+                    ErrorStage::Read,
+                    format!("Error advancing source cursor: {e}"),
                 );
                 false // Stop processing loop
             }
         } {
+            self.stats.add_read(cursor.current_size());
+
             // If advance returned Ok(true), we can deserialize the current document
             // Deserialize the current document using the faster method
-            let mut doc = match cursor.deserialize_current() {
+            let mut doc = match cursor.deserialize_current::<Document>() {
                 Ok(d) => d,
                 Err(e) => {
-                    println!(
-                        "Failed to deserialize document for collection: `{}`. Skipping document. Error: {}",
-                        &self.collection_name, e
+                    warn!(
+                        collection = %self.collection_name,
+                        error = %e,
+                        "Failed to deserialize document, skipping document"
                     );
+                    self.stats.add_failed(1);
                     continue; // Skip this document
                 }
             };
@@ -143,6 +181,7 @@ impl<T: Send + Sync> Task for ReplicatorTask<T> {
             // Apply masking if lambda exists
             if let Some(masking_fn) = self.masking_lambda.as_ref() {
                 (masking_fn)(&mut doc);
+                self.stats.add_masked(1);
             }
 
             write_batch.push(doc);
@@ -150,24 +189,37 @@ impl<T: Send + Sync> Task for ReplicatorTask<T> {
 
             // Write in batches
             if write_batch.len() >= self.config.write_batch_size as usize {
-                if let Err(e) = self
+                let started_at = Instant::now();
+                match self
                     .dbs
                     .write::<Document>(
-                        &self.collection_name,
+                        &self.target_collection_name,
                         &write_batch,
                         self.config.write_options.clone().into(),
                     )
+                    .instrument(batch_span(total_processed, write_batch.len()))
                     .await
                 {
-                    println!(
-                        "Failed to insert batch of {} records into collection: `{}`. Error: {}",
-                        write_batch.len(),
-                        &self.collection_name,
-                        e
-                    );
-                    // Decide how to handle batch write errors
-                } else {
-                    self.update_progress_bar(&self.progress_bar, write_batch.len());
+                    Err(e) => {
+                        error!(
+                            target_collection = %self.target_collection_name,
+                            documents = write_batch.len(),
+                            error = %e,
+                            "Failed to insert batch"
+                        );
+                        self.stats.add_failed(write_batch.len() as u64);
+                        self.stats
+                            .add_error(ErrorStage::Write, format!("Failed to insert batch: {e}"));
+                        // Decide how to handle batch write errors
+                    }
+                    Ok(size) => {
+                        debug!(documents = write_batch.len(), "Wrote batch");
+                        self.stats.add_written_batch(
+                            write_batch.len() as u64,
+                            size,
+                            started_at.elapsed(),
+                        );
+                    }
                 }
                 write_batch.clear();
             }
@@ -175,30 +227,46 @@ impl<T: Send + Sync> Task for ReplicatorTask<T> {
 
         // Write any remaining documents
         if !write_batch.is_empty() {
-            if let Err(e) = self
+            let started_at = Instant::now();
+            match self
                 .dbs
                 .write::<Document>(
-                    &self.collection_name,
+                    &self.target_collection_name,
                     &write_batch,
                     self.config.write_options.clone().into(),
                 )
+                .instrument(batch_span(total_processed, write_batch.len()))
                 .await
             {
-                println!(
-                    "Failed to insert final batch of {} records into collection: `{}`. Error: {}",
-                    write_batch.len(),
-                    &self.collection_name,
-                    e
-                );
-            } else {
-                self.update_progress_bar(&self.progress_bar, write_batch.len());
+                Err(e) => {
+                    error!(
+                        target_collection = %self.target_collection_name,
+                        documents = write_batch.len(),
+                        error = %e,
+                        "Failed to insert final batch"
+                    );
+                    self.stats.add_failed(write_batch.len() as u64);
+                    self.stats.add_error(
+                        ErrorStage::Write,
+                        format!("Failed to insert final batch: {e}"),
+                    );
+                }
+                Ok(size) => {
+                    debug!(documents = write_batch.len(), "Wrote batch");
+                    self.stats.add_written_batch(
+                        write_batch.len() as u64,
+                        size,
+                        started_at.elapsed(),
+                    );
+                }
             }
         }
 
         if total_processed == 0 {
-            println!(
-                "No records found or processed for batch. Query: {:?} with read options: {:?}",
-                &self.config.query, &self.config.read_options,
+            debug!(
+                query = ?self.config.query,
+                read_options = ?self.config.read_options,
+                "No records found or processed for batch"
             );
         }
     }
@@ -206,26 +274,46 @@ impl<T: Send + Sync> Task for ReplicatorTask<T> {
 
 #[async_trait]
 impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> Task for ModelTask<T> {
+    fn span(&self) -> Span {
+        self.config.span.clone()
+    }
+
     async fn run(&self) {
         // Get the cursor
-        let mut cursor = match self
-            .dbs
-            .read::<T>(
-                // Use the typed read method
-                &self.collection_name,
-                self.config.query.clone(),
-                self.config.read_options.clone().into(),
-            )
-            .await
-        {
+        let cursor = match self.config.pipeline.as_ref() {
+            Some(pipeline) => {
+                self.dbs
+                    .aggregate(
+                        &self.collection_name,
+                        pipeline.clone(),
+                        aggregate_options(&self.config.read_options).into(),
+                    )
+                    .await
+            }
+            None => {
+                self.dbs
+                    .read_documents(
+                        &self.collection_name,
+                        self.config.query.clone(),
+                        self.config.read_options.clone().into(),
+                    )
+                    .await
+            }
+        };
+        let mut cursor = match cursor {
             Ok(cursor) => cursor,
             Err(e) => {
-                println!(
-                    "Failed to retrieve cursor for collection: `{}` using Query: {:?} with read options: {:?}. Encountered error: {}",
-                    &self.collection_name,
-                    &self.config.query,
-                    &self.config.read_options,
-                    e
+                error!(
+                    collection = %self.collection_name,
+                    query = ?self.config.query,
+                    pipeline = ?self.config.pipeline,
+                    read_options = ?self.config.read_options,
+                    error = %e,
+                    "Failed to retrieve source cursor"
+                );
+                self.stats.add_error(
+                    ErrorStage::Read,
+                    format!("Failed to retrieve source cursor: {e}"),
                 );
                 return;
             }
@@ -240,22 +328,31 @@ impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> Task for Mode
             Ok(true) => true,   // Advanced successfully, okay to deserialize
             Ok(false) => false, // End of cursor
             Err(e) => {
-                println!(
-                    "Error advancing cursor for collection: `{}`. Stopping task. Error: {}",
-                    &self.collection_name, e
+                error!(
+                    collection = %self.collection_name,
+                    error = %e,
+                    "Error advancing cursor, stopping task"
+                );
+                self.stats.add_error(
+                    ErrorStage::Read,
+                    format!("Error advancing source cursor: {e}"),
                 );
                 false // Stop processing loop
             }
         } {
+            self.stats.add_read(cursor.current_size());
+
             // If advance returned Ok(true), we can deserialize the current document
             // Deserialize the current document using the faster method
-            let mut record = match cursor.deserialize_current() {
+            let mut record = match cursor.deserialize_current::<T>() {
                 Ok(d) => d,
                 Err(e) => {
-                    println!(
-                        "Failed to deserialize document for collection: `{}`. Skipping document. Error: {}",
-                        &self.collection_name, e
+                    warn!(
+                        collection = %self.collection_name,
+                        error = %e,
+                        "Failed to deserialize document, skipping document"
                     );
+                    self.stats.add_failed(1);
                     continue; // Skip this document
                 }
             };
@@ -263,6 +360,7 @@ impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> Task for Mode
             // Apply masking if strategy requires it
             if use_masking {
                 record.mask();
+                self.stats.add_masked(1);
             }
 
             write_batch.push(record);
@@ -270,25 +368,38 @@ impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> Task for Mode
 
             // Write in batches
             if write_batch.len() >= self.config.write_batch_size as usize {
-                if let Err(e) = self
+                let started_at = Instant::now();
+                match self
                     .dbs
                     .write::<T>(
-                        &self.collection_name,
+                        &self.target_collection_name,
                         &write_batch,
                         self.config.write_options.clone().into(),
                     )
+                    .instrument(batch_span(total_processed, write_batch.len()))
                     .await
                 {
-                    println!(
-                        "Failed to insert batch of {} records into collection: `{}`. Error: {}",
-                        write_batch.len(),
-                        &self.collection_name,
-                        e
-                    );
-                    // TODO; Decide how to handle batch write errors
-                    // For now just keep going
-                } else {
-                    self.update_progress_bar(&self.progress_bar, write_batch.len());
+                    Err(e) => {
+                        error!(
+                            target_collection = %self.target_collection_name,
+                            documents = write_batch.len(),
+                            error = %e,
+                            "Failed to insert batch"
+                        );
+                        self.stats.add_failed(write_batch.len() as u64);
+                        self.stats
+                            .add_error(ErrorStage::Write, format!("Failed to insert batch: {e}"));
+                        // TODO; Decide how to handle batch write errors
+                        // For now just keep going
+                    }
+                    Ok(size) => {
+                        debug!(documents = write_batch.len(), "Wrote batch");
+                        self.stats.add_written_batch(
+                            write_batch.len() as u64,
+                            size,
+                            started_at.elapsed(),
+                        );
+                    }
                 }
                 write_batch.clear();
             }
@@ -296,31 +407,57 @@ impl<T: Mask + Serialize + DeserializeOwned + Send + Sync + Unpin> Task for Mode
 
         // Write any remaining documents
         if !write_batch.is_empty() {
-            if let Err(e) = self
+            let started_at = Instant::now();
+            match self
                 .dbs
                 .write::<T>(
-                    &self.collection_name,
+                    &self.target_collection_name,
                     &write_batch,
                     self.config.write_options.clone().into(),
                 )
+                .instrument(batch_span(total_processed, write_batch.len()))
                 .await
             {
-                println!(
-                    "Failed to insert final batch of {} records into collection: `{}`. Error: {}",
-                    write_batch.len(),
-                    &self.collection_name,
-                    e
-                );
-            } else {
-                self.update_progress_bar(&self.progress_bar, write_batch.len());
+                Err(e) => {
+                    error!(
+                        target_collection = %self.target_collection_name,
+                        documents = write_batch.len(),
+                        error = %e,
+                        "Failed to insert final batch"
+                    );
+                    self.stats.add_failed(write_batch.len() as u64);
+                    self.stats.add_error(
+                        ErrorStage::Write,
+                        format!("Failed to insert final batch: {e}"),
+                    );
+                }
+                Ok(size) => {
+                    debug!(documents = write_batch.len(), "Wrote batch");
+                    self.stats.add_written_batch(
+                        write_batch.len() as u64,
+                        size,
+                        started_at.elapsed(),
+                    );
+                }
             }
         }
 
         if total_processed == 0 {
-            println!(
-                "No records found or processed for batch. Query: {:?} with read options: {:?}",
-                &self.config.query, &self.config.read_options,
+            debug!(
+                query = ?self.config.query,
+                read_options = ?self.config.read_options,
+                "No records found or processed for batch"
             );
         }
     }
 }
+
+/// Span of a single write, `written` documents of the task precede the batch
+fn batch_span(written: usize, documents: usize) -> Span {
+    debug_span!(
+        "batch",
+        first = written - documents,
+        last = written.saturating_sub(1),
+        documents
+    )
+}
@@ -1,121 +1,244 @@
+use super::indexes::{
+    index_name, is_implicit_index, prepare_index, IndexCopyOptions, IndexCopyResult, ServerVersion,
+};
+use super::schema::CollectionValidator;
+use super::sink::Sink;
+use super::source::{DocumentCursor, Source};
+use super::views::rewrite_pipeline_collections;
 use crate::{TuxedoError, TuxedoResult};
 use bson::{doc, Document};
-use futures_util::TryStreamExt;
-use mongodb::options::{FindOptions, InsertManyOptions};
-use mongodb::Cursor;
-use mongodb::{Database, IndexModel};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-
+use mongodb::options::{AggregateOptions, CreateCollectionOptions, FindOptions, InsertManyOptions};
+use mongodb::results::CollectionSpecification;
+use mongodb::IndexModel;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use tracing::{debug, info, warn};
+
+/// Name of the database mapping built from `source_db` / `target_db` (or the URIs)
+pub(crate) const DEFAULT_DATABASE: &str = "default";
+
+/// Source and sink of one database mapping. They are trait objects rather than type
+/// parameters of `Processor` and `Task`, so a single manager can mix MongoDB, file
+/// and custom backends across its mappings while processors stay object safe;
+/// MongoDB remains the default when no source or target is given.
 #[derive(Debug)]
 pub(crate) struct DatabasePair {
-    source: Database,
-    target: Database,
+    source: Arc<dyn Source>,
+    target: Arc<dyn Sink>,
+    target_version: OnceCell<Option<ServerVersion>>,
 }
 
 impl DatabasePair {
-    pub(crate) fn new(source: Database, target: Database) -> Self {
-        Self { source, target }
+    pub(crate) fn new(source: Arc<dyn Source>, target: Arc<dyn Sink>) -> Self {
+        Self {
+            source,
+            target,
+            target_version: OnceCell::new(),
+        }
     }
 
-    pub(crate) async fn read<T: Serialize + DeserializeOwned + Unpin + Send + Sync>(
+    pub(crate) async fn get_average_document_size(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<u64> {
+        self.source.average_document_size(collection_name).await
+    }
+
+    pub(crate) async fn read_documents(
         &self,
         collection_name: &str,
         query: Document,
         options: Option<FindOptions>,
-    ) -> TuxedoResult<Cursor<T>> {
-        Ok(self
-            .source
-            .collection::<T>(collection_name)
-            .find(query)
-            .with_options(options)
-            .await?)
+    ) -> TuxedoResult<DocumentCursor> {
+        self.source.find(collection_name, query, options).await
     }
 
-    pub(crate) async fn get_average_document_size(
+    pub(crate) async fn aggregate_documents(
         &self,
         collection_name: &str,
-    ) -> TuxedoResult<u64> {
-        let stats = self
-            .source
-            .run_command(doc! { "collStats": collection_name })
-            .await?;
-
-        let avg_doc_size = stats.get_f64("avgObjSize").unwrap_or(1024.0);
-        Ok(avg_doc_size as u64)
+        pipeline: Vec<Document>,
+    ) -> TuxedoResult<DocumentCursor> {
+        self.aggregate(collection_name, pipeline, None).await
     }
 
-    pub(crate) async fn read_documents(
+    /// Runs an aggregation on the source
+    pub(crate) async fn aggregate(
         &self,
         collection_name: &str,
-        query: Document,
-        options: Option<FindOptions>,
-    ) -> TuxedoResult<Cursor<Document>> {
-        Ok(self
-            .source
-            .collection::<Document>(collection_name)
-            .find(query)
-            .with_options(options)
-            .await?)
+        pipeline: Vec<Document>,
+        options: Option<AggregateOptions>,
+    ) -> TuxedoResult<DocumentCursor> {
+        self.source
+            .aggregate(collection_name, pipeline, options)
+            .await
     }
 
-    pub(crate) async fn read_total_documents<T: Send + Sync>(
+    pub(crate) async fn read_total_documents(
         &self,
         collection_name: &str,
         query: Document,
     ) -> TuxedoResult<usize> {
-        let total_documents = self
-            .source
-            .collection::<T>(collection_name)
-            .count_documents(query)
-            .await? as usize;
+        let total_documents = self.source.count_documents(collection_name, query).await? as usize;
         Ok(total_documents)
     }
 
+    /// Names of the source collections, views excluded
+    pub(crate) async fn list_source_collections(&self) -> TuxedoResult<Vec<String>> {
+        self.source.list_collection_names().await
+    }
+
+    /// Whether collections of the source can be split over several tasks
+    pub(crate) fn supports_partitioned_reads(&self) -> bool {
+        self.source.supports_partitioned_reads()
+    }
+
+    /// Writes the records into a target collection, returning their size in bytes
     pub(crate) async fn write<T: Send + Sync + Serialize>(
         &self,
         collection_name: &str,
         records: &[T],
         options: Option<InsertManyOptions>,
-    ) -> TuxedoResult<()> {
+    ) -> TuxedoResult<u64> {
+        let documents = records
+            .iter()
+            .map(bson::to_raw_document_buf)
+            .collect::<Result<Vec<_>, _>>()?;
         self.target
-            .collection::<T>(collection_name)
-            .insert_many(records)
-            .with_options(options)
+            .write(collection_name, &documents, options)
             .await?;
-        Ok(())
+        Ok(documents
+            .iter()
+            .map(|document| document.as_bytes().len() as u64)
+            .sum())
+    }
+
+    /// Reads back documents written into a target collection, `None` when the
+    /// target can't be read
+    pub(crate) async fn read_target_documents(
+        &self,
+        collection_name: &str,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> TuxedoResult<Option<DocumentCursor>> {
+        self.target.find(collection_name, query, options).await
+    }
+
+    /// Counts the documents of a target collection, `None` when the target can't be read
+    pub(crate) async fn read_target_total_documents(
+        &self,
+        collection_name: &str,
+        query: Document,
+    ) -> TuxedoResult<Option<u64>> {
+        self.target.count_documents(collection_name, query).await
+    }
+
+    // Collection options
+
+    /// Reads the `list_collections` specification of a single source collection
+    pub(crate) async fn get_source_collection_specification(
+        &self,
+        collection_name: &str,
+    ) -> TuxedoResult<Option<CollectionSpecification>> {
+        self.source.collection_specification(collection_name).await
+    }
+
+    /// Explicitly creates a target collection with the given options
+    pub(crate) async fn create_target_collection(
+        &self,
+        collection_name: &str,
+        options: CreateCollectionOptions,
+    ) -> TuxedoResult<()> {
+        self.target
+            .create_collection(collection_name, options)
+            .await
+    }
+
+    /// Applies a validator to an existing target collection
+    pub(crate) async fn apply_target_validator(
+        &self,
+        collection_name: &str,
+        validator: &CollectionValidator,
+    ) -> TuxedoResult<()> {
+        self.target
+            .apply_validator(collection_name, validator)
+            .await
     }
 
     // Indexes
 
-    /// Copies the indexes from the source collection to the equivilant target collection
-    pub(crate) async fn copy_indexes(&self, collection_name: &str) -> TuxedoResult<()> {
-        let mut source_index_cursor = self
-            .source
-            .collection::<Document>(collection_name)
-            .list_indexes()
-            .await?;
+    /// Copies the indexes from the source collection to the equivilant target collection.
+    /// All indexes are first built together, if that fails each index is built on its
+    /// own so a single unsupported index does not prevent the others from being created.
+    pub(crate) async fn copy_indexes(
+        &self,
+        collection_name: &str,
+        target_collection_name: &str,
+        copy_options: &IndexCopyOptions,
+    ) -> TuxedoResult<IndexCopyResult> {
+        let source_indexes = self.source.list_indexes(collection_name).await?;
+
+        let target_version = if copy_options.strip_unsupported_options {
+            self.get_target_server_version().await
+        } else {
+            None
+        };
 
+        let mut result = IndexCopyResult::default();
         let mut indexes: Vec<IndexModel> = Vec::new();
-        while let Some(index) = source_index_cursor.try_next().await? {
-            // Skip the _id index as it's created automatically
-            if index.keys.get("_id").is_some() {
+        for index in source_indexes {
+            // Skip the _id (and clustered) index as it's created automatically
+            if is_implicit_index(&index) {
                 continue;
             }
 
-            indexes.push(index);
+            let name = index_name(&index);
+            match prepare_index(index, copy_options, target_version) {
+                Ok(index) => indexes.push(index),
+                Err(reason) => result.failed.push((name, reason)),
+            }
         }
 
         if indexes.is_empty() {
-            println!("  -> No indexes to copy for '{}'", collection_name);
-            return Ok(());
+            debug!(collection = collection_name, "No indexes to copy");
+            return Ok(result);
         }
 
-        self.target
-            .collection::<Document>(collection_name)
-            .create_indexes(indexes)
-            .await?;
+        let index_names: Vec<String> = indexes.iter().map(index_name).collect();
 
-        Ok(())
+        if self
+            .target
+            .create_indexes(target_collection_name, indexes.clone())
+            .await
+            .is_ok()
+        {
+            result.created.extend(index_names);
+            return Ok(result);
+        }
+
+        // Isolate the failing index(es)
+        for (index, name) in indexes.into_iter().zip(index_names) {
+            match self
+                .target
+                .create_indexes(target_collection_name, vec![index])
+                .await
+            {
+                Ok(_) => result.created.push(name),
+                Err(e) => result.failed.push((name, e.to_string())),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Version of the target server, used to strip unsupported index options.
+    /// Looked up once and cached for the lifetime of the pair.
+    async fn get_target_server_version(&self) -> Option<ServerVersion> {
+        *self
+            .target_version
+            .get_or_init(|| self.target.server_version())
+            .await
     }
 
     // Database Initialization (testing) functions
@@ -126,7 +249,6 @@ impl DatabasePair {
     ) -> TuxedoResult<()> {
         let target_collections = self.target.list_collection_names().await?;
 
-        println!("******************************");
         for collection_name in target_collections.into_iter() {
             // Skip system collections:
             // 1. Collections with system.* prefix
@@ -137,39 +259,28 @@ impl DatabasePair {
                 || collection_name.starts_with("admin.")
                 || collection_name.starts_with("config.")
             {
-                println!("Skipping system collection: {}", collection_name);
+                debug!(collection = %collection_name, "Skipping system collection");
                 continue;
             }
 
             // Only drop collections that are in our list (collections + views)
             if collection_names.contains(&collection_name) {
-                println!("Dropping collection/view: {}", collection_name);
-                self.target
-                    .collection::<mongodb::bson::Document>(&collection_name)
-                    .drop()
-                    .await?;
+                debug!(collection = %collection_name, "Dropping collection/view");
+                self.target.drop_collection(&collection_name).await?;
             } else {
-                println!("Skipping collection not in drop list: {}", collection_name);
+                debug!(collection = %collection_name, "Skipping collection not in drop list");
             }
         }
-        println!("******************************");
-        println!("Target database collections and views have been selectively dropped.\n\n");
+        info!("Target database collections and views have been selectively dropped");
         Ok(())
     }
 
     pub(crate) async fn test_database_collection_source(&self) -> TuxedoResult<()> {
-        self.test_database_connection(&self.source).await
+        self.source.test_connection().await
     }
 
     pub(crate) async fn test_database_collection_target(&self) -> TuxedoResult<()> {
-        self.test_database_connection(&self.target).await
-    }
-
-    async fn test_database_connection(&self, db: &Database) -> TuxedoResult<()> {
-        db.list_collection_names()
-            .await
-            .expect("Failed to list connections for DB");
-        Ok(())
+        self.target.test_connection().await
     }
 
     // Views
@@ -181,39 +292,43 @@ impl DatabasePair {
     }
 
     /// Lists all views in the source database for copying
-    pub(crate) async fn list_source_views(
-        &self,
-    ) -> TuxedoResult<Vec<mongodb::results::CollectionSpecification>> {
-        let filter = doc! { "type": "view" };
-        let mut cursor = self.source.list_collections().filter(filter).await?;
-
+    pub(crate) async fn list_source_views(&self) -> TuxedoResult<Vec<CollectionSpecification>> {
         let mut views = Vec::new();
-        while let Some(collection_spec) = cursor.try_next().await? {
+        for collection_spec in self.source.list_views().await? {
             // Verify this is actually a view with the required fields
             if collection_spec.options.view_on.is_some()
                 && collection_spec.options.pipeline.is_some()
             {
                 views.push(collection_spec);
             } else {
-                println!(
-                    "Warning: Collection '{}' marked as view but missing view fields",
-                    collection_spec.name
+                warn!(
+                    view = %collection_spec.name,
+                    "Collection marked as view but missing view fields"
                 );
             }
         }
 
         if !views.is_empty() {
-            println!("Found {} views to copy", views.len());
+            info!(views = views.len(), "Found views to copy");
         }
 
         Ok(views)
     }
 
+    /// Lists the collections and views currently present in the target database
+    pub(crate) async fn list_target_collection_names(&self) -> TuxedoResult<Vec<String>> {
+        self.target.list_collection_names().await
+    }
+
     /// Copies a single view from source to target (used by manager tasks)
     /// Note: Target views are cleared at startup, so no existence check needed
+    ///
+    /// Collection references in `viewOn` and the pipeline are rewritten using the
+    /// source -> target `collection_mapping` so views follow renamed collections.
     pub(crate) async fn copy_single_view(
         &self,
-        view_spec: &mongodb::results::CollectionSpecification,
+        view_spec: &CollectionSpecification,
+        collection_mapping: &HashMap<String, String>,
     ) -> TuxedoResult<()> {
         // Extract view information from the CollectionSpecification
         let view_on = view_spec.options.view_on.as_ref().ok_or_else(|| {
@@ -224,15 +339,17 @@ impl DatabasePair {
             TuxedoError::Generic(format!("View '{}' missing pipeline", view_spec.name))
         })?;
 
-        // Create the view using the createView command
-        let create_view_command = doc! {
-            "create": &view_spec.name,
-            "viewOn": view_on,
-            "pipeline": pipeline,
-        };
+        let view_on = collection_mapping.get(view_on).unwrap_or(view_on);
+        let pipeline = rewrite_pipeline_collections(pipeline, collection_mapping);
 
-        self.target.run_command(create_view_command).await?;
-        Ok(())
+        self.target
+            .create_view(&view_spec.name, view_on, pipeline)
+            .await
+    }
+
+    /// Flushes the target once everything has been written
+    pub(crate) async fn finish_target(&self) -> TuxedoResult<()> {
+        self.target.finish().await
     }
 }
 
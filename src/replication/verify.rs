@@ -0,0 +1,193 @@
+use super::filter::compare_ids;
+use super::processor::{restrict_to_ids, ID_CHUNK_SIZE};
+use super::report::VerificationReport;
+use super::source::DocumentCursor;
+use super::types::DatabasePair;
+use crate::{TuxedoError, TuxedoResult};
+use bson::{doc, Bson, Document};
+use md5::{Digest, Md5};
+use mongodb::options::FindOptions;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+/// Turns a source document into the document the processor is expected to have
+/// written, when its content is not masked
+pub(crate) type ExpectedDocument<'a> = &'a (dyn Fn(Document) -> TuxedoResult<Document> + Sync);
+
+/// What to compare a processor's source documents with on the target
+pub(crate) struct Verification<'a> {
+    pub(crate) source_collection: &'a str,
+    pub(crate) target_collection: &'a str,
+    pub(crate) query: &'a Document,
+    /// `_id`s the processor was restricted to by a subset or sample
+    pub(crate) ids: Option<&'a [Bson]>,
+    /// Compare the counts, only meaningful when no other processor writes the target
+    pub(crate) compare_count: bool,
+    /// Compare the content of the documents, `None` when they are masked
+    pub(crate) expected: Option<ExpectedDocument<'a>>,
+}
+
+/// Checks every source document of a processor exists on the target by merging both
+/// sides ordered by `_id`, comparing their hashes when the content is expected to be
+/// unchanged. Documents of the target which are not in the source are ignored, only
+/// the count catches them.
+pub(crate) async fn verify_collection(
+    dbs: &DatabasePair,
+    verification: Verification<'_>,
+    mut report: VerificationReport,
+) -> VerificationReport {
+    let target_count = match dbs
+        .read_target_total_documents(verification.target_collection, Document::new())
+        .await
+    {
+        Ok(Some(target_count)) => target_count,
+        Ok(None) => return report.skipped("The target can't be read back"),
+        Err(e) => {
+            report.error = Some(format!("Could not count target documents: {e}"));
+            return report;
+        }
+    };
+    if verification.compare_count {
+        report.target_count = Some(target_count);
+    }
+
+    if let Err(e) = merge(dbs, &verification, &mut report).await {
+        report.error = Some(format!("Could not compare documents: {e}"));
+    }
+    report
+}
+
+async fn merge(
+    dbs: &DatabasePair,
+    verification: &Verification<'_>,
+    report: &mut VerificationReport,
+) -> TuxedoResult<()> {
+    // Chunks of sorted `_id`s keep the documents ordered from one chunk to the next
+    let ids = verification.ids.map(|ids| {
+        let mut ids = ids.to_vec();
+        ids.sort_by(|a, b| compare_ids(Some(a), Some(b)));
+        ids
+    });
+    let projection = match verification.expected {
+        Some(_) => None,
+        None => Some(doc! { "_id": 1 }),
+    };
+
+    let mut source = OrderedReader::new(
+        Side::Source,
+        verification.source_collection,
+        verification.query,
+        ids.as_deref(),
+        projection.clone(),
+    );
+    let mut target = OrderedReader::new(
+        Side::Target,
+        verification.target_collection,
+        &Document::new(),
+        ids.as_deref(),
+        projection,
+    );
+
+    let mut target_document = target.next(dbs).await?;
+    while let Some(source_document) = source.next(dbs).await? {
+        report.source_count += 1;
+        let id = source_document.get("_id").cloned().unwrap_or(Bson::Null);
+
+        // Target documents ordered before are not part of the processor's documents
+        while let Some(document) = target_document.as_ref() {
+            if compare_ids(document.get("_id"), Some(&id)) != Ordering::Less {
+                break;
+            }
+            target_document = target.next(dbs).await?;
+        }
+
+        let found = target_document
+            .as_ref()
+            .filter(|document| compare_ids(document.get("_id"), Some(&id)) == Ordering::Equal);
+        match (found, verification.expected) {
+            (None, _) => report.add_missing(id),
+            (Some(document), Some(expected)) => {
+                if digest(&expected(source_document)?)? != digest(document)? {
+                    report.add_mismatched(id);
+                }
+                target_document = target.next(dbs).await?;
+            }
+            (Some(_), None) => target_document = target.next(dbs).await?,
+        }
+    }
+    Ok(())
+}
+
+fn digest(document: &Document) -> TuxedoResult<Vec<u8>> {
+    Ok(Md5::digest(bson::to_vec(document)?).to_vec())
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Side {
+    Source,
+    Target,
+}
+
+/// Reads the documents of one side ordered by `_id`, one query per chunk of `_id`s
+pub(crate) struct OrderedReader<'a> {
+    side: Side,
+    collection_name: &'a str,
+    queries: VecDeque<Document>,
+    options: FindOptions,
+    cursor: Option<DocumentCursor>,
+}
+
+impl<'a> OrderedReader<'a> {
+    pub(crate) fn new(
+        side: Side,
+        collection_name: &'a str,
+        query: &Document,
+        ids: Option<&[Bson]>,
+        projection: Option<Document>,
+    ) -> Self {
+        let queries = match ids {
+            Some(ids) => ids
+                .chunks(ID_CHUNK_SIZE)
+                .map(|chunk| restrict_to_ids(query, chunk))
+                .collect(),
+            None => VecDeque::from([query.clone()]),
+        };
+
+        Self {
+            side,
+            collection_name,
+            queries,
+            options: FindOptions::builder()
+                .sort(doc! { "_id": 1 })
+                .projection(projection)
+                .build(),
+            cursor: None,
+        }
+    }
+
+    pub(crate) async fn next(&mut self, dbs: &DatabasePair) -> TuxedoResult<Option<Document>> {
+        loop {
+            if let Some(cursor) = self.cursor.as_mut() {
+                if cursor.advance().await? {
+                    return Ok(Some(cursor.deserialize_current()?));
+                }
+                self.cursor = None;
+            }
+
+            let Some(query) = self.queries.pop_front() else {
+                return Ok(None);
+            };
+            let options = Some(self.options.clone());
+            self.cursor = Some(match self.side {
+                Side::Source => {
+                    dbs.read_documents(self.collection_name, query, options)
+                        .await?
+                }
+                Side::Target => dbs
+                    .read_target_documents(self.collection_name, query, options)
+                    .await?
+                    .ok_or_else(|| TuxedoError::Generic("The target can't be read back".into()))?,
+            });
+        }
+    }
+}
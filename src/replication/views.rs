@@ -0,0 +1,425 @@
+use bson::{Bson, Document};
+use mongodb::results::CollectionSpecification;
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+
+/// Pipeline stages which reference another collection by name, along with the
+/// field holding that name.
+const COLLECTION_REFERENCE_STAGES: [(&str, &str); 3] = [
+    ("$lookup", "from"),
+    ("$graphLookup", "from"),
+    ("$unionWith", "coll"),
+];
+
+/// Returns a copy of `pipeline` where every collection referenced through `$lookup`,
+/// `$graphLookup` or `$unionWith` (including nested sub-pipelines) is renamed
+/// according to the source -> target `collection_mapping`.
+pub(crate) fn rewrite_pipeline_collections(
+    pipeline: &[Document],
+    collection_mapping: &HashMap<String, String>,
+) -> Vec<Document> {
+    pipeline
+        .iter()
+        .map(|stage| rewrite_stage(stage, collection_mapping))
+        .collect()
+}
+
+fn rewrite_stage(stage: &Document, collection_mapping: &HashMap<String, String>) -> Document {
+    let mut stage = stage.clone();
+
+    for (stage_name, field) in COLLECTION_REFERENCE_STAGES {
+        match stage.get_mut(stage_name) {
+            // `{ $unionWith: "collection" }` shorthand
+            Some(Bson::String(collection_name)) => {
+                if let Some(target) = collection_mapping.get(collection_name.as_str()) {
+                    *collection_name = target.clone();
+                }
+            }
+            Some(Bson::Document(spec)) => {
+                if let Ok(collection_name) = spec.get_str(field) {
+                    if let Some(target) = collection_mapping.get(collection_name) {
+                        spec.insert(field, target.clone());
+                    }
+                }
+                rewrite_nested_pipeline(spec, collection_mapping);
+            }
+            _ => {}
+        }
+    }
+
+    // $facet holds a named set of sub-pipelines
+    if let Ok(facets) = stage.get_document_mut("$facet") {
+        for (_, facet) in facets.iter_mut() {
+            if let Bson::Array(pipeline) = facet {
+                rewrite_pipeline_array(pipeline, collection_mapping);
+            }
+        }
+    }
+
+    stage
+}
+
+fn rewrite_nested_pipeline(spec: &mut Document, collection_mapping: &HashMap<String, String>) {
+    if let Ok(pipeline) = spec.get_array_mut("pipeline") {
+        rewrite_pipeline_array(pipeline, collection_mapping);
+    }
+}
+
+fn rewrite_pipeline_array(pipeline: &mut [Bson], collection_mapping: &HashMap<String, String>) {
+    for stage in pipeline.iter_mut() {
+        if let Bson::Document(stage) = stage {
+            *stage = rewrite_stage(stage, collection_mapping);
+        }
+    }
+}
+
+/// Returns every collection or view referenced by a pipeline through `$lookup`,
+/// `$graphLookup` or `$unionWith`, including nested and `$facet` sub-pipelines.
+pub(crate) fn pipeline_references(pipeline: &[Document]) -> Vec<String> {
+    let mut references = Vec::new();
+    for stage in pipeline {
+        collect_stage_references(stage, &mut references);
+    }
+    references
+}
+
+fn collect_stage_references(stage: &Document, references: &mut Vec<String>) {
+    for (stage_name, field) in COLLECTION_REFERENCE_STAGES {
+        match stage.get(stage_name) {
+            Some(Bson::String(collection_name)) => references.push(collection_name.clone()),
+            Some(Bson::Document(spec)) => {
+                if let Ok(collection_name) = spec.get_str(field) {
+                    references.push(collection_name.to_string());
+                }
+                if let Ok(pipeline) = spec.get_array("pipeline") {
+                    collect_array_references(pipeline, references);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Ok(facets) = stage.get_document("$facet") {
+        for (_, facet) in facets.iter() {
+            if let Bson::Array(pipeline) = facet {
+                collect_array_references(pipeline, references);
+            }
+        }
+    }
+}
+
+fn collect_array_references(pipeline: &[Bson], references: &mut Vec<String>) {
+    for stage in pipeline {
+        if let Bson::Document(stage) = stage {
+            collect_stage_references(stage, references);
+        }
+    }
+}
+
+/// Target side names a view depends on: its `viewOn` and every pipeline reference,
+/// after applying the source -> target `collection_mapping`.
+pub(crate) fn view_dependencies(
+    view_spec: &CollectionSpecification,
+    collection_mapping: &HashMap<String, String>,
+) -> Vec<String> {
+    let mut dependencies: Vec<String> = Vec::new();
+
+    if let Some(view_on) = view_spec.options.view_on.as_ref() {
+        dependencies.push(view_on.clone());
+    }
+    if let Some(pipeline) = view_spec.options.pipeline.as_ref() {
+        dependencies.extend(pipeline_references(pipeline));
+    }
+
+    let mut mapped: Vec<String> = Vec::with_capacity(dependencies.len());
+    for dependency in dependencies {
+        let dependency = collection_mapping
+            .get(&dependency)
+            .cloned()
+            .unwrap_or(dependency);
+        if !mapped.contains(&dependency) {
+            mapped.push(dependency);
+        }
+    }
+    mapped
+}
+
+/// Order in which views are created on the target
+#[derive(Debug, Default)]
+pub(crate) struct ViewPlan {
+    /// Views grouped into layers, each layer only depends on the target collections
+    /// and the layers before it so the views of a layer can be created in parallel
+    pub(crate) layers: Vec<Vec<CollectionSpecification>>,
+    /// Views which will not be created along with the reason
+    pub(crate) skipped: Vec<(String, String)>,
+}
+
+/// Builds a dependency graph between views and orders them topologically.
+///
+/// A dependency is satisfied by a collection present on the target or by another
+/// view that is created earlier. Views with missing dependencies are skipped when
+/// `skip_missing_dependencies` is set, otherwise they are created with a warning.
+/// Views on a dependency cycle, or depending on a skipped view, are always skipped.
+pub(crate) fn plan_view_creation(
+    views: Vec<CollectionSpecification>,
+    target_collections: &HashSet<String>,
+    collection_mapping: &HashMap<String, String>,
+    skip_missing_dependencies: bool,
+) -> ViewPlan {
+    let view_names: HashSet<String> = views.iter().map(|view| view.name.clone()).collect();
+    let mut plan = ViewPlan::default();
+
+    // View name -> views it depends on which still need to be created
+    let mut pending: Vec<(CollectionSpecification, HashSet<String>)> = Vec::new();
+    for view in views {
+        let mut view_dependencies_left = HashSet::new();
+        let mut missing = Vec::new();
+
+        for dependency in view_dependencies(&view, collection_mapping) {
+            if view_names.contains(&dependency) {
+                view_dependencies_left.insert(dependency);
+            } else if !target_collections.contains(&dependency) {
+                missing.push(dependency);
+            }
+        }
+
+        if !missing.is_empty() {
+            let reason = format!(
+                "depends on `{}` which is not present on the target",
+                missing.join("`, `")
+            );
+            if skip_missing_dependencies {
+                plan.skipped.push((view.name.clone(), reason));
+                continue;
+            }
+            warn!(view = %view.name, %reason, "Creating view with missing dependencies");
+        }
+
+        pending.push((view, view_dependencies_left));
+    }
+
+    let mut created: HashSet<String> = HashSet::new();
+    while !pending.is_empty() {
+        // Views depending on a skipped view can never be created
+        let skipped_names: HashSet<String> =
+            plan.skipped.iter().map(|(name, _)| name.clone()).collect();
+        if let Some(index) = pending
+            .iter()
+            .position(|(_, dependencies)| dependencies.iter().any(|d| skipped_names.contains(d)))
+        {
+            let (view, dependencies) = pending.swap_remove(index);
+            let skipped_dependency = dependencies
+                .iter()
+                .find(|d| skipped_names.contains(*d))
+                .cloned()
+                .unwrap_or_default();
+            plan.skipped.push((
+                view.name,
+                format!("depends on view `{skipped_dependency}` which was skipped"),
+            ));
+            continue;
+        }
+
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|(_, dependencies)| dependencies.iter().all(|d| created.contains(d)));
+
+        if ready.is_empty() {
+            // Everything left is part of (or waiting on) a dependency cycle
+            for (view, _) in waiting {
+                plan.skipped
+                    .push((view.name, "has a circular view dependency".to_string()));
+            }
+            break;
+        }
+
+        let layer: Vec<CollectionSpecification> = ready.into_iter().map(|(view, _)| view).collect();
+        created.extend(layer.iter().map(|view| view.name.clone()));
+        plan.layers.push(layer);
+        pending = waiting;
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn view(name: &str, view_on: &str, pipeline: Vec<Document>) -> CollectionSpecification {
+        bson::from_document(doc! {
+            "name": name,
+            "type": "view",
+            "options": { "viewOn": view_on, "pipeline": pipeline },
+            "info": { "readOnly": true },
+        })
+        .unwrap()
+    }
+
+    fn lookup(from: &str) -> Document {
+        doc! { "$lookup": { "from": from, "localField": "a", "foreignField": "b", "as": "c" } }
+    }
+
+    fn collections(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn layer_names(plan: &ViewPlan) -> Vec<Vec<String>> {
+        plan.layers
+            .iter()
+            .map(|layer| {
+                let mut names: Vec<String> = layer.iter().map(|view| view.name.clone()).collect();
+                names.sort();
+                names
+            })
+            .collect()
+    }
+
+    fn skipped_names(plan: &ViewPlan) -> Vec<&str> {
+        let mut names: Vec<&str> = plan.skipped.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn orders_views_on_views_in_layers() {
+        let views = vec![
+            view("top", "middle", vec![lookup("other")]),
+            view("middle", "base", vec![]),
+            view("other", "orders", vec![]),
+            view("base", "users", vec![]),
+        ];
+        let plan = plan_view_creation(
+            views,
+            &collections(&["users", "orders"]),
+            &HashMap::new(),
+            true,
+        );
+
+        assert!(plan.skipped.is_empty(), "{:?}", plan.skipped);
+        assert_eq!(
+            layer_names(&plan),
+            vec![vec!["base", "other"], vec!["middle"], vec!["top"]]
+        );
+    }
+
+    #[test]
+    fn skips_views_on_a_cycle_and_their_dependents() {
+        let views = vec![
+            view("a", "b", vec![]),
+            view("b", "users", vec![doc! { "$unionWith": "a" }]),
+            view("c", "a", vec![]),
+            view("d", "users", vec![]),
+        ];
+        let plan = plan_view_creation(views, &collections(&["users"]), &HashMap::new(), true);
+
+        assert_eq!(layer_names(&plan), vec![vec!["d"]]);
+        assert_eq!(skipped_names(&plan), vec!["a", "b", "c"]);
+        assert!(plan
+            .skipped
+            .iter()
+            .all(|(_, reason)| reason == "has a circular view dependency"));
+    }
+
+    #[test]
+    fn skips_views_with_missing_dependencies_and_their_dependents() {
+        let views = vec![
+            view("orphan", "missing", vec![]),
+            view("child", "orphan", vec![]),
+            view("lookup", "users", vec![lookup("gone")]),
+        ];
+        let plan = plan_view_creation(views, &collections(&["users"]), &HashMap::new(), true);
+
+        assert!(plan.layers.is_empty());
+        assert_eq!(skipped_names(&plan), vec!["child", "lookup", "orphan"]);
+        let reason = |name: &str| {
+            plan.skipped
+                .iter()
+                .find(|(view, _)| view == name)
+                .map(|(_, reason)| reason.clone())
+                .unwrap()
+        };
+        assert_eq!(
+            reason("orphan"),
+            "depends on `missing` which is not present on the target"
+        );
+        assert_eq!(
+            reason("child"),
+            "depends on view `orphan` which was skipped"
+        );
+    }
+
+    #[test]
+    fn creates_views_with_missing_dependencies_unless_skipped() {
+        let views = vec![view("orphan", "missing", vec![])];
+        let plan = plan_view_creation(views, &HashSet::new(), &HashMap::new(), false);
+
+        assert_eq!(layer_names(&plan), vec![vec!["orphan"]]);
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn resolves_dependencies_through_the_collection_mapping() {
+        let mapping = HashMap::from([("users".to_string(), "customers".to_string())]);
+        let views = vec![view("active", "users", vec![lookup("users")])];
+
+        assert_eq!(
+            view_dependencies(&views[0], &mapping),
+            vec!["customers".to_string()]
+        );
+        let plan = plan_view_creation(views, &collections(&["customers"]), &mapping, true);
+        assert_eq!(layer_names(&plan), vec![vec!["active"]]);
+    }
+
+    #[test]
+    fn rewrites_every_collection_reference() {
+        let mapping = HashMap::from([
+            ("users".to_string(), "customers".to_string()),
+            ("orders".to_string(), "purchases".to_string()),
+        ]);
+        let pipeline = vec![
+            doc! { "$lookup": {
+                "from": "users",
+                "as": "user",
+                "pipeline": [{ "$unionWith": { "coll": "orders", "pipeline": [] } }],
+            } },
+            doc! { "$graphLookup": {
+                "from": "orders",
+                "startWith": "$a",
+                "connectFromField": "a",
+                "connectToField": "b",
+                "as": "graph",
+            } },
+            doc! { "$unionWith": "users" },
+            doc! { "$facet": { "all": [lookup("orders"), { "$match": {} }] } },
+            doc! { "$lookup": { "from": "unmapped", "as": "other" } },
+        ];
+
+        let rewritten = rewrite_pipeline_collections(&pipeline, &mapping);
+        assert_eq!(
+            rewritten,
+            vec![
+                doc! { "$lookup": {
+                    "from": "customers",
+                    "as": "user",
+                    "pipeline": [{ "$unionWith": { "coll": "purchases", "pipeline": [] } }],
+                } },
+                doc! { "$graphLookup": {
+                    "from": "purchases",
+                    "startWith": "$a",
+                    "connectFromField": "a",
+                    "connectToField": "b",
+                    "as": "graph",
+                } },
+                doc! { "$unionWith": "customers" },
+                doc! { "$facet": { "all": [lookup("purchases"), { "$match": {} }] } },
+                doc! { "$lookup": { "from": "unmapped", "as": "other" } },
+            ]
+        );
+        assert_eq!(
+            pipeline_references(&pipeline),
+            vec!["users", "orders", "orders", "users", "orders", "unmapped"]
+        );
+    }
+}
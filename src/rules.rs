@@ -0,0 +1,142 @@
+use crate::Mask;
+use bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+
+/// Replacement applied to the value found at a rule's path. In a policy file unit
+/// actions are written in snake case (`"first_name"`), the others as inline tables
+/// (`{ numeric_string = 9 }`, `{ fixed = "n/a" }`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskAction {
+    Name,
+    FirstName,
+    LastName,
+    FullName,
+    Email,
+    PhoneNumber,
+    Address,
+    PostalCode,
+    Comments,
+    /// A numeric string of the given length
+    NumericString(usize),
+    /// Replaces the value with a fixed value
+    Fixed(Bson),
+    /// Replaces the value with `null`, keeping the field
+    Null,
+    /// Removes the field from the document
+    Remove,
+}
+
+/// A set of path based masking rules for documents without a typed model.
+///
+/// Paths use dot notation (`address.street`) and are applied to every element when
+/// they traverse an array. Missing fields are left untouched, a rule never adds a
+/// field that was not present in the document.
+#[derive(Debug, Clone, Default)]
+pub struct MaskRules {
+    rules: Vec<(String, MaskAction)>,
+}
+
+impl MaskRules {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn rule(mut self, path: impl Into<String>, action: MaskAction) -> Self {
+        self.rules.push((path.into(), action));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|(path, _)| path.as_str())
+    }
+
+    /// Applies every rule to the document in order
+    pub fn apply(&self, document: &mut Document) {
+        for (path, action) in self.rules.iter() {
+            let segments: Vec<&str> = path.split('.').collect();
+            apply_to_document(document, &segments, action);
+        }
+    }
+}
+
+fn apply_to_document(document: &mut Document, segments: &[&str], action: &MaskAction) {
+    let Some((field, rest)) = segments.split_first() else {
+        return;
+    };
+
+    if rest.is_empty() {
+        if !document.contains_key(*field) {
+            return;
+        }
+        match action {
+            MaskAction::Remove => {
+                document.remove(*field);
+            }
+            _ => {
+                document.insert(*field, masked_value(action));
+            }
+        }
+        return;
+    }
+
+    if let Some(value) = document.get_mut(*field) {
+        apply_to_value(value, rest, action);
+    }
+}
+
+fn apply_to_value(value: &mut Bson, segments: &[&str], action: &MaskAction) {
+    match value {
+        Bson::Document(document) => apply_to_document(document, segments, action),
+        Bson::Array(values) => {
+            // Numeric segments address a single array element
+            if let Some(index) = segments.first().and_then(|s| s.parse::<usize>().ok()) {
+                let rest = &segments[1..];
+                if let Some(element) = values.get_mut(index) {
+                    if rest.is_empty() {
+                        if !matches!(action, MaskAction::Remove) {
+                            *element = masked_value(action);
+                        }
+                    } else {
+                        apply_to_value(element, rest, action);
+                    }
+                }
+                return;
+            }
+
+            for element in values.iter_mut() {
+                apply_to_value(element, segments, action);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Fakes are generated through the `Mask` trait so rules and typed models produce
+/// the same kind of values
+struct RuleMasker;
+
+impl Mask for RuleMasker {
+    fn mask(&mut self) {}
+}
+
+fn masked_value(action: &MaskAction) -> Bson {
+    match action {
+        MaskAction::Name => RuleMasker::fake_name().into(),
+        MaskAction::FirstName => RuleMasker::fake_first_name().into(),
+        MaskAction::LastName => RuleMasker::fake_last_name().into(),
+        MaskAction::FullName => RuleMasker::fake_full_name().into(),
+        MaskAction::Email => RuleMasker::fake_email().into(),
+        MaskAction::PhoneNumber => RuleMasker::fake_phone_number().into(),
+        MaskAction::Address => RuleMasker::fake_address().into(),
+        MaskAction::PostalCode => RuleMasker::fake_postal_code().into(),
+        MaskAction::Comments => RuleMasker::fake_comments().into(),
+        MaskAction::NumericString(length) => RuleMasker::fake_numeric_string(*length).into(),
+        MaskAction::Fixed(value) => value.clone(),
+        MaskAction::Null | MaskAction::Remove => Bson::Null,
+    }
+}
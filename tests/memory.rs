@@ -0,0 +1,111 @@
+use bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+use tuxedo::{
+    Mask, MemorySink, MemorySource, ProcessorConfigBuilder, ProgressEvent,
+    ReplicationManagerBuilder, ReplicationStrategy, SubsetConfig,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct User {
+    #[serde(rename = "_id")]
+    id: i32,
+    name: String,
+    email: String,
+    plan: String,
+}
+
+impl Mask for User {
+    fn mask(&mut self) {
+        self.name = Self::fake_name();
+        self.email = Self::fake_email();
+    }
+}
+
+fn users() -> Vec<User> {
+    (0..50)
+        .map(|id| User {
+            id,
+            name: format!("Customer {id}"),
+            email: format!("customer{id}@example.com"),
+            plan: if id % 2 == 0 { "free" } else { "pro" }.to_string(),
+        })
+        .collect()
+}
+
+fn quiet(builder: ReplicationManagerBuilder) -> ReplicationManagerBuilder {
+    builder.progress(|_: &ProgressEvent| {})
+}
+
+#[tokio::test]
+async fn masks_models_in_memory() {
+    let source = MemorySource::new().records("users", users()).unwrap();
+    let sink = MemorySink::new();
+
+    let manager = quiet(ReplicationManagerBuilder::new())
+        .source(source)
+        .target(sink.clone())
+        .strategy(ReplicationStrategy::Mask)
+        .batch_size(7u64)
+        .verify(true)
+        .detect_leaks(true)
+        .add_processor_with_config::<User>(
+            "users",
+            ProcessorConfigBuilder::new()
+                .sensitive_paths(["name", "email"])
+                .build(),
+        )
+        .build()
+        .await
+        .unwrap();
+    let report = manager.run().await.unwrap();
+
+    assert!(report.is_success(), "{report:?}");
+    assert_eq!(report.documents_written(), 50);
+    assert_eq!(report.collections[0].documents_total, 50);
+
+    let mut masked = sink.records::<User>("users").unwrap();
+    masked.sort_by_key(|user| user.id);
+    assert_eq!(masked.len(), 50);
+    for (user, original) in masked.iter().zip(users()) {
+        assert_eq!(user.id, original.id);
+        assert_eq!(user.plan, original.plan);
+        assert_ne!(user.name, original.name);
+        assert_ne!(user.email, original.email);
+    }
+}
+
+#[tokio::test]
+async fn subset_skips_collections_below_an_empty_selection() {
+    let accounts = (0..10).map(|id| doc! { "_id": id });
+    // No order references a selected account
+    let orders = (0..5).map(|id| doc! { "_id": id, "account_id": 100 + id });
+    let items = (0..30).map(|id| doc! { "_id": id, "order_id": id % 5 });
+    let source = MemorySource::new()
+        .documents("accounts", accounts)
+        .documents("orders", orders)
+        .documents("items", items);
+    let sink = MemorySink::new();
+
+    let manager = quiet(ReplicationManagerBuilder::new())
+        .source(source)
+        .target(sink.clone())
+        .verify(true)
+        .subset(
+            SubsetConfig::builder("accounts")
+                .relationship("orders", "account_id", "accounts", "_id")
+                .relationship("items", "order_id", "orders", "_id")
+                .build(),
+        )
+        .add_replicator("accounts")
+        .add_replicator("orders")
+        .add_replicator("items")
+        .build()
+        .await
+        .unwrap();
+    let report = manager.run().await.unwrap();
+
+    assert!(report.is_success(), "{report:?}");
+    assert_eq!(sink.documents("accounts").len(), 10);
+    assert_eq!(sink.documents("orders"), Vec::<Document>::new());
+    assert_eq!(sink.documents("items"), Vec::<Document>::new());
+}